@@ -0,0 +1,30 @@
+-- Add migration script here
+CREATE TYPE order_status AS ENUM (
+    'pending',
+    'paid',
+    'fulfilled',
+    'shipped',
+    'delivered',
+    'cancelled',
+    'refunded'
+);
+
+ALTER TABLE orders ALTER COLUMN status DROP DEFAULT;
+ALTER TABLE orders ALTER COLUMN status TYPE order_status USING status::order_status;
+ALTER TABLE orders ALTER COLUMN status SET DEFAULT 'pending';
+
+CREATE TABLE IF NOT EXISTS order_status_history (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    order_id UUID NOT NULL REFERENCES orders(id) ON DELETE CASCADE,
+    from_status order_status,
+    to_status order_status NOT NULL,
+    -- NULL when the change was made by the system rather than a user
+    changed_by UUID REFERENCES users(id),
+    note TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS order_status_history_order_id_idx ON order_status_history (order_id, created_at);
+
+INSERT INTO order_status_history (order_id, to_status, changed_by, created_at)
+SELECT id, status, user_id, created_at FROM orders;
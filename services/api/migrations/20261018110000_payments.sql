@@ -0,0 +1,25 @@
+-- Add migration script here
+CREATE TYPE payment_status AS ENUM (
+    'pending',
+    'authorized',
+    'captured',
+    'voided',
+    'refunded',
+    'declined',
+    'failed'
+);
+
+CREATE TABLE IF NOT EXISTS payments (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    order_id UUID NOT NULL REFERENCES orders(id) ON DELETE CASCADE,
+    provider TEXT NOT NULL,
+    provider_reference TEXT,
+    status payment_status NOT NULL DEFAULT 'pending',
+    amount DECIMAL(12, 2) NOT NULL,
+    failure_reason TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS payments_order_id_idx ON payments (order_id);
+CREATE UNIQUE INDEX IF NOT EXISTS payments_provider_reference_idx ON payments (provider, provider_reference);
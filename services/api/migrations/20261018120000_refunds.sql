@@ -0,0 +1,36 @@
+-- Add migration script here
+ALTER TABLE orders ADD COLUMN refunded_amount DECIMAL(12, 2) NOT NULL DEFAULT 0;
+ALTER TABLE payments ADD COLUMN refunded_amount DECIMAL(12, 2) NOT NULL DEFAULT 0;
+
+-- Restocked units are tracked separately from refunded ones: an item can be
+-- refunded without coming back (e.g. damaged), and cancellation must not
+-- restock units a refund already put back.
+ALTER TABLE order_items ADD COLUMN refunded_quantity INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE order_items ADD COLUMN restocked_quantity INTEGER NOT NULL DEFAULT 0;
+
+UPDATE order_items oi
+SET restocked_quantity = oi.quantity
+FROM orders o
+WHERE o.id = oi.order_id AND o.status = 'cancelled';
+
+CREATE TABLE IF NOT EXISTS refunds (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    order_id UUID NOT NULL REFERENCES orders(id) ON DELETE CASCADE,
+    payment_id UUID NOT NULL REFERENCES payments(id),
+    amount DECIMAL(12, 2) NOT NULL CHECK (amount > 0),
+    reason TEXT,
+    restocked BOOLEAN NOT NULL DEFAULT FALSE,
+    provider_reference TEXT NOT NULL,
+    created_by UUID REFERENCES users(id),
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS refunds_order_id_idx ON refunds (order_id, created_at);
+
+CREATE TABLE IF NOT EXISTS refund_items (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    refund_id UUID NOT NULL REFERENCES refunds(id) ON DELETE CASCADE,
+    order_item_id UUID NOT NULL REFERENCES order_items(id),
+    quantity INTEGER NOT NULL CHECK (quantity > 0),
+    amount DECIMAL(12, 2) NOT NULL
+);
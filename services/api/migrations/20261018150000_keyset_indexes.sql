@@ -0,0 +1,6 @@
+-- Add migration script here
+-- Keyset pagination walks (created_at, id) in descending order
+DROP INDEX IF EXISTS products_active_created_at_idx;
+
+CREATE INDEX IF NOT EXISTS products_active_created_at_id_idx ON products (created_at DESC, id DESC) WHERE deleted_at IS NULL;
+CREATE INDEX IF NOT EXISTS posts_created_at_id_idx ON posts (created_at DESC, id DESC);
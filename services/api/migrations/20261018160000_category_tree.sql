@@ -0,0 +1,11 @@
+-- Add migration script here
+ALTER TABLE categories ADD COLUMN parent_id UUID REFERENCES categories(id);
+
+CREATE INDEX IF NOT EXISTS categories_parent_id_idx ON categories (parent_id);
+
+-- Names only need to be unique among siblings now
+ALTER TABLE categories DROP CONSTRAINT IF EXISTS categories_name_key;
+CREATE UNIQUE INDEX IF NOT EXISTS categories_parent_name_idx
+    ON categories (COALESCE(parent_id, '00000000-0000-0000-0000-000000000000'::uuid), name);
+
+CREATE INDEX IF NOT EXISTS products_category_id_idx ON products (category_id);
@@ -0,0 +1,9 @@
+-- Add migration script here
+CREATE TYPE user_role AS ENUM ('customer', 'seller', 'admin');
+
+ALTER TABLE users ADD COLUMN roles user_role[] NOT NULL DEFAULT '{customer}';
+
+-- Users who already list products keep being able to manage them
+UPDATE users
+SET roles = array_append(roles, 'seller')
+WHERE id IN (SELECT DISTINCT user_id FROM products WHERE user_id IS NOT NULL);
@@ -0,0 +1,16 @@
+-- Add migration script here
+CREATE TYPE login_outcome AS ENUM ('success', 'bad_credentials', 'throttled');
+
+-- Audit trail of password logins, also used to throttle guessing
+CREATE TABLE login_attempts (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    -- As submitted, so guesses against unknown usernames are tracked too
+    username TEXT NOT NULL,
+    user_id UUID REFERENCES users(id) ON DELETE SET NULL,
+    ip_address TEXT NOT NULL,
+    outcome login_outcome NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_login_attempts_username ON login_attempts(username, created_at DESC);
+CREATE INDEX idx_login_attempts_ip_address ON login_attempts(ip_address, created_at DESC);
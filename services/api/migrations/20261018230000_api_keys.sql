@@ -0,0 +1,25 @@
+-- Add migration script here
+CREATE TYPE api_scope AS ENUM (
+    'products:read', 'products:write',
+    'categories:read', 'categories:write',
+    'orders:read', 'orders:write',
+    'cart:read', 'cart:write',
+    'posts:read', 'posts:write'
+);
+
+CREATE TABLE api_keys (
+    id UUID PRIMARY KEY DEFAULT gen_random_uuid(),
+    user_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    -- Public part of the key, shown in listings to tell keys apart
+    prefix TEXT NOT NULL UNIQUE,
+    -- SHA-256 of the secret part; the secret itself is never stored
+    secret_hash TEXT NOT NULL,
+    scopes api_scope[] NOT NULL,
+    expires_at TIMESTAMPTZ,
+    last_used_at TIMESTAMPTZ,
+    revoked_at TIMESTAMPTZ,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX idx_api_keys_user_id ON api_keys(user_id);
@@ -0,0 +1,11 @@
+-- Add migration script here
+ALTER TABLE users ADD COLUMN display_name TEXT;
+ALTER TABLE users ADD COLUMN avatar_url TEXT;
+-- Set by DELETE /me; the account is erased once this passes unless the
+-- user logs back in first
+ALTER TABLE users ADD COLUMN deletion_scheduled_at TIMESTAMPTZ;
+-- Set once the account has been erased; the row stays for order history
+ALTER TABLE users ADD COLUMN deleted_at TIMESTAMPTZ;
+
+CREATE INDEX idx_users_deletion_scheduled_at ON users(deletion_scheduled_at)
+    WHERE deletion_scheduled_at IS NOT NULL;
@@ -0,0 +1,4 @@
+-- Add migration script here
+-- BCP 47 tag such as "pt-BR" for the language notifications are sent in;
+-- NULL means the notification service default
+ALTER TABLE users ADD COLUMN locale TEXT;
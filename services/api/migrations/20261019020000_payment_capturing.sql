@@ -0,0 +1,4 @@
+-- Add migration script here
+-- Set before the provider is asked to capture, so a capture whose outcome
+-- was never recorded can be found and reconciled
+ALTER TYPE payment_status ADD VALUE IF NOT EXISTS 'capturing' AFTER 'authorized';
@@ -0,0 +1,13 @@
+-- Add migration script here
+-- Refunds are recorded before the provider is asked to make them, so one
+-- interrupted in between is retried instead of lost. The refund's id is the
+-- idempotency key sent to the provider.
+CREATE TYPE refund_status AS ENUM ('pending', 'succeeded', 'failed');
+
+ALTER TABLE refunds ADD COLUMN status refund_status NOT NULL DEFAULT 'succeeded';
+ALTER TABLE refunds ALTER COLUMN status SET DEFAULT 'pending';
+ALTER TABLE refunds ALTER COLUMN provider_reference DROP NOT NULL;
+ALTER TABLE refunds ADD COLUMN failure_reason TEXT;
+ALTER TABLE refunds ADD COLUMN updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW();
+
+CREATE INDEX IF NOT EXISTS refunds_pending_idx ON refunds (updated_at) WHERE status = 'pending';
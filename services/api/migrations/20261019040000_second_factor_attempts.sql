@@ -0,0 +1,6 @@
+-- Add migration script here
+-- Wrong second-factor codes count towards the same throttle as wrong
+-- passwords, and a right password alone no longer resets it for accounts
+-- with 2FA
+ALTER TYPE login_outcome ADD VALUE IF NOT EXISTS 'challenged' AFTER 'success';
+ALTER TYPE login_outcome ADD VALUE IF NOT EXISTS 'bad_code' AFTER 'bad_credentials';
@@ -0,0 +1,17 @@
+-- Add migration script here
+-- Erased users whose notifications the notification service may still hold.
+-- A row is added in the same transaction as the erasure and removed once the
+-- service confirms the delete, so the account purger keeps retrying until it
+-- does.
+CREATE TABLE notification_erasures (
+    user_id UUID PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    last_error TEXT,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- Accounts erased before this table existed may have had their delete fail;
+-- deleting again is harmless
+INSERT INTO notification_erasures (user_id)
+SELECT id FROM users WHERE deleted_at IS NOT NULL;
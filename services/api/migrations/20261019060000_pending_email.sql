@@ -0,0 +1,4 @@
+-- Add migration script here
+-- A new address waiting for verification. The current one stays in email,
+-- and keeps receiving password resets, until the new one is verified.
+ALTER TABLE users ADD COLUMN pending_email TEXT;
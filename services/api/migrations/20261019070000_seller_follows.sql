@@ -0,0 +1,11 @@
+-- Add migration script here
+-- Users following sellers, who hear about each product the seller lists
+CREATE TABLE seller_follows (
+    follower_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    seller_id UUID NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY (follower_id, seller_id),
+    CHECK (follower_id <> seller_id)
+);
+
+CREATE INDEX idx_seller_follows_seller_id ON seller_follows(seller_id);
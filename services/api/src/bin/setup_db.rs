@@ -15,5 +15,26 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     sqlx::migrate!("./migrations").run(&pool).await?;
 
     println!("Migrations applied successfully!");
+
+    // Bootstraps the first admin: `setup_db --grant-admin <username>`
+    let args: Vec<String> = env::args().collect();
+    if let Some(index) = args.iter().position(|arg| arg == "--grant-admin") {
+        let username = args.get(index + 1).expect("--grant-admin needs a username");
+
+        let result = sqlx::query(
+            "UPDATE users SET roles = array_append(roles, 'admin')
+             WHERE username = $1 AND NOT ('admin' = ANY(roles))",
+        )
+        .bind(username)
+        .execute(&pool)
+        .await?;
+
+        if result.rows_affected() == 0 {
+            println!("No change: {} does not exist or is already an admin", username);
+        } else {
+            println!("Granted admin to {}", username);
+        }
+    }
+
     Ok(())
 }
@@ -1,21 +1,87 @@
 use std::env;
 use std::sync::Arc;
+use axum::http::HeaderName;
 use sqlx::postgres::{PgPoolOptions, PgPool};
 use dotenvy::dotenv;
 use tracing::info;
 
+use crate::payments::{self, PaymentProvider, WebhookSender};
+use crate::utils::{hash::PasswordPolicy, jwt::JwtKeys};
+
 #[derive(Clone)]
 pub struct Config {
     pub db_pool: PgPool,
-    pub jwt_secret: String,
+    pub jwt_keys: Arc<JwtKeys>,
+    pub password_policy: PasswordPolicy,
+    /// See `PasswordPolicy::dummy_hash`.
+    pub dummy_password_hash: String,
+    pub payment_provider: Arc<dyn PaymentProvider>,
+    pub email_verification: EmailVerificationPolicy,
+    /// The header the reverse proxy puts the client's address in, from
+    /// `TRUSTED_PROXY_HEADER`; see `login_throttle::ClientIp`.
+    pub trusted_proxy_header: Option<HeaderName>,
+}
+
+/// Actions that can be held back until the user has verified their email.
+#[derive(Debug, Clone, Copy)]
+pub enum VerifiedAction {
+    Checkout,
+    Selling,
+}
+
+/// Which actions need a verified email, from the comma-separated
+/// `REQUIRE_VERIFIED_EMAIL` list (e.g. `checkout,selling`). Nothing is
+/// required by default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EmailVerificationPolicy {
+    pub checkout: bool,
+    pub selling: bool,
+}
+
+impl EmailVerificationPolicy {
+    fn from_env() -> Self {
+        let mut policy = Self::default();
+        let required = env::var("REQUIRE_VERIFIED_EMAIL").unwrap_or_default();
+
+        for action in required.split(',').map(str::trim).filter(|a| !a.is_empty()) {
+            match action {
+                "checkout" => policy.checkout = true,
+                "selling" => policy.selling = true,
+                other => tracing::warn!("Ignoring unknown REQUIRE_VERIFIED_EMAIL entry: {}", other),
+            }
+        }
+
+        policy
+    }
+
+    pub fn requires(&self, action: VerifiedAction) -> bool {
+        match action {
+            VerifiedAction::Checkout => self.checkout,
+            VerifiedAction::Selling => self.selling,
+        }
+    }
 }
 
 impl Config {
-    pub async fn from_env() -> Result<Self, sqlx::Error> {
+    /// `payment_webhooks` receives asynchronous events from the payment provider.
+    pub async fn from_env(payment_webhooks: WebhookSender) -> Result<Self, sqlx::Error> {
         dotenv().ok();
 
         let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
-        let jwt_secret = env::var("JWT_SECRET").expect("JWT_SECRET must be set");
+        let jwt_keys = JwtKeys::from_env().expect("Failed to load JWT signing keys");
+        let password_policy = PasswordPolicy::from_env().expect("Invalid password hashing policy");
+        let dummy_password_hash = password_policy
+            .dummy_hash()
+            .expect("Failed to hash dummy password");
+        let trusted_proxy_header = env::var("TRUSTED_PROXY_HEADER")
+            .ok()
+            .filter(|header| !header.trim().is_empty())
+            .map(|header| {
+                header
+                    .trim()
+                    .parse::<HeaderName>()
+                    .expect("Invalid TRUSTED_PROXY_HEADER")
+            });
 
         let pool = PgPoolOptions::new()
             .max_connections(5)
@@ -26,7 +92,12 @@ impl Config {
 
         Ok(Self {
             db_pool: pool,
-            jwt_secret,
+            jwt_keys: Arc::new(jwt_keys),
+            password_policy,
+            dummy_password_hash,
+            payment_provider: payments::provider_from_env(payment_webhooks),
+            email_verification: EmailVerificationPolicy::from_env(),
+            trusted_proxy_header,
         })
     }
 }
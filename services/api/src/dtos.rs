@@ -1,12 +1,20 @@
+use std::collections::HashMap;
+
 use chrono::{DateTime, Utc};
 use rust_decimal::Decimal;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 use uuid::Uuid;
 
+use crate::model::{
+    ApiKey, ApiScope, CartLine, LoginAttempt, Order, OrderItem, OrderStatus, OrderStatusHistory,
+    Post, Product, Refund, RefundStatus, Role, SellerFollow, Session, User,
+};
+
 // Auth DTOs
 #[derive(Debug, Deserialize)]
 pub struct SignupRequest {
     pub username: String,
+    pub email: String,
     pub password: String,
 }
 
@@ -18,9 +26,83 @@ pub struct LoginRequest {
 
 #[derive(Debug, Serialize)]
 pub struct AuthResponse {
+    /// Short-lived access token for the `Authorization: Bearer` header.
+    pub token: String,
+    /// Single-use token for `POST /auth/refresh`; each refresh returns a new one.
+    pub refresh_token: String,
+    /// Seconds until `token` expires.
+    pub expires_in: i64,
+}
+
+/// Password logins for accounts with 2FA stop at a challenge, which
+/// `POST /auth/login/2fa` exchanges for tokens together with a code.
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum LoginResponse {
+    Authenticated(AuthResponse),
+    ChallengeRequired(MfaChallengeResponse),
+}
+
+#[derive(Debug, Serialize)]
+pub struct MfaChallengeResponse {
+    pub mfa_required: bool,
+    pub challenge_token: String,
+    /// Seconds until `challenge_token` expires.
+    pub expires_in: i64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct MfaLoginRequest {
+    pub challenge_token: String,
+    /// A current authenticator code or an unused recovery code.
+    pub code: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RefreshRequest {
+    pub refresh_token: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct VerifyEmailRequest {
     pub token: String,
 }
 
+#[derive(Debug, Serialize)]
+pub struct VerifyEmailResponse {
+    pub email: String,
+    pub verified_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TotpEnrollmentResponse {
+    pub secret: String,
+    pub otpauth_uri: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TotpCodeRequest {
+    /// A current authenticator code; where noted, a recovery code also works.
+    pub code: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RecoveryCodesResponse {
+    /// Shown once; only their hashes are kept.
+    pub recovery_codes: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ForgotPasswordRequest {
+    pub username: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ResetPasswordRequest {
+    pub token: String,
+    pub new_password: String,
+}
+
 // Post DTOs
 #[derive(Debug, Deserialize)]
 pub struct CreatePostRequest {
@@ -32,6 +114,23 @@ pub struct CreatePostRequest {
 pub struct PaginationRequest {
     pub limit: Option<i64>,
     pub offset: Option<i64>,
+    /// Opaque `next_cursor` from a previous page; replaces `offset`. Empty
+    /// for the first page.
+    pub cursor: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PageResponse<T> {
+    pub items: Vec<T>,
+    pub next_cursor: Option<String>,
+}
+
+/// A page of a listing, shaped by how it was requested.
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum Page<T> {
+    Offset(Vec<T>),
+    Keyset(PageResponse<T>),
 }
 
 #[derive(Debug, Serialize)]
@@ -53,6 +152,16 @@ pub struct CreateProductRequest {
     pub stock_quantity: i32,
 }
 
+/// Fields left out are kept as they are.
+#[derive(Debug, Deserialize)]
+pub struct UpdateProductRequest {
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub category_id: Option<Uuid>,
+    pub price: Option<f64>,
+    pub stock_quantity: Option<i32>,
+}
+
 #[derive(Debug, Serialize)]
 pub struct ProductResponse {
     pub id: Uuid,
@@ -63,15 +172,459 @@ pub struct ProductResponse {
     pub stock_quantity: i32,
 }
 
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProductSort {
+    #[default]
+    Newest,
+    PriceAsc,
+    PriceDesc,
+    NameAsc,
+    NameDesc,
+    StockAsc,
+    StockDesc,
+}
+
+/// Listing filters; every field is optional and they combine with AND.
+#[derive(Debug, Deserialize)]
+pub struct ProductListRequest {
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+    /// Only valid with the default `newest` sort.
+    pub cursor: Option<String>,
+    pub category_id: Option<Uuid>,
+    /// Also match products in any subcategory of `category_id`.
+    #[serde(default)]
+    pub include_descendants: bool,
+    pub min_price: Option<f64>,
+    pub max_price: Option<f64>,
+    pub in_stock: Option<bool>,
+    /// The seller who listed the product.
+    pub user_id: Option<Uuid>,
+    pub created_after: Option<DateTime<Utc>>,
+    pub created_before: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub sort: ProductSort,
+}
+
+/// Same paging semantics as `PaginationRequest`.
+#[derive(Debug, Deserialize)]
+pub struct SearchProductsRequest {
+    pub q: String,
+    pub limit: Option<i64>,
+    pub offset: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ProductSearchResponse {
+    #[serde(flatten)]
+    pub product: ProductResponse,
+    pub rank: f32,
+    /// Name as escaped HTML, with matched terms wrapped in `<mark>` tags.
+    pub name_highlight: String,
+    /// Best matching description fragments, highlighted the same way.
+    pub snippet: String,
+}
+
 //Category Dto
 #[derive(Debug, Deserialize)]
 pub struct CreateCategoryRequest {
     pub name: String,
+    pub parent_id: Option<Uuid>,
+}
+
+/// `parent_id` distinguishes "absent" (keep) from `null` (move to the root).
+#[derive(Debug, Deserialize)]
+pub struct UpdateCategoryRequest {
+    pub name: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_present")]
+    pub parent_id: Option<Option<Uuid>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CategoryListRequest {
+    /// Return the categories nested under their parents instead of a flat list.
+    #[serde(default)]
+    pub tree: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeleteCategoryRequest {
+    /// Where the category's products go; defaults to its parent.
+    pub reassign_to: Option<Uuid>,
 }
 
 #[derive(Debug, Serialize)]
 pub struct CategoryResponse {
     pub id: Uuid,
     pub name: String,
+    pub parent_id: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CategoryTreeResponse {
+    pub id: Uuid,
+    pub name: String,
+    pub parent_id: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+    pub children: Vec<CategoryTreeResponse>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+pub enum CategoryListResponse {
+    Flat(Vec<CategoryResponse>),
+    Tree(Vec<CategoryTreeResponse>),
+}
+
+/// Wraps a present field in `Some`, so `null` becomes `Some(None)` while a
+/// missing field falls back to `None` through `#[serde(default)]`.
+fn deserialize_present<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    T::deserialize(deserializer).map(Some)
+}
+
+// Cart Dto
+#[derive(Debug, Deserialize)]
+pub struct AddCartItemRequest {
+    pub product_id: Uuid,
+    pub quantity: i32,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateCartItemRequest {
+    pub quantity: i32,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CartItemResponse {
+    pub product_id: Uuid,
+    pub name: String,
+    pub unit_price: Decimal,
+    pub quantity: i32,
+    pub line_total: Decimal,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CartResponse {
+    pub id: Uuid,
+    pub items: Vec<CartItemResponse>,
+    pub total: Decimal,
+}
+
+// Order Dto
+#[derive(Debug, Deserialize)]
+pub struct OrderItemRequest {
+    pub product_id: Uuid,
+    pub quantity: i32,
+}
+
+/// Checks out the caller's cart unless an explicit item list is given.
+#[derive(Debug, Deserialize)]
+pub struct CreateOrderRequest {
+    pub items: Option<Vec<OrderItemRequest>>,
+    pub payment_method: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OrderItemResponse {
+    pub id: Uuid,
+    pub product_id: Uuid,
+    pub product_name: String,
+    pub unit_price: Decimal,
+    pub quantity: i32,
+    pub line_total: Decimal,
+    pub refunded_quantity: i32,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OrderResponse {
+    pub id: Uuid,
+    pub status: OrderStatus,
+    pub total_amount: Decimal,
+    pub refunded_amount: Decimal,
+    pub items: Vec<OrderItemResponse>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateOrderStatusRequest {
+    pub status: OrderStatus,
+    pub note: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CancelOrderRequest {
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OrderStatusHistoryResponse {
+    pub from_status: Option<OrderStatus>,
+    pub to_status: OrderStatus,
+    pub changed_by: Option<Uuid>,
+    pub note: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+// Refund Dto
+#[derive(Debug, Deserialize)]
+pub struct RefundItemRequest {
+    pub order_item_id: Uuid,
+    pub quantity: i32,
+}
+
+/// Refunds the given line items, or a flat amount, or everything still
+/// refundable when neither is given. Sellers can only refund the items they
+/// sold; flat amounts are for admins.
+#[derive(Debug, Deserialize)]
+pub struct CreateRefundRequest {
+    pub items: Option<Vec<RefundItemRequest>>,
+    pub amount: Option<Decimal>,
+    #[serde(default)]
+    pub restock: bool,
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RefundItemResponse {
+    pub order_item_id: Uuid,
+    pub quantity: i32,
+    pub amount: Decimal,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RefundResponse {
+    pub id: Uuid,
+    pub order_id: Uuid,
+    pub amount: Decimal,
+    pub reason: Option<String>,
+    pub restocked: bool,
+    /// `pending` until the payment provider has confirmed the refund.
+    pub status: RefundStatus,
+    pub items: Vec<RefundItemResponse>,
+    pub created_at: DateTime<Utc>,
+}
+
+// Admin DTOs
+#[derive(Debug, Deserialize)]
+pub struct GrantRoleRequest {
+    pub role: Role,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UserRolesResponse {
+    pub user_id: Uuid,
+    pub username: String,
+    pub roles: Vec<Role>,
+}
+
+// API key DTOs
+#[derive(Debug, Deserialize)]
+pub struct CreateApiKeyRequest {
+    pub name: String,
+    pub scopes: Vec<ApiScope>,
+    /// Never expires when omitted.
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ApiKeyResponse {
+    pub id: Uuid,
+    pub name: String,
+    pub prefix: String,
+    pub scopes: Vec<ApiScope>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
     pub created_at: DateTime<Utc>,
 }
+
+#[derive(Debug, Serialize)]
+pub struct CreatedApiKeyResponse {
+    /// The full key. It is only ever shown in this response.
+    pub key: String,
+    #[serde(flatten)]
+    pub api_key: ApiKeyResponse,
+}
+
+// Account DTOs
+#[derive(Debug, Serialize)]
+pub struct MeResponse {
+    pub id: Uuid,
+    pub username: String,
+    pub display_name: Option<String>,
+    pub email: Option<String>,
+    pub email_verified: bool,
+    /// A new address that takes over from `email` once verified.
+    pub pending_email: Option<String>,
+    pub avatar_url: Option<String>,
+    pub locale: Option<String>,
+    pub roles: Vec<Role>,
+    pub two_factor_enabled: bool,
+    pub deletion_scheduled_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// Omitted fields are left alone; `null` clears display name, avatar and
+/// locale.
+#[derive(Debug, Deserialize)]
+pub struct UpdateMeRequest {
+    #[serde(default, deserialize_with = "deserialize_present")]
+    pub display_name: Option<Option<String>>,
+    /// Takes effect once the new address is verified.
+    pub email: Option<String>,
+    /// Required to change `email`.
+    pub current_password: Option<String>,
+    #[serde(default, deserialize_with = "deserialize_present")]
+    pub avatar_url: Option<Option<String>>,
+    /// BCP 47 tag such as `pt-BR` for the language of notifications.
+    #[serde(default, deserialize_with = "deserialize_present")]
+    pub locale: Option<Option<String>>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChangePasswordRequest {
+    pub current_password: String,
+    pub new_password: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeleteAccountRequest {
+    pub password: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DeleteAccountResponse {
+    /// Logging in before this cancels the deletion.
+    pub deletion_scheduled_at: DateTime<Utc>,
+}
+
+/// What anyone may see about a seller.
+#[derive(Debug, Serialize)]
+pub struct SellerProfileResponse {
+    pub id: Uuid,
+    pub username: String,
+    pub display_name: Option<String>,
+    pub avatar_url: Option<String>,
+    pub product_count: i64,
+    pub follower_count: i64,
+    pub member_since: DateTime<Utc>,
+}
+
+// Data export DTOs
+/// Everything stored about a user, as returned by the data export endpoints.
+#[derive(Debug, Serialize)]
+pub struct UserDataExport {
+    pub exported_at: DateTime<Utc>,
+    pub profile: User,
+    pub posts: Vec<Post>,
+    pub products: Vec<Product>,
+    pub cart: Vec<CartLine>,
+    pub orders: Vec<ExportedOrder>,
+    pub sessions: Vec<Session>,
+    pub api_keys: Vec<ApiKey>,
+    pub login_attempts: Vec<LoginAttempt>,
+    /// Sellers the user follows.
+    pub following: Vec<SellerFollow>,
+    pub notifications: Vec<ExportedNotification>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ExportedOrder {
+    #[serde(flatten)]
+    pub order: Order,
+    pub items: Vec<OrderItem>,
+    pub status_history: Vec<OrderStatusHistory>,
+    pub refunds: Vec<Refund>,
+}
+
+/// A stored notification, including ones that never reached the inbox,
+/// with every try at delivering it.
+#[derive(Debug, Serialize)]
+pub struct ExportedNotification {
+    #[serde(flatten)]
+    pub notification: NotificationResponse,
+    pub in_inbox: bool,
+    pub delivery_attempts: Vec<DeliveryAttemptResponse>,
+}
+
+// Notification DTOs
+#[derive(Debug, Deserialize)]
+pub struct NotificationQuery {
+    /// Only unread notifications when true.
+    #[serde(default)]
+    pub unread: bool,
+    pub limit: Option<u32>,
+    pub offset: Option<u32>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct NotificationResponse {
+    pub id: Uuid,
+    pub kind: String,
+    pub title: String,
+    pub body: String,
+    pub created_at: DateTime<Utc>,
+    pub read_at: Option<DateTime<Utc>>,
+}
+
+/// Either specific `ids` or `all: true`.
+#[derive(Debug, Deserialize)]
+pub struct MarkNotificationsReadRequest {
+    #[serde(default)]
+    pub ids: Vec<Uuid>,
+    #[serde(default)]
+    pub all: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MarkNotificationsReadResponse {
+    pub updated: u32,
+}
+
+#[derive(Debug, Serialize)]
+pub struct UnreadCountResponse {
+    pub unread: u32,
+}
+
+/// One try at delivering a notification, for tracing lost messages.
+#[derive(Debug, Serialize)]
+pub struct DeliveryAttemptResponse {
+    pub id: Uuid,
+    pub notification_id: Uuid,
+    pub kind: String,
+    /// `email`, `webhook` or `in_app`
+    pub channel: String,
+    pub attempt: u32,
+    /// `delivered`, `failed` or `skipped`
+    pub status: String,
+    pub error: Option<String>,
+    pub attempted_at: DateTime<Utc>,
+}
+
+/// Renders a notification template with sample values for its kind;
+/// `variables` replace or add to them.
+#[derive(Debug, Deserialize)]
+pub struct PreviewTemplateRequest {
+    pub kind: String,
+    /// `email` or `in_app`
+    pub channel: String,
+    #[serde(default)]
+    pub locale: Option<String>,
+    #[serde(default)]
+    pub variables: HashMap<String, String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TemplatePreviewResponse {
+    /// The locale whose templates were used after falling back.
+    pub locale: String,
+    pub subject: String,
+    pub text: String,
+    pub html: Option<String>,
+}
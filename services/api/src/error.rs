@@ -1,15 +1,24 @@
 use axum::{
     Json,
-    http::StatusCode,
+    http::{header, StatusCode},
     response::{IntoResponse, Response},
 };
 use serde_json::json;
 
+use crate::{model::OrderStatus, payments::PaymentError};
+
 #[derive(Debug)]
 pub enum AppError {
     Database(sqlx::Error),
     Unauthorized,
+    Forbidden,
+    EmailNotVerified,
     BadRequest(String),
+    TooManyRequests { retry_after_secs: u64 },
+    InvalidStatusTransition { from: OrderStatus, to: OrderStatus },
+    PaymentDeclined(String),
+    /// A service this one depends on could not be reached.
+    ServiceUnavailable(String),
     InternalServerError,
 }
 
@@ -19,6 +28,18 @@ impl From<sqlx::Error> for AppError {
     }
 }
 
+impl From<PaymentError> for AppError {
+    fn from(inner: PaymentError) -> Self {
+        match inner {
+            PaymentError::Declined(reason) => AppError::PaymentDeclined(reason),
+            PaymentError::Provider(msg) => {
+                tracing::error!("Payment provider error: {}", msg);
+                AppError::InternalServerError
+            }
+        }
+    }
+}
+
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
         let (status, error_message) = match self {
@@ -30,7 +51,34 @@ impl IntoResponse for AppError {
                 )
             }
             AppError::Unauthorized => (StatusCode::UNAUTHORIZED, "Unauthorized".to_string()),
+            AppError::Forbidden => (StatusCode::FORBIDDEN, "Forbidden".to_string()),
+            AppError::EmailNotVerified => (
+                StatusCode::FORBIDDEN,
+                "Verify your email address first".to_string(),
+            ),
             AppError::BadRequest(msg) => (StatusCode::BAD_REQUEST, msg),
+            // The only error with a header and an extra field in its body
+            AppError::TooManyRequests { retry_after_secs } => {
+                let body = Json(json!({
+                    "error": "Too many attempts, try again later",
+                    "retry_after": retry_after_secs,
+                }));
+                return (
+                    StatusCode::TOO_MANY_REQUESTS,
+                    [(header::RETRY_AFTER, retry_after_secs.to_string())],
+                    body,
+                )
+                    .into_response();
+            }
+            AppError::InvalidStatusTransition { from, to } => (
+                StatusCode::CONFLICT,
+                format!("Cannot change order status from {} to {}", from, to),
+            ),
+            AppError::PaymentDeclined(reason) => (
+                StatusCode::PAYMENT_REQUIRED,
+                format!("Payment declined: {}", reason),
+            ),
+            AppError::ServiceUnavailable(msg) => (StatusCode::SERVICE_UNAVAILABLE, msg),
             AppError::InternalServerError => (
                 StatusCode::INTERNAL_SERVER_ERROR,
                 "Internal Server Error".to_string(),
@@ -1,4 +1,12 @@
-use tonic::transport::Channel;
+use std::{collections::HashMap, env};
+
+use tonic::{
+    codec::Streaming,
+    metadata::{Ascii, MetadataValue},
+    service::{interceptor::InterceptedService, Interceptor},
+    transport::Channel,
+    Request, Status,
+};
 
 // Include the generated protobuf code
 pub mod notification {
@@ -6,26 +14,66 @@ pub mod notification {
 }
 
 use notification::{
-    notification_service_client::NotificationServiceClient, ProductNotificationRequest,
+    notification_service_client::NotificationServiceClient, CountUnreadNotificationsRequest,
+    DeleteUserNotificationsRequest, DeliveryAttempt, EmailVerificationNotificationRequest,
+    ExportUserNotificationsRequest, ExportUserNotificationsResponse,
+    FollowedSellerListingNotificationRequest, ListDeliveryAttemptsRequest,
+    ListNotificationsRequest, MarkNotificationsReadRequest, Notification,
+    PasswordResetNotificationRequest, PreviewTemplateRequest, PreviewTemplateResponse,
+    ProductNotificationRequest, RefundNotificationRequest, SubscribeNotificationsRequest,
 };
 
+/// Used when `NOTIFICATION_SERVICE_URL` is unset.
+const DEFAULT_SERVICE_URL: &str = "http://localhost:50051";
+
+/// Adds the shared secret from `NOTIFICATION_SERVICE_TOKEN` to every call
+#[derive(Clone)]
+struct ServiceToken(MetadataValue<Ascii>);
+
+impl Interceptor for ServiceToken {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        request
+            .metadata_mut()
+            .insert("authorization", self.0.clone());
+        Ok(request)
+    }
+}
+
+/// Connect to the notification service
+async fn connect() -> Result<
+    NotificationServiceClient<InterceptedService<Channel, ServiceToken>>,
+    Box<dyn std::error::Error>,
+> {
+    let url =
+        env::var("NOTIFICATION_SERVICE_URL").unwrap_or_else(|_| DEFAULT_SERVICE_URL.to_string());
+    let token = env::var("NOTIFICATION_SERVICE_TOKEN")
+        .map_err(|_| "NOTIFICATION_SERVICE_TOKEN must be set")?;
+    let authorization = format!("Bearer {}", token).parse()?;
+
+    let channel = Channel::from_shared(url)?.connect().await?;
+
+    Ok(NotificationServiceClient::with_interceptor(
+        channel,
+        ServiceToken(authorization),
+    ))
+}
+
 /// Send a product notification to the notification service
 pub async fn send_product_notification(
     user_id: &str,
     product_name: &str,
     username: &str,
+    email: &str,
+    locale: &str,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    // Connect to the notification service
-    let channel = Channel::from_static("http://localhost:50051")
-        .connect()
-        .await?;
-
-    let mut client = NotificationServiceClient::new(channel);
+    let mut client = connect().await?;
 
     let request = tonic::Request::new(ProductNotificationRequest {
         user_id: user_id.to_string(),
         name: product_name.to_string(),
         username: username.to_string(),
+        email: email.to_string(),
+        locale: locale.to_string(),
     });
 
     let response = client.send_product_notification(request).await?;
@@ -37,3 +85,262 @@ pub async fn send_product_notification(
 
     Ok(())
 }
+
+/// Tell a follower that a seller they follow listed a product
+pub async fn send_followed_seller_listing_notification(
+    user_id: &str,
+    username: &str,
+    seller_name: &str,
+    product_name: &str,
+    email: &str,
+    locale: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = connect().await?;
+
+    let request = tonic::Request::new(FollowedSellerListingNotificationRequest {
+        user_id: user_id.to_string(),
+        username: username.to_string(),
+        seller_name: seller_name.to_string(),
+        product_name: product_name.to_string(),
+        email: email.to_string(),
+        locale: locale.to_string(),
+    });
+
+    client
+        .send_followed_seller_listing_notification(request)
+        .await?;
+
+    Ok(())
+}
+
+/// Tell a buyer that (part of) their order was refunded
+pub async fn send_refund_notification(
+    user_id: &str,
+    username: &str,
+    order_id: &str,
+    amount: &str,
+    reason: &str,
+    email: &str,
+    locale: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = connect().await?;
+
+    let request = tonic::Request::new(RefundNotificationRequest {
+        user_id: user_id.to_string(),
+        username: username.to_string(),
+        order_id: order_id.to_string(),
+        amount: amount.to_string(),
+        reason: reason.to_string(),
+        email: email.to_string(),
+        locale: locale.to_string(),
+    });
+
+    let response = client.send_refund_notification(request).await?;
+
+    tracing::info!(
+        "Refund notification sent successfully: {}",
+        response.into_inner().message
+    );
+
+    Ok(())
+}
+
+/// Deliver a password reset token to its owner
+pub async fn send_password_reset_notification(
+    user_id: &str,
+    username: &str,
+    reset_token: &str,
+    expires_at: &str,
+    email: &str,
+    locale: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = connect().await?;
+
+    let request = tonic::Request::new(PasswordResetNotificationRequest {
+        user_id: user_id.to_string(),
+        username: username.to_string(),
+        reset_token: reset_token.to_string(),
+        expires_at: expires_at.to_string(),
+        email: email.to_string(),
+        locale: locale.to_string(),
+    });
+
+    let response = client.send_password_reset_notification(request).await?;
+
+    tracing::info!(
+        "Password reset notification sent successfully: {}",
+        response.into_inner().message
+    );
+
+    Ok(())
+}
+
+/// Send a verification link for a newly added email address
+pub async fn send_email_verification_notification(
+    user_id: &str,
+    username: &str,
+    email: &str,
+    verification_token: &str,
+    expires_at: &str,
+    locale: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = connect().await?;
+
+    let request = tonic::Request::new(EmailVerificationNotificationRequest {
+        user_id: user_id.to_string(),
+        username: username.to_string(),
+        email: email.to_string(),
+        verification_token: verification_token.to_string(),
+        expires_at: expires_at.to_string(),
+        locale: locale.to_string(),
+    });
+
+    let response = client.send_email_verification_notification(request).await?;
+
+    tracing::info!(
+        "Email verification notification sent successfully: {}",
+        response.into_inner().message
+    );
+
+    Ok(())
+}
+
+/// Fetch a page of a user's notifications, newest first
+pub async fn list_notifications(
+    user_id: &str,
+    unread_only: bool,
+    limit: u32,
+    offset: u32,
+) -> Result<Vec<Notification>, Box<dyn std::error::Error>> {
+    let mut client = connect().await?;
+
+    let request = tonic::Request::new(ListNotificationsRequest {
+        user_id: user_id.to_string(),
+        unread_only,
+        limit,
+        offset,
+    });
+
+    let response = client.list_notifications(request).await?;
+
+    Ok(response.into_inner().notifications)
+}
+
+/// Mark some, or with `all`, every one of a user's notifications read
+pub async fn mark_notifications_read(
+    user_id: &str,
+    ids: Vec<String>,
+    all: bool,
+) -> Result<u32, Box<dyn std::error::Error>> {
+    let mut client = connect().await?;
+
+    let request = tonic::Request::new(MarkNotificationsReadRequest {
+        user_id: user_id.to_string(),
+        ids,
+        all,
+    });
+
+    let response = client.mark_notifications_read(request).await?;
+
+    Ok(response.into_inner().updated)
+}
+
+/// Count a user's unread notifications
+pub async fn count_unread_notifications(user_id: &str) -> Result<u32, Box<dyn std::error::Error>> {
+    let mut client = connect().await?;
+
+    let request = tonic::Request::new(CountUnreadNotificationsRequest {
+        user_id: user_id.to_string(),
+    });
+
+    let response = client.count_unread_notifications(request).await?;
+
+    Ok(response.into_inner().unread)
+}
+
+/// Remove every notification of an erased user
+pub async fn delete_user_notifications(user_id: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = connect().await?;
+
+    let request = tonic::Request::new(DeleteUserNotificationsRequest {
+        user_id: user_id.to_string(),
+    });
+
+    let response = client.delete_user_notifications(request).await?;
+
+    tracing::info!(
+        "Deleted {} notifications of user {}",
+        response.into_inner().deleted,
+        user_id
+    );
+
+    Ok(())
+}
+
+/// Fetch every notification and delivery attempt stored for a user
+pub async fn export_user_notifications(
+    user_id: &str,
+) -> Result<ExportUserNotificationsResponse, Box<dyn std::error::Error>> {
+    let mut client = connect().await?;
+
+    let request = tonic::Request::new(ExportUserNotificationsRequest {
+        user_id: user_id.to_string(),
+    });
+
+    let response = client.export_user_notifications(request).await?;
+
+    Ok(response.into_inner())
+}
+
+/// Follow a user's notifications as they are sent
+pub async fn subscribe_notifications(
+    user_id: &str,
+) -> Result<Streaming<Notification>, Box<dyn std::error::Error>> {
+    let mut client = connect().await?;
+
+    let request = tonic::Request::new(SubscribeNotificationsRequest {
+        user_id: user_id.to_string(),
+    });
+
+    let response = client.subscribe_notifications(request).await?;
+
+    Ok(response.into_inner())
+}
+
+/// Fetch a user's most recent notification delivery attempts
+pub async fn list_delivery_attempts(
+    user_id: &str,
+    limit: u32,
+) -> Result<Vec<DeliveryAttempt>, Box<dyn std::error::Error>> {
+    let mut client = connect().await?;
+
+    let request = tonic::Request::new(ListDeliveryAttemptsRequest {
+        user_id: user_id.to_string(),
+        limit,
+    });
+
+    let response = client.list_delivery_attempts(request).await?;
+
+    Ok(response.into_inner().attempts)
+}
+
+/// Render a notification template with sample data, without sending it
+pub async fn preview_template(
+    kind: &str,
+    channel_name: &str,
+    locale: &str,
+    variables: HashMap<String, String>,
+) -> Result<PreviewTemplateResponse, Box<dyn std::error::Error>> {
+    let mut client = connect().await?;
+
+    let request = tonic::Request::new(PreviewTemplateRequest {
+        kind: kind.to_string(),
+        channel: channel_name.to_string(),
+        locale: locale.to_string(),
+        variables,
+    });
+
+    let response = client.preview_template(request).await?;
+
+    Ok(response.into_inner())
+}
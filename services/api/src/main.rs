@@ -1,10 +1,10 @@
 use axum::{
-    middleware::from_fn_with_state,
-    routing::{get, post},
+    middleware::{from_fn, from_fn_with_state},
+    routing::{delete, get, patch, post, put},
     Router,
 };
-use std::sync::Arc;
-use tokio::net::TcpListener;
+use std::{net::SocketAddr, sync::Arc};
+use tokio::{net::TcpListener, sync::mpsc};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
 mod config;
@@ -12,12 +12,20 @@ mod dtos;
 mod error;
 mod grpc_client;
 mod model;
+mod payments;
 mod utils;
 mod web;
 
-use config::Config;
+use config::{Config, VerifiedAction};
+use model::{ApiScope, Role};
 use web::{
-    auth, category as category_handler, mw, post as post_handler, product as product_handler,
+    account as account_handler, admin as admin_handler, api_key as api_key_handler, auth,
+    cart as cart_handler, category as category_handler, follow as follow_handler,
+    mw::{self, ScopePair},
+    notification as notification_handler, order as order_handler, password as password_handler,
+    post as post_handler, privacy as privacy_handler, product as product_handler,
+    refund as refund_handler, two_factor as two_factor_handler,
+    verification as verification_handler,
 };
 
 #[tokio::main]
@@ -31,7 +39,8 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .init();
 
     // Load configuration
-    let config = Config::from_env().await?;
+    let (webhook_tx, webhook_rx) = mpsc::unbounded_channel();
+    let config = Config::from_env(webhook_tx).await?;
     let state = Arc::new(config);
 
     // Run migrations on startup to ensure consistency
@@ -40,10 +49,70 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .await
         .expect("Failed to run migrations");
 
+    // Data requests can also be answered from a shell; see run_command
+    let args: Vec<String> = std::env::args().collect();
+    let ran = admin_handler::run_command(&state, &args)
+        .await
+        .map_err(|e| format!("{:?}", e))?;
+    if ran {
+        return Ok(());
+    }
+
+    // Apply asynchronous payment outcomes reported by the provider
+    tokio::spawn(payments::run_webhook_listener(state.clone(), webhook_rx));
+
+    // Finish payment captures and refunds whose outcome was never recorded
+    tokio::spawn(payments::run_payment_reconciler(state.clone()));
+
+    // Erase accounts whose deletion grace period has ended
+    tokio::spawn(account_handler::run_account_purger(state.clone()));
+
+    let app = app(state);
+
+    // Start Server
+    let listener = TcpListener::bind("127.0.0.1:3001").await?;
+    tracing::info!("listening on {}", listener.local_addr()?);
+    // Client addresses are needed to throttle logins per IP
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Every route, with the middleware guarding it.
+fn app(state: Arc<Config>) -> Router {
+    // Two-factor Routes (Protected)
+    let two_factor_routes = Router::new()
+        .route("/enroll", post(two_factor_handler::enroll_totp))
+        .route("/confirm", post(two_factor_handler::confirm_totp))
+        .route("/disable", post(two_factor_handler::disable_totp))
+        .route(
+            "/recovery-codes",
+            post(two_factor_handler::regenerate_recovery_codes),
+        )
+        .route_layer(from_fn(mw::require_session))
+        .route_layer(from_fn_with_state(state.clone(), mw::auth_guard));
+
     // Auth Routes
     let auth_routes = Router::new()
         .route("/signup", post(auth::signup_handler))
-        .route("/login", post(auth::login_handler));
+        .route("/login", post(auth::login_handler))
+        .route("/login/2fa", post(two_factor_handler::login_with_code))
+        .route("/refresh", post(auth::refresh_handler))
+        .route("/logout", post(auth::logout_handler))
+        .route("/password/forgot", post(password_handler::forgot_password))
+        .route("/password/reset", post(password_handler::reset_password))
+        .route("/verify", get(verification_handler::verify_email))
+        .route(
+            "/verify/resend",
+            post(verification_handler::resend_verification)
+                .route_layer(from_fn(mw::require_session))
+                .route_layer(from_fn_with_state(state.clone(), mw::auth_guard)),
+        )
+        .nest("/2fa", two_factor_routes);
 
     // Post Routes (Protected)
     let post_routes = Router::new()
@@ -52,34 +121,217 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             post(post_handler::create_post).get(post_handler::get_posts),
         )
         .route("/{id}", get(post_handler::get_post_by_id))
+        .route_layer(from_fn_with_state(
+            ScopePair {
+                read: ApiScope::PostsRead,
+                write: ApiScope::PostsWrite,
+            },
+            mw::require_scope,
+        ))
         .route_layer(from_fn_with_state(state.clone(), mw::auth_guard));
 
+    // Held back until the user verifies their email, if configured
+    let checkout_requires_verified_email = from_fn_with_state(
+        (state.clone(), VerifiedAction::Checkout),
+        mw::require_verified_email,
+    );
+    let selling_requires_verified_email = from_fn_with_state(
+        (state.clone(), VerifiedAction::Selling),
+        mw::require_verified_email,
+    );
+
     // Product Routes (Protected)
     let product_routes = Router::new()
         .route(
             "/",
-            post(product_handler::create_product).get(product_handler::get_products),
+            post(product_handler::create_product)
+                .route_layer(selling_requires_verified_email.clone())
+                .route_layer(from_fn_with_state(Role::Seller, mw::require_role))
+                .get(product_handler::get_products),
         )
-        .route("/{id}", get(product_handler::get_product_by_id))
+        .route("/search", get(product_handler::search_products))
+        .route(
+            "/{id}",
+            patch(product_handler::update_product)
+                .delete(product_handler::delete_product)
+                .route_layer(selling_requires_verified_email.clone())
+                .get(product_handler::get_product_by_id),
+        )
+        .route_layer(from_fn_with_state(
+            ScopePair {
+                read: ApiScope::ProductsRead,
+                write: ApiScope::ProductsWrite,
+            },
+            mw::require_scope,
+        ))
         .route_layer(from_fn_with_state(state.clone(), mw::auth_guard));
 
     //Category Routes
     let category_routes = Router::new()
-        .route("/", post(category_handler::create_category))
+        .route(
+            "/",
+            post(category_handler::create_category)
+                .route_layer(from_fn_with_state(Role::Admin, mw::require_role))
+                .get(category_handler::get_categories),
+        )
+        .route(
+            "/{id}",
+            patch(category_handler::update_category)
+                .delete(category_handler::delete_category)
+                .route_layer(from_fn_with_state(Role::Admin, mw::require_role))
+                .get(category_handler::get_category_by_id),
+        )
+        .route(
+            "/{id}/breadcrumbs",
+            get(category_handler::get_category_breadcrumbs),
+        )
+        .route_layer(from_fn_with_state(
+            ScopePair {
+                read: ApiScope::CategoriesRead,
+                write: ApiScope::CategoriesWrite,
+            },
+            mw::require_scope,
+        ))
+        .route_layer(from_fn_with_state(state.clone(), mw::auth_guard));
+
+    // Cart Routes (Protected)
+    let cart_routes = Router::new()
+        .route(
+            "/",
+            get(cart_handler::get_cart).delete(cart_handler::clear_cart),
+        )
+        .route("/items", post(cart_handler::add_cart_item))
+        .route(
+            "/items/{product_id}",
+            patch(cart_handler::update_cart_item).delete(cart_handler::remove_cart_item),
+        )
+        .route_layer(from_fn_with_state(
+            ScopePair {
+                read: ApiScope::CartRead,
+                write: ApiScope::CartWrite,
+            },
+            mw::require_scope,
+        ))
         .route_layer(from_fn_with_state(state.clone(), mw::auth_guard));
 
+    // Order Routes (Protected)
+    let order_routes = Router::new()
+        .route(
+            "/",
+            post(order_handler::create_order)
+                .route_layer(checkout_requires_verified_email)
+                .get(order_handler::get_orders),
+        )
+        .route("/{id}", get(order_handler::get_order_by_id))
+        .route(
+            "/{id}/status",
+            post(order_handler::update_order_status)
+                .route_layer(selling_requires_verified_email.clone()),
+        )
+        .route("/{id}/cancel", post(order_handler::cancel_order))
+        .route("/{id}/history", get(order_handler::get_order_history))
+        .route(
+            "/{id}/refunds",
+            post(refund_handler::create_refund)
+                .route_layer(selling_requires_verified_email)
+                .get(refund_handler::get_refunds),
+        )
+        .route_layer(from_fn_with_state(
+            ScopePair {
+                read: ApiScope::OrdersRead,
+                write: ApiScope::OrdersWrite,
+            },
+            mw::require_scope,
+        ))
+        .route_layer(from_fn_with_state(state.clone(), mw::auth_guard));
+
+    // Admin Routes (Protected, admins only)
+    let admin_routes = Router::new()
+        .route(
+            "/users/{id}/roles",
+            get(admin_handler::get_user_roles).post(admin_handler::grant_role),
+        )
+        .route(
+            "/users/{id}/roles/{role}",
+            delete(admin_handler::revoke_role),
+        )
+        .route("/users/{id}/export", get(admin_handler::export_user_data))
+        .route("/users/{id}/erase", post(admin_handler::erase_user_account))
+        .route(
+            "/users/{id}/notification-deliveries",
+            get(notification_handler::get_delivery_attempts),
+        )
+        .route(
+            "/notification-templates/preview",
+            post(notification_handler::preview_template),
+        )
+        .route_layer(from_fn_with_state(Role::Admin, mw::require_role))
+        .route_layer(from_fn(mw::require_session))
+        .route_layer(from_fn_with_state(state.clone(), mw::auth_guard));
+
+    // API Key Routes (Protected, login sessions only)
+    let api_key_routes = Router::new()
+        .route(
+            "/",
+            post(api_key_handler::create_api_key).get(api_key_handler::get_api_keys),
+        )
+        .route("/{id}", delete(api_key_handler::revoke_api_key))
+        .route_layer(from_fn(mw::require_session))
+        .route_layer(from_fn_with_state(state.clone(), mw::auth_guard));
+
+    // Account Routes (Protected, login sessions only)
+    let account_routes = Router::new()
+        .route(
+            "/",
+            get(account_handler::get_me)
+                .patch(account_handler::update_me)
+                .delete(account_handler::delete_me),
+        )
+        .route("/password", post(account_handler::change_password))
+        .route("/export", get(privacy_handler::export_my_data))
+        .route_layer(from_fn(mw::require_session))
+        .route_layer(from_fn_with_state(state.clone(), mw::auth_guard));
+
+    // Live notifications over SSE; EventSource cannot set headers, so the
+    // access token may also come as ?access_token=
+    let notification_stream_routes = Router::new()
+        .route("/stream", get(notification_handler::stream_notifications))
+        .route_layer(from_fn(mw::require_session))
+        .route_layer(from_fn_with_state(state.clone(), mw::auth_guard))
+        .route_layer(from_fn(mw::access_token_from_query));
+
+    // Notification Routes (Protected, login sessions only)
+    let notification_routes = Router::new()
+        .route("/", get(notification_handler::get_notifications))
+        .route("/unread-count", get(notification_handler::get_unread_count))
+        .route("/read", post(notification_handler::mark_read))
+        .route_layer(from_fn(mw::require_session))
+        .route_layer(from_fn_with_state(state.clone(), mw::auth_guard))
+        .merge(notification_stream_routes);
+
+    // Following works for signed-in users only; profiles are public
+    let seller_routes = Router::new()
+        .route(
+            "/{id}/follow",
+            put(follow_handler::follow_seller).delete(follow_handler::unfollow_seller),
+        )
+        .route_layer(from_fn(mw::require_session))
+        .route_layer(from_fn_with_state(state.clone(), mw::auth_guard))
+        .route("/{id}", get(account_handler::get_seller_profile));
+
     // Combine Routes
-    let app = Router::new()
+    Router::new()
+        .route("/.well-known/jwks.json", get(auth::jwks_handler))
         .nest("/auth", auth_routes)
         .nest("/posts", post_routes)
         .nest("/products", product_routes)
         .nest("/categories", category_routes)
-        .with_state(state);
-
-    // Start Server
-    let listener = TcpListener::bind("127.0.0.1:3001").await?;
-    tracing::info!("listening on {}", listener.local_addr()?);
-    axum::serve(listener, app).await?;
-
-    Ok(())
+        .nest("/cart", cart_routes)
+        .nest("/orders", order_routes)
+        .nest("/admin", admin_routes)
+        .nest("/api-keys", api_key_routes)
+        .nest("/me", account_routes)
+        .nest("/notifications", notification_routes)
+        .nest("/sellers", seller_routes)
+        .with_state(state)
 }
@@ -2,14 +2,150 @@ use chrono::{DateTime, Utc};
 use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
+use std::fmt;
 use uuid::Uuid;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "user_role", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Customer,
+    Seller,
+    Admin,
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Role::Customer => "customer",
+            Role::Seller => "seller",
+            Role::Admin => "admin",
+        };
+        f.write_str(name)
+    }
+}
+
 #[derive(Debug, Serialize, FromRow)]
 pub struct User {
     pub id: Uuid,
     pub username: String,
     #[serde(skip)]
     pub password_hash: String,
+    pub roles: Vec<Role>,
+    pub email: Option<String>,
+    pub email_verified_at: Option<DateTime<Utc>>,
+    pub pending_email: Option<String>,
+    #[serde(skip)]
+    pub totp_secret: Option<String>,
+    pub totp_enabled_at: Option<DateTime<Utc>>,
+    #[serde(skip)]
+    pub totp_last_step: Option<i64>,
+    pub display_name: Option<String>,
+    pub avatar_url: Option<String>,
+    pub locale: Option<String>,
+    pub deletion_scheduled_at: Option<DateTime<Utc>>,
+    pub deleted_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, FromRow)]
+pub struct Session {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub created_at: DateTime<Utc>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+/// The lookup fields of a refresh token; its hash is only ever matched in SQL.
+#[derive(Debug, FromRow)]
+pub struct RefreshToken {
+    pub id: Uuid,
+    pub session_id: Uuid,
+    pub expires_at: DateTime<Utc>,
+    pub used_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "login_outcome", rename_all = "snake_case")]
+pub enum LoginOutcome {
+    Success,
+    /// The password was right, but a second factor is still owed.
+    Challenged,
+    BadCredentials,
+    /// A wrong second-factor code.
+    BadCode,
+    /// Rejected before the password was checked.
+    Throttled,
+}
+
+/// A login attempt, kept for throttling and as an audit trail.
+#[derive(Debug, Serialize, FromRow)]
+pub struct LoginAttempt {
+    pub id: Uuid,
+    /// As submitted, so attempts against the account before the user id
+    /// was known are included.
+    pub username: String,
+    pub user_id: Option<Uuid>,
+    pub ip_address: String,
+    pub outcome: LoginOutcome,
+    pub created_at: DateTime<Utc>,
+}
+
+/// What an API key may do. Write scopes do not imply read scopes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "api_scope")]
+pub enum ApiScope {
+    #[serde(rename = "products:read")]
+    #[sqlx(rename = "products:read")]
+    ProductsRead,
+    #[serde(rename = "products:write")]
+    #[sqlx(rename = "products:write")]
+    ProductsWrite,
+    #[serde(rename = "categories:read")]
+    #[sqlx(rename = "categories:read")]
+    CategoriesRead,
+    #[serde(rename = "categories:write")]
+    #[sqlx(rename = "categories:write")]
+    CategoriesWrite,
+    #[serde(rename = "orders:read")]
+    #[sqlx(rename = "orders:read")]
+    OrdersRead,
+    #[serde(rename = "orders:write")]
+    #[sqlx(rename = "orders:write")]
+    OrdersWrite,
+    #[serde(rename = "cart:read")]
+    #[sqlx(rename = "cart:read")]
+    CartRead,
+    #[serde(rename = "cart:write")]
+    #[sqlx(rename = "cart:write")]
+    CartWrite,
+    #[serde(rename = "posts:read")]
+    #[sqlx(rename = "posts:read")]
+    PostsRead,
+    #[serde(rename = "posts:write")]
+    #[sqlx(rename = "posts:write")]
+    PostsWrite,
+}
+
+/// An API key without its secret hash, which is only matched in SQL.
+#[derive(Debug, Serialize, FromRow)]
+pub struct ApiKey {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub name: String,
+    pub prefix: String,
+    pub scopes: Vec<ApiScope>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, FromRow)]
+pub struct SellerFollow {
+    pub follower_id: Uuid,
+    pub seller_id: Uuid,
     pub created_at: DateTime<Utc>,
 }
 
@@ -33,11 +169,189 @@ pub struct Product {
     pub stock_quantity: i32,
     pub created_at: DateTime<Utc>,
     pub updated_at: DateTime<Utc>,
+    pub deleted_at: Option<DateTime<Utc>>,
+}
+
+/// A product matched by full-text search, with its rank and highlights.
+#[derive(Debug, FromRow)]
+pub struct ProductSearchHit {
+    #[sqlx(flatten)]
+    pub product: Product,
+    pub rank: f32,
+    pub name_highlight: String,
+    pub snippet: String,
 }
 
 #[derive(Debug, Serialize, FromRow)]
 pub struct Category {
     pub id: Uuid,
     pub name: String,
+    pub parent_id: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, FromRow)]
+pub struct Cart {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// A cart item joined with the product it points at.
+#[derive(Debug, Serialize, FromRow)]
+pub struct CartLine {
+    pub product_id: Uuid,
+    pub name: String,
+    pub price: Decimal,
+    pub quantity: i32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "order_status", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum OrderStatus {
+    Pending,
+    Paid,
+    Fulfilled,
+    Shipped,
+    Delivered,
+    Cancelled,
+    Refunded,
+}
+
+impl OrderStatus {
+    /// The states an order may legally move to from this one.
+    pub fn allowed_transitions(self) -> &'static [OrderStatus] {
+        use OrderStatus::*;
+
+        match self {
+            Pending => &[Paid, Cancelled],
+            Paid => &[Fulfilled, Cancelled, Refunded],
+            Fulfilled => &[Shipped, Cancelled, Refunded],
+            Shipped => &[Delivered, Refunded],
+            Delivered => &[Refunded],
+            Cancelled | Refunded => &[],
+        }
+    }
+
+    pub fn can_transition_to(self, next: OrderStatus) -> bool {
+        self.allowed_transitions().contains(&next)
+    }
+}
+
+impl fmt::Display for OrderStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            OrderStatus::Pending => "pending",
+            OrderStatus::Paid => "paid",
+            OrderStatus::Fulfilled => "fulfilled",
+            OrderStatus::Shipped => "shipped",
+            OrderStatus::Delivered => "delivered",
+            OrderStatus::Cancelled => "cancelled",
+            OrderStatus::Refunded => "refunded",
+        };
+        f.write_str(name)
+    }
+}
+
+#[derive(Debug, Serialize, FromRow)]
+pub struct Order {
+    pub id: Uuid,
+    pub user_id: Uuid,
+    pub status: OrderStatus,
+    pub total_amount: Decimal,
+    pub refunded_amount: Decimal,
     pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, FromRow)]
+pub struct OrderItem {
+    pub id: Uuid,
+    pub order_id: Uuid,
+    pub product_id: Uuid,
+    pub product_name: String,
+    pub unit_price: Decimal,
+    pub quantity: i32,
+    pub line_total: Decimal,
+    pub refunded_quantity: i32,
+    pub restocked_quantity: i32,
+}
+
+#[derive(Debug, Serialize, FromRow)]
+pub struct OrderStatusHistory {
+    pub id: Uuid,
+    pub order_id: Uuid,
+    pub from_status: Option<OrderStatus>,
+    pub to_status: OrderStatus,
+    pub changed_by: Option<Uuid>,
+    pub note: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "payment_status", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum PaymentStatus {
+    Pending,
+    Authorized,
+    /// Sent to the provider for capture; the outcome is not recorded yet.
+    Capturing,
+    Captured,
+    Voided,
+    Refunded,
+    Declined,
+    Failed,
+}
+
+#[derive(Debug, Serialize, FromRow)]
+pub struct Payment {
+    pub id: Uuid,
+    pub order_id: Uuid,
+    pub provider: String,
+    pub provider_reference: Option<String>,
+    pub status: PaymentStatus,
+    pub amount: Decimal,
+    pub refunded_amount: Decimal,
+    pub failure_reason: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(type_name = "refund_status", rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum RefundStatus {
+    /// Recorded, but the provider has not confirmed it yet.
+    Pending,
+    Succeeded,
+    /// The provider refused; the amount is refundable again.
+    Failed,
+}
+
+#[derive(Debug, Serialize, FromRow)]
+pub struct Refund {
+    pub id: Uuid,
+    pub order_id: Uuid,
+    pub payment_id: Uuid,
+    pub amount: Decimal,
+    pub reason: Option<String>,
+    pub restocked: bool,
+    pub status: RefundStatus,
+    /// Set once the provider has made the refund.
+    pub provider_reference: Option<String>,
+    pub failure_reason: Option<String>,
+    pub created_by: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Serialize, FromRow)]
+pub struct RefundItem {
+    pub id: Uuid,
+    pub refund_id: Uuid,
+    pub order_item_id: Uuid,
+    pub quantity: i32,
+    pub amount: Decimal,
 }
@@ -0,0 +1,409 @@
+use std::{collections::HashMap, sync::Mutex, time::Duration};
+
+use async_trait::async_trait;
+use rust_decimal::Decimal;
+use uuid::Uuid;
+
+use super::{
+    Authorization, AuthorizeRequest, PaymentError, PaymentProvider, WebhookEvent, WebhookSender,
+};
+
+/// Payment methods understood by the mock gateway. Anything else is declined.
+pub const APPROVE: &str = "tok_approve";
+pub const DECLINE: &str = "tok_decline";
+pub const INSUFFICIENT_FUNDS: &str = "tok_insufficient_funds";
+pub const DELAYED_APPROVE: &str = "tok_delayed_approve";
+pub const DELAYED_DECLINE: &str = "tok_delayed_decline";
+
+const WEBHOOK_DELAY: Duration = Duration::from_secs(2);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ChargeState {
+    Authorized,
+    Captured,
+    Voided,
+}
+
+#[derive(Debug)]
+struct Charge {
+    amount: Decimal,
+    refunded: Decimal,
+    state: ChargeState,
+    /// Refund references by the idempotency key they were requested with.
+    refunds: HashMap<String, String>,
+}
+
+/// An in-process gateway that never touches the network. It keeps its own
+/// ledger so captures, voids and refunds are validated the way a real
+/// processor would, and delivers "delayed" outcomes as webhook events.
+pub struct MockPaymentProvider {
+    charges: Mutex<HashMap<String, Charge>>,
+    webhooks: WebhookSender,
+    webhook_delay: Duration,
+}
+
+impl MockPaymentProvider {
+    pub fn new(webhooks: WebhookSender) -> Self {
+        Self {
+            charges: Mutex::new(HashMap::new()),
+            webhooks,
+            webhook_delay: WEBHOOK_DELAY,
+        }
+    }
+
+    fn new_reference(prefix: &str) -> String {
+        format!("{}_{}", prefix, Uuid::new_v4().simple())
+    }
+
+    fn record_authorization(&self, reference: &str, amount: Decimal) {
+        self.charges.lock().unwrap().insert(
+            reference.to_string(),
+            Charge {
+                amount,
+                refunded: Decimal::ZERO,
+                state: ChargeState::Authorized,
+                refunds: HashMap::new(),
+            },
+        );
+    }
+
+    fn schedule_webhook(&self, event: WebhookEvent) {
+        let webhooks = self.webhooks.clone();
+        let delay = self.webhook_delay;
+
+        tokio::spawn(async move {
+            tokio::time::sleep(delay).await;
+            if webhooks.send(event).is_err() {
+                tracing::warn!("Mock payment webhook dropped: listener is gone");
+            }
+        });
+    }
+}
+
+#[async_trait]
+impl PaymentProvider for MockPaymentProvider {
+    fn name(&self) -> &'static str {
+        "mock"
+    }
+
+    async fn authorize(&self, request: AuthorizeRequest) -> Result<Authorization, PaymentError> {
+        if request.amount <= Decimal::ZERO {
+            return Err(PaymentError::Provider(
+                "amount must be positive".to_string(),
+            ));
+        }
+
+        let reference = Self::new_reference("mock_auth");
+        tracing::debug!(
+            "Mock authorization {} for order {} using {}",
+            reference,
+            request.order_id,
+            request.payment_method
+        );
+
+        match request.payment_method.as_str() {
+            APPROVE => {
+                self.record_authorization(&reference, request.amount);
+                Ok(Authorization::Approved { reference })
+            }
+            DECLINE => Err(PaymentError::Declined("card_declined".to_string())),
+            INSUFFICIENT_FUNDS => Err(PaymentError::Declined("insufficient_funds".to_string())),
+            DELAYED_APPROVE => {
+                self.record_authorization(&reference, request.amount);
+                self.schedule_webhook(WebhookEvent::AuthorizationSucceeded {
+                    reference: reference.clone(),
+                });
+                Ok(Authorization::Pending { reference })
+            }
+            DELAYED_DECLINE => {
+                self.schedule_webhook(WebhookEvent::AuthorizationFailed {
+                    reference: reference.clone(),
+                    reason: "card_declined".to_string(),
+                });
+                Ok(Authorization::Pending { reference })
+            }
+            _ => Err(PaymentError::Declined("unknown_payment_method".to_string())),
+        }
+    }
+
+    async fn capture(&self, reference: &str, amount: Decimal) -> Result<(), PaymentError> {
+        let mut charges = self.charges.lock().unwrap();
+        let charge = charges
+            .get_mut(reference)
+            .ok_or_else(|| PaymentError::Provider(format!("unknown charge {}", reference)))?;
+
+        // Retrying a capture that already went through is harmless
+        if charge.state == ChargeState::Captured && charge.amount == amount {
+            return Ok(());
+        }
+        if charge.state != ChargeState::Authorized {
+            return Err(PaymentError::Provider(format!(
+                "charge {} cannot be captured",
+                reference
+            )));
+        }
+        if amount > charge.amount {
+            return Err(PaymentError::Provider(
+                "capture exceeds authorized amount".to_string(),
+            ));
+        }
+
+        charge.amount = amount;
+        charge.state = ChargeState::Captured;
+        Ok(())
+    }
+
+    async fn void(&self, reference: &str) -> Result<(), PaymentError> {
+        let mut charges = self.charges.lock().unwrap();
+        let charge = charges
+            .get_mut(reference)
+            .ok_or_else(|| PaymentError::Provider(format!("unknown charge {}", reference)))?;
+
+        if charge.state != ChargeState::Authorized {
+            return Err(PaymentError::Provider(format!(
+                "charge {} cannot be voided",
+                reference
+            )));
+        }
+
+        charge.state = ChargeState::Voided;
+        Ok(())
+    }
+
+    async fn refund(
+        &self,
+        reference: &str,
+        amount: Decimal,
+        idempotency_key: &str,
+    ) -> Result<String, PaymentError> {
+        let mut charges = self.charges.lock().unwrap();
+        let charge = charges
+            .get_mut(reference)
+            .ok_or_else(|| PaymentError::Provider(format!("unknown charge {}", reference)))?;
+
+        if let Some(refund_reference) = charge.refunds.get(idempotency_key) {
+            return Ok(refund_reference.clone());
+        }
+        if charge.state != ChargeState::Captured {
+            return Err(PaymentError::Declined("charge_not_captured".to_string()));
+        }
+        if amount <= Decimal::ZERO || charge.refunded + amount > charge.amount {
+            return Err(PaymentError::Declined(
+                "refund_exceeds_captured_amount".to_string(),
+            ));
+        }
+
+        let refund_reference = Self::new_reference("mock_refund");
+        charge.refunded += amount;
+        charge
+            .refunds
+            .insert(idempotency_key.to_string(), refund_reference.clone());
+        Ok(refund_reference)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal_macros::dec;
+    use tokio::sync::mpsc::{self, UnboundedReceiver};
+
+    use super::*;
+
+    fn provider() -> (MockPaymentProvider, UnboundedReceiver<WebhookEvent>) {
+        let (webhooks, events) = mpsc::unbounded_channel();
+        let provider = MockPaymentProvider {
+            webhook_delay: Duration::from_millis(10),
+            ..MockPaymentProvider::new(webhooks)
+        };
+        (provider, events)
+    }
+
+    fn request(payment_method: &str, amount: Decimal) -> AuthorizeRequest {
+        AuthorizeRequest {
+            order_id: Uuid::new_v4(),
+            amount,
+            payment_method: payment_method.to_string(),
+        }
+    }
+
+    async fn approved(provider: &MockPaymentProvider, amount: Decimal) -> String {
+        match provider.authorize(request(APPROVE, amount)).await {
+            Ok(Authorization::Approved { reference }) => reference,
+            other => panic!("expected approval, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn approves_and_captures() {
+        let (provider, _events) = provider();
+        let reference = approved(&provider, dec!(25.00)).await;
+
+        provider.capture(&reference, dec!(25.00)).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn declines_bad_payment_methods() {
+        let (provider, _events) = provider();
+
+        for (method, reason) in [
+            (DECLINE, "card_declined"),
+            (INSUFFICIENT_FUNDS, "insufficient_funds"),
+            ("tok_unknown", "unknown_payment_method"),
+        ] {
+            match provider.authorize(request(method, dec!(10.00))).await {
+                Err(PaymentError::Declined(declined)) => assert_eq!(declined, reason),
+                other => panic!("expected {} to be declined, got {:?}", method, other),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_non_positive_amounts() {
+        let (provider, _events) = provider();
+
+        let result = provider.authorize(request(APPROVE, Decimal::ZERO)).await;
+        assert!(matches!(result, Err(PaymentError::Provider(_))));
+    }
+
+    #[tokio::test]
+    async fn capture_can_be_retried_but_not_exceed_authorization() {
+        let (provider, _events) = provider();
+        let reference = approved(&provider, dec!(25.00)).await;
+
+        assert!(provider.capture(&reference, dec!(30.00)).await.is_err());
+        provider.capture(&reference, dec!(25.00)).await.unwrap();
+        provider.capture(&reference, dec!(25.00)).await.unwrap();
+        assert!(provider
+            .capture("mock_auth_missing", dec!(1.00))
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn voids_only_uncaptured_authorizations() {
+        let (provider, _events) = provider();
+
+        let voided = approved(&provider, dec!(10.00)).await;
+        provider.void(&voided).await.unwrap();
+        assert!(provider.capture(&voided, dec!(10.00)).await.is_err());
+        assert!(provider.refund(&voided, dec!(1.00), "r1").await.is_err());
+
+        let captured = approved(&provider, dec!(10.00)).await;
+        provider.capture(&captured, dec!(10.00)).await.unwrap();
+        assert!(provider.void(&captured).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn refunds_up_to_the_captured_amount() {
+        let (provider, _events) = provider();
+        let reference = approved(&provider, dec!(50.00)).await;
+
+        // Nothing to refund before the capture
+        assert!(provider.refund(&reference, dec!(5.00), "r1").await.is_err());
+        provider.capture(&reference, dec!(50.00)).await.unwrap();
+
+        let first = provider
+            .refund(&reference, dec!(20.00), "r1")
+            .await
+            .unwrap();
+        let second = provider
+            .refund(&reference, dec!(30.00), "r2")
+            .await
+            .unwrap();
+        assert_ne!(first, second);
+
+        assert!(provider.refund(&reference, dec!(0.01), "r3").await.is_err());
+        assert!(provider
+            .refund(&reference, Decimal::ZERO, "r4")
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn rejects_over_refund() {
+        let (provider, _events) = provider();
+        let reference = approved(&provider, dec!(50.00)).await;
+        provider.capture(&reference, dec!(50.00)).await.unwrap();
+
+        assert!(matches!(
+            provider.refund(&reference, dec!(50.01), "r1").await,
+            Err(PaymentError::Declined(_))
+        ));
+        provider
+            .refund(&reference, dec!(50.00), "r2")
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn repeated_refund_requests_refund_once() {
+        let (provider, _events) = provider();
+        let reference = approved(&provider, dec!(50.00)).await;
+        provider.capture(&reference, dec!(50.00)).await.unwrap();
+
+        let first = provider
+            .refund(&reference, dec!(40.00), "r1")
+            .await
+            .unwrap();
+        let retried = provider
+            .refund(&reference, dec!(40.00), "r1")
+            .await
+            .unwrap();
+        assert_eq!(first, retried);
+
+        // The retry did not use up any more of the captured amount
+        provider
+            .refund(&reference, dec!(10.00), "r2")
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn delayed_approval_arrives_by_webhook() {
+        let (provider, mut events) = provider();
+
+        let reference = match provider
+            .authorize(request(DELAYED_APPROVE, dec!(15.00)))
+            .await
+        {
+            Ok(Authorization::Pending { reference }) => reference,
+            other => panic!("expected a pending authorization, got {:?}", other),
+        };
+        assert!(
+            events.try_recv().is_err(),
+            "webhook arrived before the delay"
+        );
+
+        let event = events.recv().await.unwrap();
+        assert_eq!(
+            event,
+            WebhookEvent::AuthorizationSucceeded {
+                reference: reference.clone()
+            }
+        );
+        provider.capture(&reference, dec!(15.00)).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn delayed_decline_arrives_by_webhook() {
+        let (provider, mut events) = provider();
+
+        let reference = match provider
+            .authorize(request(DELAYED_DECLINE, dec!(15.00)))
+            .await
+        {
+            Ok(Authorization::Pending { reference }) => reference,
+            other => panic!("expected a pending authorization, got {:?}", other),
+        };
+
+        let event = events.recv().await.unwrap();
+        assert_eq!(
+            event,
+            WebhookEvent::AuthorizationFailed {
+                reference: reference.clone(),
+                reason: "card_declined".to_string(),
+            }
+        );
+        assert!(provider.capture(&reference, dec!(15.00)).await.is_err());
+    }
+}
@@ -0,0 +1,857 @@
+use std::{fmt, sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use rust_decimal::Decimal;
+use sqlx::{PgConnection, PgPool};
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+use uuid::Uuid;
+
+use crate::{
+    config::Config,
+    error::AppError,
+    model::{Order, OrderStatus, Payment, PaymentStatus, Refund, RefundItem, RefundStatus},
+    web::order::transition_order,
+};
+
+pub mod mock;
+
+/// How often payments stuck mid-capture or mid-refund are looked for.
+const RECONCILE_INTERVAL: Duration = Duration::from_secs(60);
+/// A capture or refund older than this is assumed to have been interrupted
+/// rather than still be in flight.
+const STALE_MINUTES: i32 = 5;
+
+/// What the provider needs to know to authorize a charge.
+#[derive(Debug, Clone)]
+pub struct AuthorizeRequest {
+    pub order_id: Uuid,
+    pub amount: Decimal,
+    /// Opaque token identifying the buyer's payment method with the provider.
+    pub payment_method: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Authorization {
+    /// Funds are reserved and can be captured right away.
+    Approved { reference: String },
+    /// The provider will report the outcome later through a webhook.
+    Pending { reference: String },
+}
+
+#[derive(Debug)]
+pub enum PaymentError {
+    Declined(String),
+    Provider(String),
+}
+
+impl fmt::Display for PaymentError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PaymentError::Declined(reason) => write!(f, "payment declined: {}", reason),
+            PaymentError::Provider(msg) => write!(f, "payment provider error: {}", msg),
+        }
+    }
+}
+
+/// Asynchronous notifications a provider sends about earlier requests.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WebhookEvent {
+    AuthorizationSucceeded { reference: String },
+    AuthorizationFailed { reference: String, reason: String },
+}
+
+pub type WebhookSender = UnboundedSender<WebhookEvent>;
+
+/// A payment processor. Amounts are in the store currency.
+#[async_trait]
+pub trait PaymentProvider: Send + Sync {
+    /// Stored alongside each payment so references stay unambiguous.
+    fn name(&self) -> &'static str;
+
+    async fn authorize(&self, request: AuthorizeRequest) -> Result<Authorization, PaymentError>;
+
+    /// Capturing a charge that is already captured for the same amount must
+    /// succeed, so a capture whose outcome was never recorded can be retried.
+    async fn capture(&self, reference: &str, amount: Decimal) -> Result<(), PaymentError>;
+
+    async fn void(&self, reference: &str) -> Result<(), PaymentError>;
+
+    /// Returns the provider's reference for the refund. A repeated request
+    /// with the same idempotency key must return the original refund rather
+    /// than refund again.
+    async fn refund(
+        &self,
+        reference: &str,
+        amount: Decimal,
+        idempotency_key: &str,
+    ) -> Result<String, PaymentError>;
+}
+
+/// Builds the provider selected by `PAYMENT_PROVIDER` (defaults to `mock`).
+pub fn provider_from_env(webhooks: WebhookSender) -> Arc<dyn PaymentProvider> {
+    let name = std::env::var("PAYMENT_PROVIDER").unwrap_or_else(|_| "mock".into());
+
+    match name.as_str() {
+        "mock" => Arc::new(mock::MockPaymentProvider::new(webhooks)),
+        other => panic!("Unsupported PAYMENT_PROVIDER: {}", other),
+    }
+}
+
+/// Charges a freshly placed order. Approved payments are captured and the
+/// order becomes paid; declined or failed payments cancel the order, which
+/// releases its stock. Pending authorizations leave the order pending until
+/// the provider's webhook arrives.
+pub async fn charge_order(
+    state: &Config,
+    order: Order,
+    payment_method: &str,
+) -> Result<Order, AppError> {
+    let provider = &state.payment_provider;
+
+    let payment = sqlx::query_as::<_, Payment>(
+        "INSERT INTO payments (order_id, provider, amount) VALUES ($1, $2, $3) RETURNING *",
+    )
+    .bind(order.id)
+    .bind(provider.name())
+    .bind(order.total_amount)
+    .fetch_one(&state.db_pool)
+    .await?;
+
+    let request = AuthorizeRequest {
+        order_id: order.id,
+        amount: order.total_amount,
+        payment_method: payment_method.to_string(),
+    };
+
+    match provider.authorize(request).await {
+        Ok(Authorization::Approved { reference }) => {
+            set_payment_status(
+                &state.db_pool,
+                payment.id,
+                PaymentStatus::Authorized,
+                Some(&reference),
+            )
+            .await?;
+            capture_payment(state, payment.id, order.id, &reference, payment.amount).await
+        }
+        Ok(Authorization::Pending { reference }) => {
+            set_payment_status(
+                &state.db_pool,
+                payment.id,
+                PaymentStatus::Pending,
+                Some(&reference),
+            )
+            .await?;
+            Ok(order)
+        }
+        Err(e) => {
+            let status = match e {
+                PaymentError::Declined(_) => PaymentStatus::Declined,
+                PaymentError::Provider(_) => PaymentStatus::Failed,
+            };
+            fail_payment(&state.db_pool, payment.id, order.id, status, &e.to_string()).await?;
+            Err(e.into())
+        }
+    }
+}
+
+/// A line item covered by a refund and the share of the amount it accounts for.
+#[derive(Debug, Clone)]
+pub struct RefundLine {
+    pub order_item_id: Uuid,
+    pub quantity: i32,
+    pub amount: Decimal,
+}
+
+#[derive(Debug)]
+pub struct NewRefund<'a> {
+    pub amount: Decimal,
+    pub lines: Vec<RefundLine>,
+    /// Put the refunded units back into stock.
+    pub restock: bool,
+    pub reason: Option<&'a str>,
+    pub created_by: Option<Uuid>,
+}
+
+/// Records a refund against an order's captured payment, without making it
+/// yet.
+///
+/// Runs on the caller's transaction, which should already hold the order
+/// row lock. The refund starts out pending, with its amount and quantities
+/// already counted as refunded so concurrent refunds cannot go over what was
+/// paid. Once the transaction commits, [`complete_refund`] asks the provider
+/// for the money; a refund that never gets that far is picked up by
+/// [`run_payment_reconciler`].
+pub async fn record_refund(
+    conn: &mut PgConnection,
+    order_id: Uuid,
+    refund: NewRefund<'_>,
+) -> Result<Refund, AppError> {
+    let payment = sqlx::query_as::<_, Payment>(
+        "SELECT * FROM payments WHERE order_id = $1 AND status = 'captured' FOR UPDATE",
+    )
+    .bind(order_id)
+    .fetch_optional(&mut *conn)
+    .await?
+    .ok_or(AppError::BadRequest(
+        "Order has no captured payment to refund".to_string(),
+    ))?;
+
+    let remaining = payment.amount - payment.refunded_amount;
+    if refund.amount <= Decimal::ZERO || refund.amount > remaining {
+        return Err(AppError::BadRequest(format!(
+            "Refund amount must be between 0 and {}",
+            remaining
+        )));
+    }
+
+    sqlx::query(
+        "UPDATE payments SET refunded_amount = refunded_amount + $2, updated_at = NOW() WHERE id = $1",
+    )
+    .bind(payment.id)
+    .bind(refund.amount)
+    .execute(&mut *conn)
+    .await?;
+
+    sqlx::query(
+        "UPDATE orders SET refunded_amount = refunded_amount + $2, updated_at = NOW() WHERE id = $1",
+    )
+    .bind(order_id)
+    .bind(refund.amount)
+    .execute(&mut *conn)
+    .await?;
+
+    let record = sqlx::query_as::<_, Refund>(
+        "INSERT INTO refunds (order_id, payment_id, amount, reason, restocked, created_by)
+         VALUES ($1, $2, $3, $4, $5, $6) RETURNING *",
+    )
+    .bind(order_id)
+    .bind(payment.id)
+    .bind(refund.amount)
+    .bind(refund.reason)
+    .bind(refund.restock)
+    .bind(refund.created_by)
+    .fetch_one(&mut *conn)
+    .await?;
+
+    for line in &refund.lines {
+        sqlx::query(
+            "UPDATE order_items SET refunded_quantity = refunded_quantity + $2 WHERE id = $1",
+        )
+        .bind(line.order_item_id)
+        .bind(line.quantity)
+        .execute(&mut *conn)
+        .await?;
+
+        sqlx::query(
+            "INSERT INTO refund_items (refund_id, order_item_id, quantity, amount)
+             VALUES ($1, $2, $3, $4)",
+        )
+        .bind(record.id)
+        .bind(line.order_item_id)
+        .bind(line.quantity)
+        .bind(line.amount)
+        .execute(&mut *conn)
+        .await?;
+    }
+
+    Ok(record)
+}
+
+/// Asks the provider for a pending refund's money and records the outcome.
+///
+/// The refund's id is sent as the idempotency key, so calling this again
+/// for a refund the provider already made does not refund twice. A refund
+/// the provider could not be reached for stays pending and is returned as
+/// such; one it refused is marked failed and its amount and quantities are
+/// released.
+pub async fn complete_refund(state: &Config, refund_id: Uuid) -> Result<Refund, AppError> {
+    let refund = sqlx::query_as::<_, Refund>("SELECT * FROM refunds WHERE id = $1")
+        .bind(refund_id)
+        .fetch_one(&state.db_pool)
+        .await?;
+
+    if refund.status != RefundStatus::Pending {
+        return Ok(refund);
+    }
+
+    let reference = sqlx::query_scalar::<_, Option<String>>(
+        "SELECT provider_reference FROM payments WHERE id = $1",
+    )
+    .bind(refund.payment_id)
+    .fetch_one(&state.db_pool)
+    .await?
+    .ok_or(AppError::InternalServerError)?;
+    match state
+        .payment_provider
+        .refund(&reference, refund.amount, &refund.id.to_string())
+        .await
+    {
+        Ok(provider_reference) => finish_refund(state, &refund, &provider_reference).await,
+        Err(PaymentError::Declined(reason)) => {
+            release_refund(state, &refund, &reason).await?;
+            Err(AppError::PaymentDeclined(reason))
+        }
+        Err(PaymentError::Provider(msg)) => {
+            tracing::warn!(
+                "Refund {} could not be made yet, leaving it for reconciliation: {}",
+                refund.id,
+                msg
+            );
+            Ok(refund)
+        }
+    }
+}
+
+/// Records that the provider made a refund: restocks its items and marks
+/// the order refunded once nothing paid for is left.
+async fn finish_refund(
+    state: &Config,
+    refund: &Refund,
+    provider_reference: &str,
+) -> Result<Refund, AppError> {
+    let mut tx = state.db_pool.begin().await?;
+
+    let order = sqlx::query_as::<_, Order>("SELECT * FROM orders WHERE id = $1 FOR UPDATE")
+        .bind(refund.order_id)
+        .fetch_one(&mut *tx)
+        .await?;
+
+    let finished = sqlx::query_as::<_, Refund>(
+        "UPDATE refunds
+         SET status = 'succeeded', provider_reference = $2, updated_at = NOW()
+         WHERE id = $1 AND status = 'pending'
+         RETURNING *",
+    )
+    .bind(refund.id)
+    .bind(provider_reference)
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    // Another attempt got here first
+    let Some(finished) = finished else {
+        let current = sqlx::query_as::<_, Refund>("SELECT * FROM refunds WHERE id = $1")
+            .bind(refund.id)
+            .fetch_one(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        return Ok(current);
+    };
+
+    sqlx::query(
+        "UPDATE payments
+         SET status = CASE WHEN refunded_amount >= amount THEN 'refunded' ELSE status END,
+             updated_at = NOW()
+         WHERE id = $1",
+    )
+    .bind(refund.payment_id)
+    .execute(&mut *tx)
+    .await?;
+
+    if refund.restocked {
+        let items =
+            sqlx::query_as::<_, RefundItem>("SELECT * FROM refund_items WHERE refund_id = $1")
+                .bind(refund.id)
+                .fetch_all(&mut *tx)
+                .await?;
+
+        for item in items {
+            // Units a cancellation put back in the meantime are not
+            // restocked twice
+            let restock = sqlx::query_scalar::<_, i32>(
+                "SELECT LEAST($2, quantity - restocked_quantity) FROM order_items
+                 WHERE id = $1 FOR UPDATE",
+            )
+            .bind(item.order_item_id)
+            .bind(item.quantity)
+            .fetch_one(&mut *tx)
+            .await?;
+
+            sqlx::query(
+                "UPDATE order_items SET restocked_quantity = restocked_quantity + $2 WHERE id = $1",
+            )
+            .bind(item.order_item_id)
+            .bind(restock)
+            .execute(&mut *tx)
+            .await?;
+
+            sqlx::query(
+                "UPDATE products p
+                 SET stock_quantity = p.stock_quantity + $2, updated_at = NOW()
+                 FROM order_items oi
+                 WHERE oi.id = $1 AND p.id = oi.product_id",
+            )
+            .bind(item.order_item_id)
+            .bind(restock)
+            .execute(&mut *tx)
+            .await?;
+        }
+    }
+
+    // Refunds still in flight might yet fail and give their amount back
+    let in_flight = sqlx::query_scalar::<_, bool>(
+        "SELECT EXISTS (SELECT 1 FROM refunds WHERE order_id = $1 AND status = 'pending')",
+    )
+    .bind(order.id)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    if !in_flight
+        && order.refunded_amount >= order.total_amount
+        && order.status.can_transition_to(OrderStatus::Refunded)
+    {
+        transition_order(
+            &mut tx,
+            order.id,
+            OrderStatus::Refunded,
+            refund.created_by,
+            refund.reason.as_deref(),
+        )
+        .await?;
+    }
+
+    tx.commit().await?;
+
+    Ok(finished)
+}
+
+/// Gives back what a refund the provider refused had reserved.
+async fn release_refund(state: &Config, refund: &Refund, reason: &str) -> Result<(), AppError> {
+    let mut tx = state.db_pool.begin().await?;
+
+    sqlx::query("SELECT 1 FROM orders WHERE id = $1 FOR UPDATE")
+        .bind(refund.order_id)
+        .execute(&mut *tx)
+        .await?;
+
+    let released = sqlx::query(
+        "UPDATE refunds SET status = 'failed', failure_reason = $2, updated_at = NOW()
+         WHERE id = $1 AND status = 'pending'",
+    )
+    .bind(refund.id)
+    .bind(reason)
+    .execute(&mut *tx)
+    .await?;
+    if released.rows_affected() == 0 {
+        return Ok(());
+    }
+
+    tracing::error!(
+        "Provider refused refund {} of order {}: {}",
+        refund.id,
+        refund.order_id,
+        reason
+    );
+
+    sqlx::query(
+        "UPDATE payments SET refunded_amount = refunded_amount - $2, updated_at = NOW() WHERE id = $1",
+    )
+    .bind(refund.payment_id)
+    .bind(refund.amount)
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query(
+        "UPDATE orders SET refunded_amount = refunded_amount - $2, updated_at = NOW() WHERE id = $1",
+    )
+    .bind(refund.order_id)
+    .bind(refund.amount)
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query(
+        "UPDATE order_items oi
+         SET refunded_quantity = oi.refunded_quantity - ri.quantity
+         FROM refund_items ri
+         WHERE ri.refund_id = $1 AND oi.id = ri.order_item_id",
+    )
+    .bind(refund.id)
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+/// Records a refund of whatever is left of the captured payment of an order
+/// being cancelled, if it was paid. Cancellation restocks the items itself.
+/// The caller passes the returned refund to [`complete_refund`] after
+/// committing.
+pub async fn refund_captured_payment(
+    conn: &mut PgConnection,
+    order_id: Uuid,
+    created_by: Option<Uuid>,
+) -> Result<Option<Refund>, AppError> {
+    let remaining = sqlx::query_scalar::<_, Decimal>(
+        "SELECT amount - refunded_amount FROM payments WHERE order_id = $1 AND status = 'captured'",
+    )
+    .bind(order_id)
+    .fetch_optional(&mut *conn)
+    .await?;
+
+    let Some(amount) = remaining.filter(|amount| *amount > Decimal::ZERO) else {
+        return Ok(None);
+    };
+
+    let refund = record_refund(
+        conn,
+        order_id,
+        NewRefund {
+            amount,
+            lines: Vec::new(),
+            restock: false,
+            reason: Some("Order cancelled"),
+            created_by,
+        },
+    )
+    .await?;
+
+    Ok(Some(refund))
+}
+
+/// Applies provider webhooks until the sending side goes away.
+pub async fn run_webhook_listener(state: Arc<Config>, mut events: UnboundedReceiver<WebhookEvent>) {
+    while let Some(event) = events.recv().await {
+        if let Err(e) = handle_webhook_event(&state, &event).await {
+            tracing::error!("Failed to handle payment webhook {:?}: {:?}", event, e);
+        }
+    }
+}
+
+async fn handle_webhook_event(state: &Config, event: &WebhookEvent) -> Result<(), AppError> {
+    let reference = match event {
+        WebhookEvent::AuthorizationSucceeded { reference }
+        | WebhookEvent::AuthorizationFailed { reference, .. } => reference,
+    };
+
+    let payment = sqlx::query_as::<_, Payment>(
+        "SELECT * FROM payments WHERE provider = $1 AND provider_reference = $2",
+    )
+    .bind(state.payment_provider.name())
+    .bind(reference)
+    .fetch_optional(&state.db_pool)
+    .await?;
+
+    // Providers may redeliver webhooks; only pending payments still need work
+    let Some(payment) = payment.filter(|p| p.status == PaymentStatus::Pending) else {
+        tracing::debug!("Ignoring webhook for settled payment {}", reference);
+        return Ok(());
+    };
+
+    let order_status =
+        sqlx::query_scalar::<_, OrderStatus>("SELECT status FROM orders WHERE id = $1")
+            .bind(payment.order_id)
+            .fetch_one(&state.db_pool)
+            .await?;
+
+    match event {
+        // The buyer cancelled before the authorization came through
+        WebhookEvent::AuthorizationSucceeded { reference }
+            if order_status != OrderStatus::Pending =>
+        {
+            state.payment_provider.void(reference).await?;
+            set_payment_status(&state.db_pool, payment.id, PaymentStatus::Voided, None).await?;
+        }
+        WebhookEvent::AuthorizationSucceeded { reference } => {
+            set_payment_status(&state.db_pool, payment.id, PaymentStatus::Authorized, None).await?;
+            capture_payment(
+                state,
+                payment.id,
+                payment.order_id,
+                reference,
+                payment.amount,
+            )
+            .await?;
+        }
+        WebhookEvent::AuthorizationFailed { .. } if order_status != OrderStatus::Pending => {
+            set_payment_status(&state.db_pool, payment.id, PaymentStatus::Declined, None).await?;
+        }
+        WebhookEvent::AuthorizationFailed { reason, .. } => {
+            fail_payment(
+                &state.db_pool,
+                payment.id,
+                payment.order_id,
+                PaymentStatus::Declined,
+                reason,
+            )
+            .await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Captures an authorized payment and marks the order paid.
+///
+/// The payment is marked `capturing` before the provider is called, so if
+/// recording the outcome fails the payment is left for
+/// [`run_payment_reconciler`] instead of captured money going unnoticed.
+async fn capture_payment(
+    state: &Config,
+    payment_id: Uuid,
+    order_id: Uuid,
+    reference: &str,
+    amount: Decimal,
+) -> Result<Order, AppError> {
+    let claimed = sqlx::query(
+        "UPDATE payments SET status = 'capturing', updated_at = NOW()
+         WHERE id = $1 AND status = 'authorized'",
+    )
+    .bind(payment_id)
+    .execute(&state.db_pool)
+    .await?;
+    if claimed.rows_affected() == 0 {
+        return Err(AppError::BadRequest(
+            "Payment is not awaiting capture".to_string(),
+        ));
+    }
+
+    if let Err(e) = state.payment_provider.capture(reference, amount).await {
+        abandon_capture(state, payment_id, order_id, reference, &e).await?;
+        return Err(e.into());
+    }
+
+    match settle_capture(state, payment_id, order_id).await {
+        Ok(order) => Ok(order),
+        Err(e) => {
+            tracing::error!(
+                "Captured payment {} but failed to record it, leaving it for reconciliation: {:?}",
+                payment_id,
+                e
+            );
+            let order = sqlx::query_as::<_, Order>("SELECT * FROM orders WHERE id = $1")
+                .bind(order_id)
+                .fetch_one(&state.db_pool)
+                .await?;
+            Ok(order)
+        }
+    }
+}
+
+/// Records a capture the provider accepted. If the order was cancelled while
+/// the capture was in flight, the money goes straight back to the buyer.
+async fn settle_capture(
+    state: &Config,
+    payment_id: Uuid,
+    order_id: Uuid,
+) -> Result<Order, AppError> {
+    let mut tx = state.db_pool.begin().await?;
+
+    let order_status =
+        sqlx::query_scalar::<_, OrderStatus>("SELECT status FROM orders WHERE id = $1 FOR UPDATE")
+            .bind(order_id)
+            .fetch_one(&mut *tx)
+            .await?;
+
+    let settled = sqlx::query(
+        "UPDATE payments SET status = 'captured', updated_at = NOW()
+         WHERE id = $1 AND status = 'capturing'",
+    )
+    .bind(payment_id)
+    .execute(&mut *tx)
+    .await?;
+
+    // Only one settlement wins; a later one has nothing left to do
+    let mut refund = None;
+    if settled.rows_affected() == 1 {
+        if order_status == OrderStatus::Pending {
+            transition_order(
+                &mut tx,
+                order_id,
+                OrderStatus::Paid,
+                None,
+                Some("Payment captured"),
+            )
+            .await?;
+        } else {
+            tracing::warn!(
+                "Order {} became {} while its payment was captured; refunding",
+                order_id,
+                order_status
+            );
+            refund = refund_captured_payment(&mut tx, order_id, None).await?;
+        }
+    }
+
+    let order = sqlx::query_as::<_, Order>("SELECT * FROM orders WHERE id = $1")
+        .bind(order_id)
+        .fetch_one(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+
+    if let Some(refund) = refund {
+        if let Err(e) = complete_refund(state, refund.id).await {
+            tracing::error!("Failed to refund cancelled order {}: {:?}", order_id, e);
+        }
+    }
+
+    Ok(order)
+}
+
+/// Gives up on a capture the provider refused: releases the hold rather
+/// than leave the buyer's funds reserved, and cancels the order if it is
+/// still waiting for payment.
+async fn abandon_capture(
+    state: &Config,
+    payment_id: Uuid,
+    order_id: Uuid,
+    reference: &str,
+    error: &PaymentError,
+) -> Result<(), AppError> {
+    if let Err(void_error) = state.payment_provider.void(reference).await {
+        tracing::warn!("Failed to void authorization {}: {}", reference, void_error);
+    }
+
+    let order_status =
+        sqlx::query_scalar::<_, OrderStatus>("SELECT status FROM orders WHERE id = $1")
+            .bind(order_id)
+            .fetch_one(&state.db_pool)
+            .await?;
+
+    if order_status == OrderStatus::Pending {
+        fail_payment(
+            &state.db_pool,
+            payment_id,
+            order_id,
+            PaymentStatus::Failed,
+            &error.to_string(),
+        )
+        .await
+    } else {
+        set_payment_status(&state.db_pool, payment_id, PaymentStatus::Failed, None).await
+    }
+}
+
+/// Finishes captures and refunds that were interrupted between the provider
+/// call and recording its outcome, e.g. by a crash or a database error.
+pub async fn run_payment_reconciler(state: Arc<Config>) {
+    let mut interval = tokio::time::interval(RECONCILE_INTERVAL);
+    loop {
+        interval.tick().await;
+        if let Err(e) = reconcile_captures(&state).await {
+            tracing::error!("Failed to reconcile payment captures: {:?}", e);
+        }
+        if let Err(e) = reconcile_refunds(&state).await {
+            tracing::error!("Failed to reconcile refunds: {:?}", e);
+        }
+    }
+}
+
+async fn reconcile_captures(state: &Config) -> Result<(), AppError> {
+    let stale = sqlx::query_as::<_, Payment>(
+        "SELECT * FROM payments
+         WHERE status = 'capturing' AND provider = $1
+           AND updated_at < NOW() - make_interval(mins => $2)",
+    )
+    .bind(state.payment_provider.name())
+    .bind(STALE_MINUTES)
+    .fetch_all(&state.db_pool)
+    .await?;
+
+    for payment in stale {
+        let Some(reference) = payment.provider_reference.as_deref() else {
+            tracing::error!("Payment {} is capturing without a reference", payment.id);
+            continue;
+        };
+
+        // Captures are idempotent, so this either repeats a capture that
+        // already happened or completes one that never reached the provider
+        match state
+            .payment_provider
+            .capture(reference, payment.amount)
+            .await
+        {
+            Ok(()) => {
+                settle_capture(state, payment.id, payment.order_id).await?;
+                tracing::info!("Reconciled capture of payment {}", payment.id);
+            }
+            Err(e) => {
+                abandon_capture(state, payment.id, payment.order_id, reference, &e).await?;
+                tracing::warn!("Abandoned capture of payment {}: {}", payment.id, e);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+async fn reconcile_refunds(state: &Config) -> Result<(), AppError> {
+    let stale = sqlx::query_scalar::<_, Uuid>(
+        "SELECT r.id FROM refunds r
+         JOIN payments p ON p.id = r.payment_id
+         WHERE r.status = 'pending' AND p.provider = $1
+           AND r.updated_at < NOW() - make_interval(mins => $2)",
+    )
+    .bind(state.payment_provider.name())
+    .bind(STALE_MINUTES)
+    .fetch_all(&state.db_pool)
+    .await?;
+
+    for refund_id in stale {
+        // Refunds are idempotent, so one the provider already made is not
+        // made again
+        match complete_refund(state, refund_id).await {
+            Ok(refund) if refund.status == RefundStatus::Succeeded => {
+                tracing::info!("Reconciled refund {}", refund_id);
+            }
+            Ok(_) => {}
+            Err(e) => tracing::warn!("Failed to reconcile refund {}: {:?}", refund_id, e),
+        }
+    }
+
+    Ok(())
+}
+
+async fn fail_payment(
+    pool: &PgPool,
+    payment_id: Uuid,
+    order_id: Uuid,
+    status: PaymentStatus,
+    reason: &str,
+) -> Result<(), AppError> {
+    let mut tx = pool.begin().await?;
+
+    sqlx::query(
+        "UPDATE payments SET status = $2, failure_reason = $3, updated_at = NOW() WHERE id = $1",
+    )
+    .bind(payment_id)
+    .bind(status)
+    .bind(reason)
+    .execute(&mut *tx)
+    .await?;
+
+    transition_order(
+        &mut tx,
+        order_id,
+        OrderStatus::Cancelled,
+        None,
+        Some(reason),
+    )
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+async fn set_payment_status(
+    pool: &PgPool,
+    payment_id: Uuid,
+    status: PaymentStatus,
+    reference: Option<&str>,
+) -> Result<(), AppError> {
+    sqlx::query(
+        "UPDATE payments
+         SET status = $2, provider_reference = COALESCE($3, provider_reference), updated_at = NOW()
+         WHERE id = $1",
+    )
+    .bind(payment_id)
+    .bind(status)
+    .bind(reference)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
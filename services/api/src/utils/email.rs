@@ -0,0 +1,22 @@
+use crate::error::AppError;
+
+/// Trims and lowercases an address and rejects anything that is clearly not
+/// one. Real validation happens when the verification email arrives.
+pub fn normalize_email(email: &str) -> Result<String, AppError> {
+    let email = email.trim().to_lowercase();
+    let invalid = || AppError::BadRequest("Invalid email address".to_string());
+
+    let (local, domain) = email.split_once('@').ok_or_else(invalid)?;
+    if local.is_empty()
+        || domain.contains('@')
+        || !domain.contains('.')
+        || domain.starts_with('.')
+        || domain.ends_with('.')
+        || email.chars().any(char::is_whitespace)
+        || email.len() > 254
+    {
+        return Err(invalid());
+    }
+
+    Ok(email)
+}
@@ -1,10 +1,293 @@
+use std::env;
+
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, SaltString},
+    Algorithm, Argon2, Params, PasswordHasher, PasswordVerifier, Version,
+};
+
 use crate::error::AppError;
-use bcrypt::{hash, verify, DEFAULT_COST};
 
-pub fn hash_password(password: &str) -> Result<String, AppError> {
-    hash(password, DEFAULT_COST).map_err(|e| AppError::InternalServerError)
+/// Which algorithm new password hashes use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashAlgorithm {
+    Argon2id,
+    Bcrypt,
+}
+
+/// How passwords are hashed, read from the environment:
+///
+/// - `PASSWORD_HASH_ALGORITHM`: `argon2id` (default) or `bcrypt`
+/// - `ARGON2_MEMORY_KIB`, `ARGON2_ITERATIONS`, `ARGON2_PARALLELISM`:
+///   defaults follow the OWASP recommendation (19 MiB, 2, 1)
+/// - `BCRYPT_COST`: defaults to bcrypt's `DEFAULT_COST`
+///
+/// Stored hashes identify their own algorithm and parameters, so any hash
+/// keeps verifying after the policy changes; `needs_rehash` tells which
+/// ones to upgrade.
+#[derive(Debug, Clone, Copy)]
+pub struct PasswordPolicy {
+    pub algorithm: HashAlgorithm,
+    pub argon2_memory_kib: u32,
+    pub argon2_iterations: u32,
+    pub argon2_parallelism: u32,
+    pub bcrypt_cost: u32,
+}
+
+impl PasswordPolicy {
+    pub fn from_env() -> Result<Self, String> {
+        let algorithm = match env::var("PASSWORD_HASH_ALGORITHM").as_deref() {
+            Err(_) | Ok("argon2id") => HashAlgorithm::Argon2id,
+            Ok("bcrypt") => HashAlgorithm::Bcrypt,
+            Ok(other) => return Err(format!("Unknown PASSWORD_HASH_ALGORITHM: {}", other)),
+        };
+
+        let policy = Self {
+            algorithm,
+            argon2_memory_kib: env_u32("ARGON2_MEMORY_KIB", Params::DEFAULT_M_COST)?,
+            argon2_iterations: env_u32("ARGON2_ITERATIONS", Params::DEFAULT_T_COST)?,
+            argon2_parallelism: env_u32("ARGON2_PARALLELISM", Params::DEFAULT_P_COST)?,
+            bcrypt_cost: env_u32("BCRYPT_COST", bcrypt::DEFAULT_COST)?,
+        };
+
+        // Fail at startup rather than on the first signup
+        policy.argon2_params()?;
+        if !(4..=31).contains(&policy.bcrypt_cost) {
+            return Err("BCRYPT_COST must be between 4 and 31".to_string());
+        }
+
+        Ok(policy)
+    }
+
+    fn argon2_params(&self) -> Result<Params, String> {
+        Params::new(
+            self.argon2_memory_kib,
+            self.argon2_iterations,
+            self.argon2_parallelism,
+            None,
+        )
+        .map_err(|e| format!("Invalid Argon2 parameters: {}", e))
+    }
+
+    /// A hash of a throwaway password with the current policy. Checking
+    /// against it when a username does not exist makes that failure take as
+    /// long as a wrong password.
+    pub fn dummy_hash(&self) -> Result<String, String> {
+        self.hash_blocking("not-a-real-password")
+    }
+
+    fn hash_blocking(&self, password: &str) -> Result<String, String> {
+        match self.algorithm {
+            HashAlgorithm::Argon2id => {
+                let argon2 =
+                    Argon2::new(Algorithm::Argon2id, Version::V0x13, self.argon2_params()?);
+                let salt = SaltString::generate(&mut OsRng);
+                argon2
+                    .hash_password(password.as_bytes(), &salt)
+                    .map(|hash| hash.to_string())
+                    .map_err(|e| e.to_string())
+            }
+            HashAlgorithm::Bcrypt => {
+                bcrypt::hash(password, self.bcrypt_cost).map_err(|e| e.to_string())
+            }
+        }
+    }
+
+    /// Whether `hash` was made with a different algorithm or parameters than
+    /// the policy would use today.
+    pub fn needs_rehash(&self, hash: &str) -> bool {
+        match (self.algorithm, StoredHash::detect(hash)) {
+            (HashAlgorithm::Argon2id, Some(StoredHash::Argon2)) => {
+                let Ok(parsed) = PasswordHash::new(hash) else {
+                    return true;
+                };
+                let Ok(params) = Params::try_from(&parsed) else {
+                    return true;
+                };
+                parsed.algorithm != Algorithm::Argon2id.ident()
+                    || parsed.version != Some(Version::V0x13.into())
+                    || params.m_cost() != self.argon2_memory_kib
+                    || params.t_cost() != self.argon2_iterations
+                    || params.p_cost() != self.argon2_parallelism
+            }
+            (HashAlgorithm::Bcrypt, Some(StoredHash::Bcrypt)) => {
+                bcrypt_cost(hash) != Some(self.bcrypt_cost)
+            }
+            _ => true,
+        }
+    }
+}
+
+/// Algorithms recognised in stored hashes.
+enum StoredHash {
+    /// A PHC string such as `$argon2id$v=19$m=19456,t=2,p=1$...`
+    Argon2,
+    /// Modular crypt format such as `$2b$12$...`
+    Bcrypt,
+}
+
+impl StoredHash {
+    fn detect(hash: &str) -> Option<Self> {
+        if hash.starts_with("$argon2") {
+            Some(StoredHash::Argon2)
+        } else if ["$2a$", "$2b$", "$2x$", "$2y$"]
+            .iter()
+            .any(|prefix| hash.starts_with(prefix))
+        {
+            Some(StoredHash::Bcrypt)
+        } else {
+            None
+        }
+    }
+}
+
+/// Hashes a password on the blocking thread pool, since a good hash takes
+/// tens of milliseconds of CPU.
+pub async fn hash_password(policy: &PasswordPolicy, password: &str) -> Result<String, AppError> {
+    let policy = *policy;
+    let password = password.to_owned();
+
+    tokio::task::spawn_blocking(move || policy.hash_blocking(&password))
+        .await
+        .map_err(|e| {
+            tracing::error!("Password hashing task failed: {}", e);
+            AppError::InternalServerError
+        })?
+        .map_err(|e| {
+            tracing::error!("Password hashing failed: {}", e);
+            AppError::InternalServerError
+        })
+}
+
+/// Checks a password against a stored hash of any supported algorithm.
+pub async fn verify_password(password: &str, hash: &str) -> Result<bool, AppError> {
+    let password = password.to_owned();
+    let hash = hash.to_owned();
+
+    tokio::task::spawn_blocking(move || verify_blocking(&password, &hash))
+        .await
+        .map_err(|e| {
+            tracing::error!("Password verification task failed: {}", e);
+            AppError::InternalServerError
+        })?
+        .map_err(|e| {
+            tracing::error!("Password verification failed: {}", e);
+            AppError::InternalServerError
+        })
 }
 
-pub fn verify_password(password: &str, hash: &str) -> Result<bool, AppError> {
-    verify(password, hash).map_err(|e| AppError::InternalServerError)
+fn verify_blocking(password: &str, hash: &str) -> Result<bool, String> {
+    match StoredHash::detect(hash) {
+        Some(StoredHash::Argon2) => {
+            let parsed = PasswordHash::new(hash).map_err(|e| e.to_string())?;
+            // The hash carries its own variant and parameters
+            match Argon2::default().verify_password(password.as_bytes(), &parsed) {
+                Ok(()) => Ok(true),
+                Err(argon2::password_hash::Error::Password) => Ok(false),
+                Err(e) => Err(e.to_string()),
+            }
+        }
+        Some(StoredHash::Bcrypt) => bcrypt::verify(password, hash).map_err(|e| e.to_string()),
+        None => Err("Unrecognised password hash format".to_string()),
+    }
+}
+
+fn bcrypt_cost(hash: &str) -> Option<u32> {
+    hash.split('$').nth(2)?.parse().ok()
+}
+
+fn env_u32(name: &str, default: u32) -> Result<u32, String> {
+    match env::var(name) {
+        Ok(value) => value
+            .parse()
+            .map_err(|_| format!("{} must be a positive integer", name)),
+        Err(_) => Ok(default),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Cheap parameters, so the tests do not spend seconds hashing.
+    fn argon2_policy(memory_kib: u32, iterations: u32, parallelism: u32) -> PasswordPolicy {
+        PasswordPolicy {
+            algorithm: HashAlgorithm::Argon2id,
+            argon2_memory_kib: memory_kib,
+            argon2_iterations: iterations,
+            argon2_parallelism: parallelism,
+            bcrypt_cost: 4,
+        }
+    }
+
+    fn bcrypt_policy(cost: u32) -> PasswordPolicy {
+        PasswordPolicy {
+            algorithm: HashAlgorithm::Bcrypt,
+            bcrypt_cost: cost,
+            ..argon2_policy(1024, 1, 1)
+        }
+    }
+
+    #[test]
+    fn verifies_legacy_bcrypt_hashes() {
+        let hash = bcrypt::hash("correct horse", 4).unwrap();
+
+        assert!(verify_blocking("correct horse", &hash).unwrap());
+        assert!(!verify_blocking("wrong horse", &hash).unwrap());
+    }
+
+    #[test]
+    fn verifies_argon2_hashes_with_their_own_parameters() {
+        let hash = argon2_policy(1024, 1, 1)
+            .hash_blocking("correct horse")
+            .unwrap();
+
+        assert!(hash.starts_with("$argon2id$v=19$m=1024,t=1,p=1$"));
+        assert!(verify_blocking("correct horse", &hash).unwrap());
+        assert!(!verify_blocking("wrong horse", &hash).unwrap());
+    }
+
+    #[test]
+    fn rejects_unrecognised_hashes() {
+        assert!(verify_blocking("correct horse", "correct horse").is_err());
+        assert!(argon2_policy(1024, 1, 1).needs_rehash("correct horse"));
+    }
+
+    #[test]
+    fn bcrypt_hashes_need_rehashing_under_argon2() {
+        let hash = bcrypt::hash("correct horse", 4).unwrap();
+
+        assert!(argon2_policy(1024, 1, 1).needs_rehash(&hash));
+    }
+
+    #[test]
+    fn current_argon2_hashes_do_not_need_rehashing() {
+        let policy = argon2_policy(1024, 1, 1);
+        let hash = policy.hash_blocking("correct horse").unwrap();
+
+        assert!(!policy.needs_rehash(&hash));
+    }
+
+    #[test]
+    fn argon2_hashes_with_other_parameters_need_rehashing() {
+        let hash = argon2_policy(1024, 1, 1)
+            .hash_blocking("correct horse")
+            .unwrap();
+
+        assert!(argon2_policy(2048, 1, 1).needs_rehash(&hash));
+        assert!(argon2_policy(1024, 2, 1).needs_rehash(&hash));
+        assert!(argon2_policy(1024, 1, 2).needs_rehash(&hash));
+    }
+
+    #[test]
+    fn bcrypt_policy_rehashes_other_costs_and_algorithms() {
+        let hash = bcrypt::hash("correct horse", 4).unwrap();
+
+        assert!(!bcrypt_policy(4).needs_rehash(&hash));
+        assert!(bcrypt_policy(5).needs_rehash(&hash));
+
+        let argon2_hash = argon2_policy(1024, 1, 1)
+            .hash_blocking("correct horse")
+            .unwrap();
+        assert!(bcrypt_policy(4).needs_rehash(&argon2_hash));
+    }
 }
@@ -1,41 +1,198 @@
+use std::{collections::HashMap, env, fs, path::Path};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
 use chrono::{Duration, Utc};
-use jsonwebtoken::{encode, decode, Header, EncodingKey, DecodingKey, Validation};
+use jsonwebtoken::{
+    decode, decode_header, encode,
+    jwk::{
+        AlgorithmParameters, CommonParameters, EllipticCurve, Jwk, JwkSet, KeyAlgorithm,
+        OctetKeyPairParameters, OctetKeyPairType, PublicKeyUse,
+    },
+    Algorithm, DecodingKey, EncodingKey, Header, Validation,
+};
+use ring::{
+    rand::SystemRandom,
+    signature::{Ed25519KeyPair, KeyPair},
+};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
+
 use crate::error::AppError;
+use crate::model::Role;
+
+/// Access tokens are short-lived; clients renew them with a refresh token.
+pub const ACCESS_TOKEN_TTL_MINUTES: i64 = 15;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Claims {
     pub sub: Uuid,
+    /// The session the token was issued for, checked on every request so
+    /// logging out takes effect immediately.
+    pub sid: Uuid,
+    /// Roles at the time the token was issued; tokens from before roles
+    /// existed carry none.
+    #[serde(default)]
+    pub roles: Vec<Role>,
     pub exp: usize,
     pub iat: usize,
 }
 
-pub fn encode_jwt(user_id: Uuid, secret: &str) -> Result<String, AppError> {
+/// The Ed25519 keys tokens are signed and verified with.
+///
+/// Keys are PKCS#8 PEM files in `JWT_KEYS_DIR`, each named `<kid>.pem`.
+/// `JWT_ACTIVE_KID` picks the one new tokens are signed with; every other
+/// key in the directory still verifies tokens until its file is removed.
+/// To rotate, add a new key, point `JWT_ACTIVE_KID` at it, and delete the
+/// old file once the tokens it signed have expired.
+///
+/// Generate a key with `openssl genpkey -algorithm ed25519 -out <kid>.pem`.
+///
+/// For local development, `JWT_ALLOW_EPHEMERAL=1` signs with a key generated
+/// at startup instead; without either setting the service refuses to start.
+pub struct JwtKeys {
+    active_kid: String,
+    signing_key: EncodingKey,
+    verifying_keys: HashMap<String, DecodingKey>,
+    jwks: JwkSet,
+}
+
+impl JwtKeys {
+    pub fn from_env() -> Result<Self, String> {
+        let Ok(dir) = env::var("JWT_KEYS_DIR") else {
+            if env::var("JWT_ALLOW_EPHEMERAL").as_deref() != Ok("1") {
+                return Err(
+                    "JWT_KEYS_DIR must be set (or JWT_ALLOW_EPHEMERAL=1 for development)"
+                        .to_string(),
+                );
+            }
+            tracing::warn!(
+                "JWT_KEYS_DIR is not set; signing with a temporary key, \
+                 so tokens will not survive a restart"
+            );
+            return Self::ephemeral();
+        };
+        let active_kid = env::var("JWT_ACTIVE_KID")
+            .map_err(|_| "JWT_ACTIVE_KID must be set when JWT_KEYS_DIR is".to_string())?;
+
+        Self::load(Path::new(&dir), active_kid)
+    }
+
+    fn load(dir: &Path, active_kid: String) -> Result<Self, String> {
+        let entries =
+            fs::read_dir(dir).map_err(|e| format!("Cannot read {}: {}", dir.display(), e))?;
+
+        let mut keys = Vec::new();
+        for entry in entries {
+            let path = entry.map_err(|e| e.to_string())?.path();
+            if path.extension().and_then(|ext| ext.to_str()) != Some("pem") {
+                continue;
+            }
+            let Some(kid) = path.file_stem().and_then(|stem| stem.to_str()) else {
+                continue;
+            };
+
+            let contents =
+                fs::read(&path).map_err(|e| format!("Cannot read {}: {}", path.display(), e))?;
+            let pkcs8 = pem::parse(&contents)
+                .map_err(|e| format!("{} is not a PEM file: {}", path.display(), e))?;
+
+            keys.push((kid.to_string(), pkcs8.into_contents()));
+        }
+
+        Self::from_pkcs8_keys(keys, active_kid)
+    }
+
+    /// A key generated on the spot; tokens it signs die with the process.
+    pub fn ephemeral() -> Result<Self, String> {
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new())
+            .map_err(|_| "Failed to generate a signing key".to_string())?;
+        let kid = format!("ephemeral-{}", Uuid::new_v4().simple());
+
+        Self::from_pkcs8_keys(vec![(kid.clone(), pkcs8.as_ref().to_vec())], kid)
+    }
+
+    fn from_pkcs8_keys(keys: Vec<(String, Vec<u8>)>, active_kid: String) -> Result<Self, String> {
+        let mut signing_key = None;
+        let mut verifying_keys = HashMap::new();
+        let mut jwks = Vec::new();
+
+        for (kid, pkcs8) in keys {
+            let key_pair = Ed25519KeyPair::from_pkcs8_maybe_unchecked(&pkcs8)
+                .map_err(|_| format!("Key {} is not an Ed25519 PKCS#8 key", kid))?;
+            let x = URL_SAFE_NO_PAD.encode(key_pair.public_key().as_ref());
+
+            verifying_keys.insert(
+                kid.clone(),
+                DecodingKey::from_ed_components(&x).map_err(|e| e.to_string())?,
+            );
+            jwks.push(Jwk {
+                common: CommonParameters {
+                    public_key_use: Some(PublicKeyUse::Signature),
+                    key_algorithm: Some(KeyAlgorithm::EdDSA),
+                    key_id: Some(kid.clone()),
+                    ..Default::default()
+                },
+                algorithm: AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+                    key_type: OctetKeyPairType::OctetKeyPair,
+                    curve: EllipticCurve::Ed25519,
+                    x,
+                }),
+            });
+
+            if kid == active_kid {
+                signing_key = Some(EncodingKey::from_ed_der(&pkcs8));
+            }
+        }
+
+        let signing_key =
+            signing_key.ok_or_else(|| format!("No key found for JWT_ACTIVE_KID {}", active_kid))?;
+
+        Ok(Self {
+            active_kid,
+            signing_key,
+            verifying_keys,
+            jwks: JwkSet { keys: jwks },
+        })
+    }
+
+    /// The public half of every key that currently verifies tokens.
+    pub fn jwks(&self) -> &JwkSet {
+        &self.jwks
+    }
+}
+
+pub fn encode_jwt(
+    user_id: Uuid,
+    session_id: Uuid,
+    roles: &[Role],
+    keys: &JwtKeys,
+) -> Result<String, AppError> {
     let now = Utc::now();
-    let expire = now + Duration::hours(24);
-    
+    let expire = now + Duration::minutes(ACCESS_TOKEN_TTL_MINUTES);
+
     let claims = Claims {
         sub: user_id,
+        sid: session_id,
+        roles: roles.to_vec(),
         exp: expire.timestamp() as usize,
         iat: now.timestamp() as usize,
     };
 
-    encode(
-        &Header::default(),
-        &claims,
-        &EncodingKey::from_secret(secret.as_bytes()),
-    )
-    .map_err(|_| AppError::InternalServerError)
+    let mut header = Header::new(Algorithm::EdDSA);
+    header.kid = Some(keys.active_kid.clone());
+
+    encode(&header, &claims, &keys.signing_key).map_err(|_| AppError::InternalServerError)
 }
 
-pub fn decode_jwt(token: &str, secret: &str) -> Result<Claims, AppError> {
-    let token_data = decode::<Claims>(
-        token,
-        &DecodingKey::from_secret(secret.as_bytes()),
-        &Validation::default(),
-    )
-    .map_err(|_| AppError::Unauthorized)?;
+pub fn decode_jwt(token: &str, keys: &JwtKeys) -> Result<Claims, AppError> {
+    let header = decode_header(token).map_err(|_| AppError::Unauthorized)?;
+    let key = header
+        .kid
+        .and_then(|kid| keys.verifying_keys.get(&kid))
+        .ok_or(AppError::Unauthorized)?;
+
+    let token_data = decode::<Claims>(token, key, &Validation::new(Algorithm::EdDSA))
+        .map_err(|_| AppError::Unauthorized)?;
 
     Ok(token_data.claims)
 }
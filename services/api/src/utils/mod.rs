@@ -1,2 +1,6 @@
+pub mod email;
 pub mod hash;
 pub mod jwt;
+pub mod pagination;
+pub mod token;
+pub mod totp;
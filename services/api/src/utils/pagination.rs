@@ -0,0 +1,227 @@
+use crate::{
+    dtos::{Page, PageResponse},
+    error::AppError,
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, SecondsFormat, Utc};
+use uuid::Uuid;
+
+pub const DEFAULT_PAGE_SIZE: i64 = 10;
+pub const MAX_PAGE_SIZE: i64 = 100;
+
+/// Clamps a client-supplied page size to `1..=MAX_PAGE_SIZE`.
+pub fn page_size(limit: Option<i64>) -> i64 {
+    limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE)
+}
+
+/// Position of the last row of a page in `(created_at, id)` descending order.
+/// Clients only ever see it as an opaque string.
+#[derive(Debug, Clone, Copy)]
+pub struct Cursor {
+    pub created_at: DateTime<Utc>,
+    pub id: Uuid,
+}
+
+impl Cursor {
+    pub fn encode(&self) -> String {
+        let raw = format!(
+            "{}|{}",
+            self.created_at.to_rfc3339_opts(SecondsFormat::Micros, true),
+            self.id
+        );
+        URL_SAFE_NO_PAD.encode(raw)
+    }
+
+    pub fn decode(encoded: &str) -> Result<Self, AppError> {
+        let invalid = || AppError::BadRequest("Invalid cursor".to_string());
+
+        let bytes = URL_SAFE_NO_PAD.decode(encoded).map_err(|_| invalid())?;
+        let raw = String::from_utf8(bytes).map_err(|_| invalid())?;
+        let (created_at, id) = raw.split_once('|').ok_or_else(invalid)?;
+
+        Ok(Self {
+            created_at: DateTime::parse_from_rfc3339(created_at)
+                .map_err(|_| invalid())?
+                .with_timezone(&Utc),
+            id: id.parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+/// Where a listing starts: the legacy offset mode, or keyset mode from the
+/// top or after a cursor. Passing an empty `cursor` opts into keyset mode.
+#[derive(Debug, Clone, Copy)]
+pub enum PageStart {
+    Offset(i64),
+    First,
+    After(Cursor),
+}
+
+impl PageStart {
+    pub fn from_request(cursor: Option<&str>, offset: Option<i64>) -> Result<Self, AppError> {
+        match (cursor, offset) {
+            (Some(_), Some(_)) => Err(AppError::BadRequest(
+                "Use either cursor or offset, not both".to_string(),
+            )),
+            (Some(""), None) => Ok(PageStart::First),
+            (Some(cursor), None) => Ok(PageStart::After(Cursor::decode(cursor)?)),
+            (None, offset) => Ok(PageStart::Offset(offset.unwrap_or(0).max(0))),
+        }
+    }
+
+    /// Offset requests get the bare array they always have; keyset requests
+    /// get the items along with the cursor for the next page.
+    pub fn page<T>(self, items: Vec<T>, next_cursor: Option<String>) -> Page<T> {
+        match self {
+            PageStart::Offset(_) => Page::Offset(items),
+            PageStart::First | PageStart::After(_) => {
+                Page::Keyset(PageResponse { items, next_cursor })
+            }
+        }
+    }
+}
+
+/// Listing queries fetch one row more than `limit` to learn whether another
+/// page exists. This drops that look-ahead row and returns the cursor for the
+/// next page, if any.
+pub fn finish_page<T>(
+    mut rows: Vec<T>,
+    limit: i64,
+    cursor_of: impl Fn(&T) -> Cursor,
+) -> (Vec<T>, Option<String>) {
+    let has_more = rows.len() as i64 > limit;
+    rows.truncate(limit as usize);
+
+    let next_cursor = if has_more {
+        rows.last().map(|row| cursor_of(row).encode())
+    } else {
+        None
+    };
+
+    (rows, next_cursor)
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn cursor(micros: i64) -> Cursor {
+        Cursor {
+            created_at: Utc.timestamp_micros(micros).unwrap(),
+            id: Uuid::from_u128(micros as u128),
+        }
+    }
+
+    fn is_bad_request<T>(result: Result<T, AppError>, expected: &str) -> bool {
+        matches!(result, Err(AppError::BadRequest(message)) if message == expected)
+    }
+
+    #[test]
+    fn cursors_round_trip_to_the_microsecond() {
+        let original = cursor(1_792_000_000_123_456);
+
+        let encoded = original.encode();
+        let decoded = Cursor::decode(&encoded).unwrap();
+
+        assert!(!encoded.contains('|'));
+        assert_eq!(decoded.created_at, original.created_at);
+        assert_eq!(decoded.id, original.id);
+    }
+
+    #[test]
+    fn rejects_garbage_cursors() {
+        let encode = |raw: &str| URL_SAFE_NO_PAD.encode(raw);
+
+        for garbage in [
+            "not base64!".to_string(),
+            URL_SAFE_NO_PAD.encode([0xff, 0xfe]),
+            encode("no separator"),
+            encode("yesterday|00000000-0000-0000-0000-000000000001"),
+            encode("2026-10-18T12:00:00.000000Z|not-a-uuid"),
+        ] {
+            assert!(
+                is_bad_request(Cursor::decode(&garbage), "Invalid cursor"),
+                "{}",
+                garbage
+            );
+        }
+    }
+
+    #[test]
+    fn page_start_follows_the_request() {
+        assert!(matches!(
+            PageStart::from_request(None, None),
+            Ok(PageStart::Offset(0))
+        ));
+        assert!(matches!(
+            PageStart::from_request(None, Some(30)),
+            Ok(PageStart::Offset(30))
+        ));
+        assert!(matches!(
+            PageStart::from_request(None, Some(-5)),
+            Ok(PageStart::Offset(0))
+        ));
+        assert!(matches!(
+            PageStart::from_request(Some(""), None),
+            Ok(PageStart::First)
+        ));
+
+        let after = cursor(1_792_000_000_000_000);
+        match PageStart::from_request(Some(&after.encode()), None) {
+            Ok(PageStart::After(decoded)) => assert_eq!(decoded.id, after.id),
+            other => panic!("expected a cursor, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn page_start_rejects_cursor_with_offset_and_garbage_cursors() {
+        assert!(is_bad_request(
+            PageStart::from_request(Some(""), Some(0)),
+            "Use either cursor or offset, not both"
+        ));
+        assert!(is_bad_request(
+            PageStart::from_request(Some("garbage"), None),
+            "Invalid cursor"
+        ));
+    }
+
+    #[test]
+    fn finish_page_drops_the_look_ahead_row() {
+        let rows: Vec<i64> = (1..=4).collect();
+
+        let (items, next_cursor) = finish_page(rows, 3, |&row| cursor(row));
+
+        assert_eq!(items, [1, 2, 3]);
+        let next = Cursor::decode(&next_cursor.unwrap()).unwrap();
+        assert_eq!(next.id, cursor(3).id);
+    }
+
+    #[test]
+    fn finish_page_has_no_next_cursor_on_the_last_page() {
+        for rows in [vec![1, 2, 3], vec![1], vec![]] {
+            let expected = rows.clone();
+
+            let (items, next_cursor) = finish_page(rows, 3, |&row| cursor(row));
+
+            assert_eq!(items, expected);
+            assert!(next_cursor.is_none());
+        }
+    }
+
+    #[test]
+    fn offset_pages_stay_bare_and_keyset_pages_carry_the_cursor() {
+        assert!(matches!(
+            PageStart::Offset(0).page(vec![1], Some("next".to_string())),
+            Page::Offset(items) if items == [1]
+        ));
+        match PageStart::First.page(vec![1], Some("next".to_string())) {
+            Page::Keyset(PageResponse { items, next_cursor }) => {
+                assert_eq!(items, [1]);
+                assert_eq!(next_cursor.as_deref(), Some("next"));
+            }
+            other => panic!("expected a keyset page, got {:?}", other),
+        }
+    }
+}
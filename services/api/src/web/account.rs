@@ -0,0 +1,458 @@
+use std::{sync::Arc, time::Duration as StdDuration};
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Extension, Json,
+};
+use chrono::{Duration, Utc};
+use sqlx::{PgConnection, PgPool};
+
+use crate::{
+    config::Config,
+    dtos::{
+        ChangePasswordRequest, DeleteAccountRequest, DeleteAccountResponse, MeResponse,
+        SellerProfileResponse, UpdateMeRequest,
+    },
+    error::AppError,
+    model::User,
+    utils::{
+        email::normalize_email,
+        hash::{hash_password, verify_password},
+    },
+    web::{auth::revoke_user_sessions, mw::AuthUser, verification::start_email_verification},
+};
+use uuid::Uuid;
+
+/// How long a deleted account can still be recovered by logging in.
+pub const DELETION_GRACE_DAYS: i64 = 30;
+const PURGE_INTERVAL: StdDuration = StdDuration::from_secs(60 * 60);
+const MAX_DISPLAY_NAME_CHARS: usize = 64;
+const MAX_AVATAR_URL_LEN: usize = 2048;
+const MAX_LOCALE_LEN: usize = 35;
+
+pub async fn get_me(
+    State(state): State<Arc<Config>>,
+    Extension(user): Extension<AuthUser>,
+) -> Result<Json<MeResponse>, AppError> {
+    let user = find_user(&state.db_pool, user.id).await?;
+
+    Ok(Json(to_me_response(user)))
+}
+
+/// A new email needs the current password and only replaces the old one
+/// once verified, so a stolen session cannot redirect password resets.
+/// Asking for the current address again cancels a pending change.
+pub async fn update_me(
+    State(state): State<Arc<Config>>,
+    Extension(user): Extension<AuthUser>,
+    Json(payload): Json<UpdateMeRequest>,
+) -> Result<Json<MeResponse>, AppError> {
+    let current = find_user(&state.db_pool, user.id).await?;
+
+    let display_name = match payload.display_name {
+        Some(Some(name)) => {
+            let name = name.trim().to_string();
+            if name.is_empty() || name.chars().count() > MAX_DISPLAY_NAME_CHARS {
+                return Err(AppError::BadRequest(format!(
+                    "Display name must be 1 to {} characters",
+                    MAX_DISPLAY_NAME_CHARS
+                )));
+            }
+            Some(name)
+        }
+        Some(None) => None,
+        None => current.display_name,
+    };
+
+    let avatar_url = match payload.avatar_url {
+        Some(Some(url)) => {
+            let url = url.trim().to_string();
+            if !(url.starts_with("https://") || url.starts_with("http://"))
+                || url.len() > MAX_AVATAR_URL_LEN
+            {
+                return Err(AppError::BadRequest(
+                    "Avatar must be an http(s) URL".to_string(),
+                ));
+            }
+            Some(url)
+        }
+        Some(None) => None,
+        None => current.avatar_url,
+    };
+
+    let locale = match payload.locale {
+        Some(Some(locale)) => Some(parse_locale(&locale)?),
+        Some(None) => None,
+        None => current.locale,
+    };
+
+    let email = payload.email.as_deref().map(normalize_email).transpose()?;
+    let pending_email = match email {
+        Some(email) if Some(&email) == current.email.as_ref() => None,
+        Some(email) => {
+            let password = payload.current_password.as_deref().ok_or_else(|| {
+                AppError::BadRequest("Current password is required to change email".to_string())
+            })?;
+            if !verify_password(password, &current.password_hash).await? {
+                return Err(AppError::BadRequest(
+                    "Current password is incorrect".to_string(),
+                ));
+            }
+            Some(email)
+        }
+        None => current.pending_email.clone(),
+    };
+    let email_changed = pending_email.is_some() && pending_email != current.pending_email;
+
+    if let Some(email) = pending_email.as_ref().filter(|_| email_changed) {
+        let taken = sqlx::query_scalar::<_, bool>(
+            "SELECT EXISTS (SELECT 1 FROM users WHERE email = $1 AND id <> $2)",
+        )
+        .bind(email)
+        .bind(user.id)
+        .fetch_one(&state.db_pool)
+        .await?;
+        if taken {
+            return Err(AppError::BadRequest("Email already in use".to_string()));
+        }
+    }
+
+    let updated = sqlx::query_as::<_, User>(
+        "UPDATE users
+         SET display_name = $2,
+             avatar_url = $3,
+             locale = $4,
+             pending_email = $5
+         WHERE id = $1
+         RETURNING *",
+    )
+    .bind(user.id)
+    .bind(&display_name)
+    .bind(&avatar_url)
+    .bind(&locale)
+    .bind(&pending_email)
+    .fetch_one(&state.db_pool)
+    .await?;
+
+    if email_changed {
+        start_email_verification(&state.db_pool, &updated).await?;
+    }
+
+    Ok(Json(to_me_response(updated)))
+}
+
+/// Signs out every other session; the one making the change stays.
+pub async fn change_password(
+    State(state): State<Arc<Config>>,
+    Extension(user): Extension<AuthUser>,
+    Json(payload): Json<ChangePasswordRequest>,
+) -> Result<StatusCode, AppError> {
+    let current = find_user(&state.db_pool, user.id).await?;
+
+    if !verify_password(&payload.current_password, &current.password_hash).await? {
+        return Err(AppError::BadRequest(
+            "Current password is incorrect".to_string(),
+        ));
+    }
+
+    let password_hash = hash_password(&state.password_policy, &payload.new_password).await?;
+
+    let mut tx = state.db_pool.begin().await?;
+
+    sqlx::query("UPDATE users SET password_hash = $2 WHERE id = $1")
+        .bind(user.id)
+        .bind(&password_hash)
+        .execute(&mut *tx)
+        .await?;
+
+    sqlx::query(
+        "UPDATE sessions SET revoked_at = NOW()
+         WHERE user_id = $1 AND revoked_at IS NULL AND id IS DISTINCT FROM $2",
+    )
+    .bind(user.id)
+    .bind(user.session_id)
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Schedules the account for erasure after the grace period and signs it
+/// out everywhere. Logging in again before then cancels the deletion.
+pub async fn delete_me(
+    State(state): State<Arc<Config>>,
+    Extension(user): Extension<AuthUser>,
+    Json(payload): Json<DeleteAccountRequest>,
+) -> Result<(StatusCode, Json<DeleteAccountResponse>), AppError> {
+    let current = find_user(&state.db_pool, user.id).await?;
+
+    if !verify_password(&payload.password, &current.password_hash).await? {
+        return Err(AppError::BadRequest("Password is incorrect".to_string()));
+    }
+
+    let deletion_scheduled_at = Utc::now() + Duration::days(DELETION_GRACE_DAYS);
+
+    let mut tx = state.db_pool.begin().await?;
+
+    sqlx::query("UPDATE users SET deletion_scheduled_at = $2 WHERE id = $1")
+        .bind(user.id)
+        .bind(deletion_scheduled_at)
+        .execute(&mut *tx)
+        .await?;
+
+    sqlx::query("UPDATE api_keys SET revoked_at = NOW() WHERE user_id = $1 AND revoked_at IS NULL")
+        .bind(user.id)
+        .execute(&mut *tx)
+        .await?;
+
+    revoke_user_sessions(&mut tx, user.id).await?;
+
+    tx.commit().await?;
+
+    Ok((
+        StatusCode::ACCEPTED,
+        Json(DeleteAccountResponse {
+            deletion_scheduled_at,
+        }),
+    ))
+}
+
+pub async fn get_seller_profile(
+    State(state): State<Arc<Config>>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<SellerProfileResponse>, AppError> {
+    let seller = sqlx::query_as::<_, User>(
+        "SELECT * FROM users WHERE id = $1 AND 'seller' = ANY(roles) AND deleted_at IS NULL",
+    )
+    .bind(id)
+    .fetch_optional(&state.db_pool)
+    .await?
+    .ok_or(AppError::BadRequest("Seller not found".to_string()))?;
+
+    let product_count = sqlx::query_scalar::<_, i64>(
+        "SELECT COUNT(*) FROM products WHERE user_id = $1 AND deleted_at IS NULL",
+    )
+    .bind(id)
+    .fetch_one(&state.db_pool)
+    .await?;
+
+    let follower_count = sqlx::query_scalar::<_, i64>(
+        "SELECT COUNT(*) FROM seller_follows f
+         JOIN users u ON u.id = f.follower_id
+         WHERE f.seller_id = $1 AND u.deleted_at IS NULL",
+    )
+    .bind(id)
+    .fetch_one(&state.db_pool)
+    .await?;
+
+    Ok(Json(SellerProfileResponse {
+        id: seller.id,
+        username: seller.username,
+        display_name: seller.display_name,
+        avatar_url: seller.avatar_url,
+        product_count,
+        follower_count,
+        member_since: seller.created_at,
+    }))
+}
+
+/// Erases accounts whose deletion grace period has run out, and retries
+/// deleting the notifications of erased accounts until it succeeds.
+pub async fn run_account_purger(state: Arc<Config>) {
+    let mut interval = tokio::time::interval(PURGE_INTERVAL);
+    loop {
+        interval.tick().await;
+        if let Err(e) = purge_due_accounts(&state.db_pool).await {
+            tracing::error!("Failed to purge deleted accounts: {:?}", e);
+        }
+        if let Err(e) = retry_notification_erasures(&state.db_pool).await {
+            tracing::error!("Failed to retry notification erasures: {:?}", e);
+        }
+    }
+}
+
+async fn purge_due_accounts(pool: &PgPool) -> Result<(), AppError> {
+    let due = sqlx::query_scalar::<_, Uuid>(
+        "SELECT id FROM users WHERE deletion_scheduled_at <= NOW() AND deleted_at IS NULL",
+    )
+    .fetch_all(pool)
+    .await?;
+
+    for user_id in due {
+        let mut tx = pool.begin().await?;
+        erase_user(&mut tx, user_id).await?;
+        tx.commit().await?;
+        tracing::info!("Erased account {}", user_id);
+
+        forget_notifications(pool, user_id).await;
+    }
+
+    Ok(())
+}
+
+async fn retry_notification_erasures(pool: &PgPool) -> Result<(), AppError> {
+    let pending = sqlx::query_scalar::<_, Uuid>(
+        "SELECT user_id FROM notification_erasures ORDER BY updated_at",
+    )
+    .fetch_all(pool)
+    .await?;
+
+    for user_id in pending {
+        forget_notifications(pool, user_id).await;
+    }
+
+    Ok(())
+}
+
+/// Strips an account of everything that identifies its owner. The row
+/// itself stays so orders and refunds keep pointing at a (now anonymous)
+/// buyer or seller; listings are retired and everything else is deleted.
+/// The user's notifications are queued for [`forget_notifications`].
+pub async fn erase_user(conn: &mut PgConnection, user_id: Uuid) -> Result<(), AppError> {
+    // Throttled attempts and ones before the account was known carry only
+    // the username, which is about to be replaced
+    sqlx::query(
+        "DELETE FROM login_attempts
+         WHERE user_id = $1 OR username = (SELECT username FROM users WHERE id = $1)",
+    )
+    .bind(user_id)
+    .execute(&mut *conn)
+    .await?;
+
+    sqlx::query(
+        "UPDATE users
+         SET username = 'deleted-' || id,
+             password_hash = '!',
+             roles = '{}',
+             email = NULL,
+             email_verified_at = NULL,
+             pending_email = NULL,
+             display_name = NULL,
+             avatar_url = NULL,
+             locale = NULL,
+             totp_secret = NULL,
+             totp_enabled_at = NULL,
+             totp_last_step = NULL,
+             deletion_scheduled_at = NULL,
+             deleted_at = NOW()
+         WHERE id = $1",
+    )
+    .bind(user_id)
+    .execute(&mut *conn)
+    .await?;
+
+    sqlx::query("UPDATE products SET deleted_at = NOW() WHERE user_id = $1 AND deleted_at IS NULL")
+        .bind(user_id)
+        .execute(&mut *conn)
+        .await?;
+
+    for statement in [
+        "DELETE FROM cart_items WHERE product_id IN (SELECT id FROM products WHERE user_id = $1)",
+        "DELETE FROM carts WHERE user_id = $1",
+        "DELETE FROM posts WHERE user_id = $1",
+        "DELETE FROM seller_follows WHERE follower_id = $1 OR seller_id = $1",
+        "DELETE FROM sessions WHERE user_id = $1",
+        "DELETE FROM api_keys WHERE user_id = $1",
+        "DELETE FROM recovery_codes WHERE user_id = $1",
+        "DELETE FROM mfa_challenges WHERE user_id = $1",
+        "DELETE FROM password_reset_tokens WHERE user_id = $1",
+        "DELETE FROM email_verification_tokens WHERE user_id = $1",
+        "INSERT INTO notification_erasures (user_id) VALUES ($1) ON CONFLICT DO NOTHING",
+    ] {
+        sqlx::query(statement)
+            .bind(user_id)
+            .execute(&mut *conn)
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Deletes an erased user's notifications. They live in another service, so
+/// this happens after the erasure commits; until the service confirms, the
+/// user stays in `notification_erasures` for the account purger to retry.
+pub async fn forget_notifications(pool: &PgPool, user_id: Uuid) {
+    let deleted = crate::grpc_client::delete_user_notifications(&user_id.to_string())
+        .await
+        .map_err(|e| e.to_string());
+
+    let recorded = match deleted {
+        Ok(()) => {
+            sqlx::query("DELETE FROM notification_erasures WHERE user_id = $1")
+                .bind(user_id)
+                .execute(pool)
+                .await
+        }
+        Err(e) => {
+            tracing::warn!("Failed to delete notifications of user {}: {}", user_id, e);
+            sqlx::query(
+                "UPDATE notification_erasures
+                 SET attempts = attempts + 1, last_error = $2, updated_at = NOW()
+                 WHERE user_id = $1",
+            )
+            .bind(user_id)
+            .bind(e)
+            .execute(pool)
+            .await
+        }
+    };
+
+    if let Err(e) = recorded {
+        tracing::error!(
+            "Failed to record notification erasure of user {}: {}",
+            user_id,
+            e
+        );
+    }
+}
+
+async fn find_user(pool: &PgPool, user_id: Uuid) -> Result<User, AppError> {
+    sqlx::query_as::<_, User>("SELECT * FROM users WHERE id = $1")
+        .bind(user_id)
+        .fetch_one(pool)
+        .await
+        .map_err(AppError::from)
+}
+
+/// Accepts language tags shaped like `en`, `pt-BR` or `zh-Hant-TW`; whether
+/// there are templates for one is up to the notification service, which
+/// falls back to its default.
+fn parse_locale(locale: &str) -> Result<String, AppError> {
+    let locale = locale.trim().replace('_', "-");
+    let mut subtags = locale.split('-');
+    let language = subtags.next().unwrap_or_default();
+
+    let valid = locale.len() <= MAX_LOCALE_LEN
+        && (2..=3).contains(&language.len())
+        && language.chars().all(|c| c.is_ascii_alphabetic())
+        && subtags.all(|subtag| {
+            (1..=8).contains(&subtag.len()) && subtag.chars().all(|c| c.is_ascii_alphanumeric())
+        });
+    if !valid {
+        return Err(AppError::BadRequest(
+            "Locale must be a language tag such as en or pt-BR".to_string(),
+        ));
+    }
+
+    Ok(locale)
+}
+
+fn to_me_response(user: User) -> MeResponse {
+    MeResponse {
+        id: user.id,
+        username: user.username,
+        display_name: user.display_name,
+        email: user.email,
+        email_verified: user.email_verified_at.is_some(),
+        pending_email: user.pending_email,
+        avatar_url: user.avatar_url,
+        locale: user.locale,
+        roles: user.roles,
+        two_factor_enabled: user.totp_enabled_at.is_some(),
+        deletion_scheduled_at: user.deletion_scheduled_at,
+        created_at: user.created_at,
+    }
+}
@@ -0,0 +1,192 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::Response,
+    Extension, Json,
+};
+
+use crate::{
+    config::Config,
+    dtos::{GrantRoleRequest, UserRolesResponse},
+    error::AppError,
+    model::{Role, User},
+    web::{
+        account::{erase_user, forget_notifications},
+        mw::AuthUser,
+        privacy::{collect_user_data, export_response},
+    },
+};
+use uuid::Uuid;
+
+pub async fn get_user_roles(
+    State(state): State<Arc<Config>>,
+    Path(user_id): Path<Uuid>,
+) -> Result<Json<UserRolesResponse>, AppError> {
+    let user = sqlx::query_as::<_, User>("SELECT * FROM users WHERE id = $1")
+        .bind(user_id)
+        .fetch_optional(&state.db_pool)
+        .await?
+        .ok_or(AppError::BadRequest("User not found".to_string()))?;
+
+    Ok(Json(to_user_roles_response(user)))
+}
+
+/// Granting a role the user already holds is a no-op.
+pub async fn grant_role(
+    State(state): State<Arc<Config>>,
+    Path(user_id): Path<Uuid>,
+    Json(payload): Json<GrantRoleRequest>,
+) -> Result<Json<UserRolesResponse>, AppError> {
+    let user = sqlx::query_as::<_, User>(
+        "UPDATE users
+         SET roles = CASE WHEN $2 = ANY(roles) THEN roles ELSE array_append(roles, $2) END
+         WHERE id = $1
+         RETURNING *",
+    )
+    .bind(user_id)
+    .bind(payload.role)
+    .fetch_optional(&state.db_pool)
+    .await?
+    .ok_or(AppError::BadRequest("User not found".to_string()))?;
+
+    tracing::info!("Granted role {} to user {}", payload.role, user_id);
+
+    Ok(Json(to_user_roles_response(user)))
+}
+
+/// Role changes apply to tokens issued afterwards; existing tokens keep the
+/// roles they were issued with until they expire.
+pub async fn revoke_role(
+    State(state): State<Arc<Config>>,
+    Extension(admin): Extension<AuthUser>,
+    Path((user_id, role)): Path<(Uuid, Role)>,
+) -> Result<Json<UserRolesResponse>, AppError> {
+    // Keeps at least one admin able to manage roles
+    if user_id == admin.id && role == Role::Admin {
+        return Err(AppError::BadRequest(
+            "Admins cannot revoke their own admin role".to_string(),
+        ));
+    }
+
+    let user = sqlx::query_as::<_, User>(
+        "UPDATE users SET roles = array_remove(roles, $2) WHERE id = $1 RETURNING *",
+    )
+    .bind(user_id)
+    .bind(role)
+    .fetch_optional(&state.db_pool)
+    .await?
+    .ok_or(AppError::BadRequest("User not found".to_string()))?;
+
+    tracing::info!("Revoked role {} from user {}", role, user_id);
+
+    Ok(Json(to_user_roles_response(user)))
+}
+
+/// Answers a data access request on a user's behalf.
+pub async fn export_user_data(
+    State(state): State<Arc<Config>>,
+    Path(user_id): Path<Uuid>,
+) -> Result<Response, AppError> {
+    let export = collect_user_data(&state.db_pool, user_id).await?;
+
+    tracing::info!("Exported data for user {}", user_id);
+
+    Ok(export_response(export))
+}
+
+/// Answers an erasure request immediately, skipping the grace period a
+/// self-service deletion gets. Orders stay, attached to the anonymised user.
+pub async fn erase_user_account(
+    State(state): State<Arc<Config>>,
+    Extension(admin): Extension<AuthUser>,
+    Path(user_id): Path<Uuid>,
+) -> Result<StatusCode, AppError> {
+    if user_id == admin.id {
+        return Err(AppError::BadRequest(
+            "Admins cannot erase their own account".to_string(),
+        ));
+    }
+
+    erase_now(&state, user_id).await?;
+
+    tracing::info!("Erased user {} at the request of {}", user_id, admin.id);
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Runs `--export-user <username or id>`, which writes the user's data
+/// export to a JSON file in the working directory, or `--erase-user
+/// <username or id>`, for data requests answered from a shell. Returns
+/// whether a command was given.
+pub async fn run_command(state: &Config, args: &[String]) -> Result<bool, AppError> {
+    let target = |flag: &str| {
+        let index = args.iter().position(|arg| arg == flag)?;
+        Some(args.get(index + 1).cloned().unwrap_or_default())
+    };
+
+    if let Some(user) = target("--export-user") {
+        let user_id = resolve_user(state, &user).await?;
+        let export = collect_user_data(&state.db_pool, user_id).await?;
+        let path = format!(
+            "user-{}-{}.json",
+            user_id,
+            export.exported_at.format("%Y%m%d%H%M%S")
+        );
+        let json = serde_json::to_vec_pretty(&export).map_err(|_| AppError::InternalServerError)?;
+        std::fs::write(&path, json).map_err(|e| {
+            tracing::error!("Failed to write {}: {}", path, e);
+            AppError::InternalServerError
+        })?;
+        println!("Exported user {} to {}", user_id, path);
+        return Ok(true);
+    }
+
+    if let Some(user) = target("--erase-user") {
+        let user_id = resolve_user(state, &user).await?;
+        erase_now(state, user_id).await?;
+        tracing::info!("Erased user {} from the command line", user_id);
+        return Ok(true);
+    }
+
+    Ok(false)
+}
+
+async fn resolve_user(state: &Config, user: &str) -> Result<Uuid, AppError> {
+    sqlx::query_scalar::<_, Uuid>("SELECT id FROM users WHERE id::text = $1 OR username = $1")
+        .bind(user)
+        .fetch_optional(&state.db_pool)
+        .await?
+        .ok_or(AppError::BadRequest("User not found".to_string()))
+}
+
+async fn erase_now(state: &Config, user_id: Uuid) -> Result<(), AppError> {
+    let mut tx = state.db_pool.begin().await?;
+
+    let user = sqlx::query_as::<_, User>("SELECT * FROM users WHERE id = $1 FOR UPDATE")
+        .bind(user_id)
+        .fetch_optional(&mut *tx)
+        .await?
+        .ok_or(AppError::BadRequest("User not found".to_string()))?;
+
+    if user.deleted_at.is_some() {
+        return Err(AppError::BadRequest("User is already erased".to_string()));
+    }
+
+    erase_user(&mut tx, user_id).await?;
+
+    tx.commit().await?;
+
+    forget_notifications(&state.db_pool, user_id).await;
+
+    Ok(())
+}
+
+fn to_user_roles_response(user: User) -> UserRolesResponse {
+    UserRolesResponse {
+        user_id: user.id,
+        username: user.username,
+        roles: user.roles,
+    }
+}
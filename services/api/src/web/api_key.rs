@@ -0,0 +1,171 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Extension, Json,
+};
+use chrono::Utc;
+use rand::{rngs::OsRng, RngCore};
+use sqlx::PgPool;
+
+use crate::{
+    config::Config,
+    dtos::{ApiKeyResponse, CreateApiKeyRequest, CreatedApiKeyResponse},
+    error::AppError,
+    model::{ApiKey, Role},
+    utils::token::{generate_token, hash_token},
+    web::mw::AuthUser,
+};
+use uuid::Uuid;
+
+/// Every key starts with this, which makes leaked keys easy to scan for.
+pub const KEY_PREFIX: &str = "rk_";
+const PREFIX_BYTES: usize = 6;
+
+/// Issues a key that acts as the caller, limited to the given scopes. The
+/// full key is returned once; afterwards only its prefix is known.
+pub async fn create_api_key(
+    State(state): State<Arc<Config>>,
+    Extension(user): Extension<AuthUser>,
+    Json(payload): Json<CreateApiKeyRequest>,
+) -> Result<Json<CreatedApiKeyResponse>, AppError> {
+    if payload.name.trim().is_empty() {
+        return Err(AppError::BadRequest("Name is required".to_string()));
+    }
+    if payload.scopes.is_empty() {
+        return Err(AppError::BadRequest(
+            "At least one scope is required".to_string(),
+        ));
+    }
+    if payload
+        .expires_at
+        .is_some_and(|expires_at| expires_at <= Utc::now())
+    {
+        return Err(AppError::BadRequest(
+            "Expiry must be in the future".to_string(),
+        ));
+    }
+
+    let mut prefix_bytes = [0u8; PREFIX_BYTES];
+    OsRng.fill_bytes(&mut prefix_bytes);
+    let prefix = hex::encode(prefix_bytes);
+    let secret = generate_token();
+
+    let mut scopes = payload.scopes;
+    scopes.sort_by_key(|scope| *scope as u8);
+    scopes.dedup();
+
+    let api_key = sqlx::query_as::<_, ApiKey>(
+        "INSERT INTO api_keys (user_id, name, prefix, secret_hash, scopes, expires_at)
+         VALUES ($1, $2, $3, $4, $5, $6)
+         RETURNING *",
+    )
+    .bind(user.id)
+    .bind(payload.name.trim())
+    .bind(&prefix)
+    .bind(hash_token(&secret))
+    .bind(&scopes)
+    .bind(payload.expires_at)
+    .fetch_one(&state.db_pool)
+    .await?;
+
+    Ok(Json(CreatedApiKeyResponse {
+        key: format!("{}{}_{}", KEY_PREFIX, prefix, secret),
+        api_key: to_api_key_response(api_key),
+    }))
+}
+
+pub async fn get_api_keys(
+    State(state): State<Arc<Config>>,
+    Extension(user): Extension<AuthUser>,
+) -> Result<Json<Vec<ApiKeyResponse>>, AppError> {
+    let api_keys = sqlx::query_as::<_, ApiKey>(
+        "SELECT * FROM api_keys WHERE user_id = $1 ORDER BY created_at DESC",
+    )
+    .bind(user.id)
+    .fetch_all(&state.db_pool)
+    .await?;
+
+    Ok(Json(
+        api_keys.into_iter().map(to_api_key_response).collect(),
+    ))
+}
+
+/// Revoked keys stop working immediately but stay listed for auditing.
+pub async fn revoke_api_key(
+    State(state): State<Arc<Config>>,
+    Extension(user): Extension<AuthUser>,
+    Path(id): Path<Uuid>,
+) -> Result<StatusCode, AppError> {
+    let result = sqlx::query(
+        "UPDATE api_keys SET revoked_at = COALESCE(revoked_at, NOW())
+         WHERE id = $1 AND user_id = $2",
+    )
+    .bind(id)
+    .bind(user.id)
+    .execute(&state.db_pool)
+    .await?;
+
+    if result.rows_affected() == 0 {
+        return Err(AppError::BadRequest("API key not found".to_string()));
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Resolves a presented key (`rk_<prefix>_<secret>`) to the user it acts as.
+pub async fn authenticate_api_key(pool: &PgPool, key: &str) -> Result<AuthUser, AppError> {
+    let (prefix, secret) = key
+        .strip_prefix(KEY_PREFIX)
+        .and_then(|rest| rest.split_once('_'))
+        .ok_or(AppError::Unauthorized)?;
+
+    let api_key = sqlx::query_as::<_, ApiKey>(
+        "SELECT * FROM api_keys
+         WHERE prefix = $1
+           AND secret_hash = $2
+           AND revoked_at IS NULL
+           AND (expires_at IS NULL OR expires_at > NOW())",
+    )
+    .bind(prefix)
+    .bind(hash_token(secret))
+    .fetch_optional(pool)
+    .await?
+    .ok_or(AppError::Unauthorized)?;
+
+    // Current roles, so revoking a role also limits the user's keys
+    let roles = sqlx::query_scalar::<_, Vec<Role>>("SELECT roles FROM users WHERE id = $1")
+        .bind(api_key.user_id)
+        .fetch_one(pool)
+        .await?;
+
+    // Coarse on purpose: at most one write per key per minute
+    sqlx::query(
+        "UPDATE api_keys SET last_used_at = NOW()
+         WHERE id = $1 AND (last_used_at IS NULL OR last_used_at < NOW() - INTERVAL '1 minute')",
+    )
+    .bind(api_key.id)
+    .execute(pool)
+    .await?;
+
+    Ok(AuthUser {
+        id: api_key.user_id,
+        roles,
+        session_id: None,
+        api_key_scopes: Some(api_key.scopes),
+    })
+}
+
+fn to_api_key_response(api_key: ApiKey) -> ApiKeyResponse {
+    ApiKeyResponse {
+        id: api_key.id,
+        name: api_key.name,
+        prefix: api_key.prefix,
+        scopes: api_key.scopes,
+        expires_at: api_key.expires_at,
+        last_used_at: api_key.last_used_at,
+        revoked_at: api_key.revoked_at,
+        created_at: api_key.created_at,
+    }
+}
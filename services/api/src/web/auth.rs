@@ -1,26 +1,46 @@
 use crate::{
     config::Config,
-    dtos::{AuthResponse, LoginRequest, SignupRequest},
+    dtos::{AuthResponse, LoginRequest, LoginResponse, RefreshRequest, SignupRequest},
     error::AppError,
-    model::User,
+    model::{LoginOutcome, RefreshToken, Session, User},
     utils::{
+        email::normalize_email,
         hash::{hash_password, verify_password},
-        jwt::encode_jwt,
+        jwt::{encode_jwt, JwtKeys, ACCESS_TOKEN_TTL_MINUTES},
+        token::{generate_token, hash_token},
     },
+    web::{
+        login_throttle::{self, ClientIp},
+        two_factor::create_mfa_challenge,
+        verification::start_email_verification,
+    },
+};
+use axum::{
+    Json,
+    extract::State,
+    http::StatusCode,
 };
-use axum::{Json, extract::State};
+use jsonwebtoken::jwk::JwkSet;
+use chrono::{Duration, Utc};
+use sqlx::{PgConnection, PgPool};
 use std::sync::Arc;
+use uuid::Uuid;
+
+/// How long a session survives without being refreshed.
+const REFRESH_TOKEN_TTL_DAYS: i64 = 30;
 
 pub async fn signup_handler(
     State(state): State<Arc<Config>>,
     Json(payload): Json<SignupRequest>,
 ) -> Result<Json<AuthResponse>, AppError> {
-    let hashed_password = hash_password(&payload.password)?;
+    let email = normalize_email(&payload.email)?;
+    let hashed_password = hash_password(&state.password_policy, &payload.password).await?;
 
     let user = sqlx::query_as::<_, User>(
-        "INSERT INTO users (username, password_hash) VALUES ($1, $2) RETURNING id, username, password_hash, created_at"
+        "INSERT INTO users (username, email, password_hash) VALUES ($1, $2, $3) RETURNING *"
     )
     .bind(&payload.username)
+    .bind(&email)
     .bind(&hashed_password)
     .fetch_one(&state.db_pool)
     .await
@@ -28,32 +48,263 @@ pub async fn signup_handler(
         // Handle unique constraint violation
         if let Some(db_error) = e.as_database_error() {
              if db_error.is_unique_violation() {
+                 if db_error.constraint() == Some("idx_users_email") {
+                     return AppError::BadRequest("Email already in use".to_string());
+                 }
                  return AppError::BadRequest("Username already exists".to_string());
              }
         }
         AppError::Database(e)
     })?;
 
-    let token = encode_jwt(user.id, &state.jwt_secret)?;
+    start_email_verification(&state.db_pool, &user).await?;
 
-    Ok(Json(AuthResponse { token }))
+    Ok(Json(start_session(&state, &user).await?))
 }
 
 pub async fn login_handler(
     State(state): State<Arc<Config>>,
+    ClientIp(ip): ClientIp,
     Json(payload): Json<LoginRequest>,
+) -> Result<Json<LoginResponse>, AppError> {
+    let attempt_id = login_throttle::begin(&state.db_pool, &payload.username, ip).await?;
+
+    let user =
+        sqlx::query_as::<_, User>("SELECT * FROM users WHERE username = $1 AND deleted_at IS NULL")
+            .bind(&payload.username)
+            .fetch_optional(&state.db_pool)
+            .await?;
+
+    // Unknown usernames still pay for a hash check so both failures take
+    // the same time
+    let password_hash = user
+        .as_ref()
+        .map_or(state.dummy_password_hash.as_str(), |user| {
+            user.password_hash.as_str()
+        });
+    let password_ok = verify_password(&payload.password, password_hash).await?;
+
+    let user = match user {
+        Some(user) if password_ok => user,
+        user => {
+            login_throttle::finish(
+                &state.db_pool,
+                attempt_id,
+                user.map(|user| user.id),
+                LoginOutcome::BadCredentials,
+            )
+            .await?;
+            return Err(AppError::Unauthorized);
+        }
+    };
+
+    // With 2FA on, only the second step completes the login, so failed codes
+    // keep counting until then
+    let outcome = if user.totp_enabled_at.is_some() {
+        LoginOutcome::Challenged
+    } else {
+        LoginOutcome::Success
+    };
+    login_throttle::finish(&state.db_pool, attempt_id, Some(user.id), outcome).await?;
+
+    if state.password_policy.needs_rehash(&user.password_hash) {
+        upgrade_password_hash(&state, user.id, &payload.password).await;
+    }
+
+    if user.totp_enabled_at.is_some() {
+        let challenge = create_mfa_challenge(&state.db_pool, user.id).await?;
+        return Ok(Json(LoginResponse::ChallengeRequired(challenge)));
+    }
+
+    Ok(Json(LoginResponse::Authenticated(
+        start_session(&state, &user).await?,
+    )))
+}
+
+/// Re-hashes a password that was just verified with the current policy.
+/// Failures are only logged; the old hash keeps working.
+async fn upgrade_password_hash(state: &Config, user_id: Uuid, password: &str) {
+    let result = async {
+        let password_hash = hash_password(&state.password_policy, password).await?;
+        sqlx::query("UPDATE users SET password_hash = $2 WHERE id = $1")
+            .bind(user_id)
+            .bind(&password_hash)
+            .execute(&state.db_pool)
+            .await?;
+        Ok::<_, AppError>(())
+    }
+    .await;
+
+    match result {
+        Ok(()) => tracing::info!("Upgraded password hash for user {}", user_id),
+        Err(e) => tracing::warn!("Failed to upgrade password hash for user {}: {:?}", user_id, e),
+    }
+}
+
+/// Exchanges a refresh token for a new access token and a new refresh token.
+/// Each refresh token works once: presenting one that was already exchanged
+/// means it leaked, so the whole session is revoked.
+pub async fn refresh_handler(
+    State(state): State<Arc<Config>>,
+    Json(payload): Json<RefreshRequest>,
 ) -> Result<Json<AuthResponse>, AppError> {
-    let user = sqlx::query_as::<_, User>("SELECT * FROM users WHERE username = $1")
-        .bind(&payload.username)
-        .fetch_optional(&state.db_pool)
-        .await?
-        .ok_or(AppError::Unauthorized)?;
+    let mut tx = state.db_pool.begin().await?;
+
+    let refresh_token = sqlx::query_as::<_, RefreshToken>(
+        "SELECT id, session_id, expires_at, used_at FROM refresh_tokens WHERE token_hash = $1 FOR UPDATE"
+    )
+    .bind(hash_token(&payload.refresh_token))
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or(AppError::Unauthorized)?;
+
+    let session = sqlx::query_as::<_, Session>("SELECT * FROM sessions WHERE id = $1 FOR UPDATE")
+        .bind(refresh_token.session_id)
+        .fetch_one(&mut *tx)
+        .await?;
+
+    if session.revoked_at.is_some() {
+        return Err(AppError::Unauthorized);
+    }
+
+    if refresh_token.used_at.is_some() {
+        tracing::warn!(
+            "Refresh token reuse detected; revoking session {} of user {}",
+            session.id,
+            session.user_id
+        );
+        revoke_session(&mut tx, session.id).await?;
+        tx.commit().await?;
+        return Err(AppError::Unauthorized);
+    }
 
-    if !verify_password(&payload.password, &user.password_hash)? {
+    if refresh_token.expires_at <= Utc::now() {
         return Err(AppError::Unauthorized);
     }
 
-    let token = encode_jwt(user.id, &state.jwt_secret)?;
+    sqlx::query("UPDATE refresh_tokens SET used_at = NOW() WHERE id = $1")
+        .bind(refresh_token.id)
+        .execute(&mut *tx)
+        .await?;
+
+    // Roles are re-read so grants and revocations apply from the next refresh
+    let user = sqlx::query_as::<_, User>("SELECT * FROM users WHERE id = $1")
+        .bind(session.user_id)
+        .fetch_one(&mut *tx)
+        .await?;
+
+    let response = issue_tokens(&mut tx, &state.jwt_keys, &user, session.id).await?;
+
+    tx.commit().await?;
+
+    Ok(Json(response))
+}
+
+/// Ends the session the refresh token belongs to. Unknown tokens are ignored
+/// so logging out is always safe to retry.
+pub async fn logout_handler(
+    State(state): State<Arc<Config>>,
+    Json(payload): Json<RefreshRequest>,
+) -> Result<StatusCode, AppError> {
+    let session_id = sqlx::query_scalar::<_, Uuid>(
+        "SELECT session_id FROM refresh_tokens WHERE token_hash = $1"
+    )
+    .bind(hash_token(&payload.refresh_token))
+    .fetch_optional(&state.db_pool)
+    .await?;
+
+    if let Some(session_id) = session_id {
+        let mut conn = state.db_pool.acquire().await?;
+        revoke_session(&mut conn, session_id).await?;
+    }
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Opens a new session for a user who just authenticated. Signing in
+/// cancels a pending account deletion.
+pub async fn start_session(state: &Config, user: &User) -> Result<AuthResponse, AppError> {
+    let mut tx = state.db_pool.begin().await?;
+
+    sqlx::query(
+        "UPDATE users SET deletion_scheduled_at = NULL
+         WHERE id = $1 AND deletion_scheduled_at IS NOT NULL",
+    )
+    .bind(user.id)
+    .execute(&mut *tx)
+    .await?;
+
+    let session_id = sqlx::query_scalar::<_, Uuid>(
+        "INSERT INTO sessions (user_id) VALUES ($1) RETURNING id"
+    )
+    .bind(user.id)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    let response = issue_tokens(&mut tx, &state.jwt_keys, user, session_id).await?;
+
+    tx.commit().await?;
+
+    Ok(response)
+}
+
+async fn issue_tokens(
+    conn: &mut PgConnection,
+    jwt_keys: &JwtKeys,
+    user: &User,
+    session_id: Uuid,
+) -> Result<AuthResponse, AppError> {
+    let refresh_token = generate_token();
+
+    sqlx::query(
+        "INSERT INTO refresh_tokens (session_id, token_hash, expires_at) VALUES ($1, $2, $3)"
+    )
+    .bind(session_id)
+    .bind(hash_token(&refresh_token))
+    .bind(Utc::now() + Duration::days(REFRESH_TOKEN_TTL_DAYS))
+    .execute(conn)
+    .await?;
+
+    Ok(AuthResponse {
+        token: encode_jwt(user.id, session_id, &user.roles, jwt_keys)?,
+        refresh_token,
+        expires_in: ACCESS_TOKEN_TTL_MINUTES * 60,
+    })
+}
+
+pub async fn revoke_session(conn: &mut PgConnection, session_id: Uuid) -> Result<(), AppError> {
+    sqlx::query("UPDATE sessions SET revoked_at = NOW() WHERE id = $1 AND revoked_at IS NULL")
+        .bind(session_id)
+        .execute(conn)
+        .await?;
+
+    Ok(())
+}
+
+/// Signs the user out everywhere, e.g. after a password change.
+pub async fn revoke_user_sessions(conn: &mut PgConnection, user_id: Uuid) -> Result<(), AppError> {
+    sqlx::query("UPDATE sessions SET revoked_at = NOW() WHERE user_id = $1 AND revoked_at IS NULL")
+        .bind(user_id)
+        .execute(conn)
+        .await?;
+
+    Ok(())
+}
+
+/// Whether access tokens issued for the session are still honoured.
+pub async fn is_session_active(pool: &PgPool, session_id: Uuid) -> Result<bool, AppError> {
+    let active = sqlx::query_scalar::<_, bool>(
+        "SELECT revoked_at IS NULL FROM sessions WHERE id = $1"
+    )
+    .bind(session_id)
+    .fetch_optional(pool)
+    .await?
+    .unwrap_or(false);
+
+    Ok(active)
+}
 
-    Ok(Json(AuthResponse { token }))
+/// Public keys for verifying access tokens, in the standard JWKS format.
+pub async fn jwks_handler(State(state): State<Arc<Config>>) -> Json<JwkSet> {
+    Json(state.jwt_keys.jwks().clone())
 }
@@ -0,0 +1,210 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, State},
+    Extension, Json,
+};
+use rust_decimal::Decimal;
+use sqlx::PgPool;
+
+use crate::{
+    config::Config,
+    dtos::{AddCartItemRequest, CartItemResponse, CartResponse, UpdateCartItemRequest},
+    error::AppError,
+    model::{Cart, CartLine, Product},
+};
+use uuid::Uuid;
+
+pub async fn get_cart(
+    State(state): State<Arc<Config>>,
+    Extension(user_id): Extension<Uuid>,
+) -> Result<Json<CartResponse>, AppError> {
+    let cart = get_or_create_cart(&state.db_pool, user_id).await?;
+
+    Ok(Json(build_cart_response(&state.db_pool, &cart).await?))
+}
+
+pub async fn add_cart_item(
+    State(state): State<Arc<Config>>,
+    Extension(user_id): Extension<Uuid>,
+    Json(payload): Json<AddCartItemRequest>,
+) -> Result<Json<CartResponse>, AppError> {
+    if payload.quantity <= 0 {
+        return Err(AppError::BadRequest(
+            "Quantity must be greater than zero".to_string(),
+        ));
+    }
+
+    let cart = get_or_create_cart(&state.db_pool, user_id).await?;
+    let product = find_product(&state.db_pool, payload.product_id).await?;
+
+    let existing = sqlx::query_scalar::<_, i32>(
+        "SELECT quantity FROM cart_items WHERE cart_id = $1 AND product_id = $2",
+    )
+    .bind(cart.id)
+    .bind(product.id)
+    .fetch_optional(&state.db_pool)
+    .await?
+    .unwrap_or(0);
+
+    let quantity = existing
+        .checked_add(payload.quantity)
+        .ok_or(AppError::BadRequest("Quantity is too large".to_string()))?;
+    ensure_in_stock(&product, quantity)?;
+
+    sqlx::query(
+        "INSERT INTO cart_items (cart_id, product_id, quantity) VALUES ($1, $2, $3)
+         ON CONFLICT (cart_id, product_id)
+         DO UPDATE SET quantity = cart_items.quantity + EXCLUDED.quantity, updated_at = NOW()",
+    )
+    .bind(cart.id)
+    .bind(product.id)
+    .bind(payload.quantity)
+    .execute(&state.db_pool)
+    .await?;
+
+    Ok(Json(build_cart_response(&state.db_pool, &cart).await?))
+}
+
+pub async fn update_cart_item(
+    State(state): State<Arc<Config>>,
+    Extension(user_id): Extension<Uuid>,
+    Path(product_id): Path<Uuid>,
+    Json(payload): Json<UpdateCartItemRequest>,
+) -> Result<Json<CartResponse>, AppError> {
+    let cart = get_or_create_cart(&state.db_pool, user_id).await?;
+
+    // A quantity of zero is treated as a removal
+    if payload.quantity <= 0 {
+        sqlx::query("DELETE FROM cart_items WHERE cart_id = $1 AND product_id = $2")
+            .bind(cart.id)
+            .bind(product_id)
+            .execute(&state.db_pool)
+            .await?;
+
+        return Ok(Json(build_cart_response(&state.db_pool, &cart).await?));
+    }
+
+    let product = find_product(&state.db_pool, product_id).await?;
+    ensure_in_stock(&product, payload.quantity)?;
+
+    let updated = sqlx::query(
+        "UPDATE cart_items SET quantity = $3, updated_at = NOW() WHERE cart_id = $1 AND product_id = $2",
+    )
+    .bind(cart.id)
+    .bind(product_id)
+    .bind(payload.quantity)
+    .execute(&state.db_pool)
+    .await?;
+
+    if updated.rows_affected() == 0 {
+        return Err(AppError::BadRequest("Item not in cart".to_string()));
+    }
+
+    Ok(Json(build_cart_response(&state.db_pool, &cart).await?))
+}
+
+pub async fn remove_cart_item(
+    State(state): State<Arc<Config>>,
+    Extension(user_id): Extension<Uuid>,
+    Path(product_id): Path<Uuid>,
+) -> Result<Json<CartResponse>, AppError> {
+    let cart = get_or_create_cart(&state.db_pool, user_id).await?;
+
+    let removed = sqlx::query("DELETE FROM cart_items WHERE cart_id = $1 AND product_id = $2")
+        .bind(cart.id)
+        .bind(product_id)
+        .execute(&state.db_pool)
+        .await?;
+
+    if removed.rows_affected() == 0 {
+        return Err(AppError::BadRequest("Item not in cart".to_string()));
+    }
+
+    Ok(Json(build_cart_response(&state.db_pool, &cart).await?))
+}
+
+pub async fn clear_cart(
+    State(state): State<Arc<Config>>,
+    Extension(user_id): Extension<Uuid>,
+) -> Result<Json<CartResponse>, AppError> {
+    let cart = get_or_create_cart(&state.db_pool, user_id).await?;
+
+    sqlx::query("DELETE FROM cart_items WHERE cart_id = $1")
+        .bind(cart.id)
+        .execute(&state.db_pool)
+        .await?;
+
+    Ok(Json(build_cart_response(&state.db_pool, &cart).await?))
+}
+
+/// Every user has at most one cart; it is created lazily on first access.
+pub async fn get_or_create_cart(pool: &PgPool, user_id: Uuid) -> Result<Cart, AppError> {
+    let cart = sqlx::query_as::<_, Cart>(
+        "INSERT INTO carts (user_id) VALUES ($1)
+         ON CONFLICT (user_id) DO UPDATE SET updated_at = NOW()
+         RETURNING *",
+    )
+    .bind(user_id)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(cart)
+}
+
+pub async fn fetch_cart_lines(pool: &PgPool, cart_id: Uuid) -> Result<Vec<CartLine>, AppError> {
+    let lines = sqlx::query_as::<_, CartLine>(
+        "SELECT ci.product_id, p.name, p.price, ci.quantity
+         FROM cart_items ci
+         JOIN products p ON p.id = ci.product_id
+         WHERE ci.cart_id = $1
+         ORDER BY ci.created_at",
+    )
+    .bind(cart_id)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(lines)
+}
+
+async fn build_cart_response(pool: &PgPool, cart: &Cart) -> Result<CartResponse, AppError> {
+    let lines = fetch_cart_lines(pool, cart.id).await?;
+
+    let items: Vec<CartItemResponse> = lines
+        .into_iter()
+        .map(|line| CartItemResponse {
+            product_id: line.product_id,
+            line_total: line.price * Decimal::from(line.quantity),
+            name: line.name,
+            unit_price: line.price,
+            quantity: line.quantity,
+        })
+        .collect();
+
+    let total = items.iter().map(|item| item.line_total).sum();
+
+    Ok(CartResponse {
+        id: cart.id,
+        items,
+        total,
+    })
+}
+
+async fn find_product(pool: &PgPool, product_id: Uuid) -> Result<Product, AppError> {
+    sqlx::query_as::<_, Product>("SELECT * FROM products WHERE id = $1 AND deleted_at IS NULL")
+        .bind(product_id)
+        .fetch_optional(pool)
+        .await?
+        .ok_or(AppError::BadRequest("Product not found".to_string()))
+}
+
+fn ensure_in_stock(product: &Product, quantity: i32) -> Result<(), AppError> {
+    if quantity > product.stock_quantity {
+        return Err(AppError::BadRequest(format!(
+            "Only {} of {} in stock",
+            product.stock_quantity, product.name
+        )));
+    }
+
+    Ok(())
+}
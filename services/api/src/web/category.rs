@@ -1,13 +1,18 @@
-use std::sync::Arc;
+use std::{collections::HashMap, sync::Arc};
 
 use axum::{
     extract::{Path, Query, State},
-    Extension, Json,
+    http::StatusCode,
+    Json,
 };
+use sqlx::{PgConnection, PgPool};
 
 use crate::{
     config::Config,
-    dtos::{CategoryResponse, CreateCategoryRequest},
+    dtos::{
+        CategoryListRequest, CategoryListResponse, CategoryResponse, CategoryTreeResponse,
+        CreateCategoryRequest, DeleteCategoryRequest, UpdateCategoryRequest,
+    },
     error::AppError,
     model::Category,
 };
@@ -15,18 +20,275 @@ use uuid::Uuid;
 
 pub async fn create_category(
     State(state): State<Arc<Config>>,
-    Extension(user_id): Extension<Uuid>,
     Json(payload): Json<CreateCategoryRequest>,
 ) -> Result<Json<CategoryResponse>, AppError> {
+    if let Some(parent_id) = payload.parent_id {
+        find_category(&state.db_pool, parent_id).await?;
+    }
+
+    let category = sqlx::query_as::<_, Category>(
+        "INSERT INTO categories (name, parent_id) VALUES ($1, $2) RETURNING *",
+    )
+    .bind(&payload.name)
+    .bind(payload.parent_id)
+    .fetch_one(&state.db_pool)
+    .await
+    .map_err(map_name_conflict)?;
+
+    Ok(Json(to_category_response(category)))
+}
+
+pub async fn get_categories(
+    State(state): State<Arc<Config>>,
+    Query(params): Query<CategoryListRequest>,
+) -> Result<Json<CategoryListResponse>, AppError> {
+    let categories = sqlx::query_as::<_, Category>("SELECT * FROM categories ORDER BY name")
+        .fetch_all(&state.db_pool)
+        .await?;
+
+    if !params.tree {
+        return Ok(Json(CategoryListResponse::Flat(
+            categories.into_iter().map(to_category_response).collect(),
+        )));
+    }
+
+    let mut children: HashMap<Option<Uuid>, Vec<Category>> = HashMap::new();
+    for category in categories {
+        children
+            .entry(category.parent_id)
+            .or_default()
+            .push(category);
+    }
+
+    Ok(Json(CategoryListResponse::Tree(build_tree(
+        &mut children,
+        None,
+    ))))
+}
+
+pub async fn get_category_by_id(
+    State(state): State<Arc<Config>>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<CategoryResponse>, AppError> {
+    let category = find_category(&state.db_pool, id).await?;
+
+    Ok(Json(to_category_response(category)))
+}
+
+/// The path from the root category down to (and including) this one.
+pub async fn get_category_breadcrumbs(
+    State(state): State<Arc<Config>>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<Vec<CategoryResponse>>, AppError> {
+    let breadcrumbs = sqlx::query_as::<_, Category>(
+        "WITH RECURSIVE ancestors AS (
+             SELECT c.*, 0 AS depth FROM categories c WHERE c.id = $1
+             UNION ALL
+             SELECT p.*, a.depth + 1 FROM categories p JOIN ancestors a ON p.id = a.parent_id
+         ) CYCLE id SET is_cycle USING path
+         SELECT id, name, parent_id, created_at FROM ancestors
+         WHERE NOT is_cycle
+         ORDER BY depth DESC",
+    )
+    .bind(id)
+    .fetch_all(&state.db_pool)
+    .await?;
+
+    if breadcrumbs.is_empty() {
+        return Err(AppError::BadRequest("Category not found".to_string()));
+    }
+
+    Ok(Json(
+        breadcrumbs.into_iter().map(to_category_response).collect(),
+    ))
+}
+
+/// Renames and/or moves a category. Moving a category under itself or one of
+/// its descendants is rejected, since that would detach a cycle from the tree.
+pub async fn update_category(
+    State(state): State<Arc<Config>>,
+    Path(id): Path<Uuid>,
+    Json(payload): Json<UpdateCategoryRequest>,
+) -> Result<Json<CategoryResponse>, AppError> {
+    let mut tx = state.db_pool.begin().await?;
+
+    // Moves run one at a time; otherwise moving A under B and B under A at
+    // once would both pass the subtree check and commit a cycle
+    if matches!(payload.parent_id, Some(Some(_))) {
+        sqlx::query("LOCK TABLE categories IN SHARE ROW EXCLUSIVE MODE")
+            .execute(&mut *tx)
+            .await?;
+    }
+
     let category =
-        sqlx::query_as::<_, Category>("INSERT INTO categories (name) VALUES ($1) RETURNING *")
-            .bind(&payload.name)
-            .fetch_one(&state.db_pool)
+        sqlx::query_as::<_, Category>("SELECT * FROM categories WHERE id = $1 FOR UPDATE")
+            .bind(id)
+            .fetch_optional(&mut *tx)
+            .await?
+            .ok_or(AppError::BadRequest("Category not found".to_string()))?;
+
+    let parent_id = match payload.parent_id {
+        Some(Some(parent_id)) => {
+            find_category(&state.db_pool, parent_id).await?;
+            if is_in_subtree(&mut tx, id, parent_id).await? {
+                return Err(AppError::BadRequest(
+                    "A category cannot be moved under itself or its descendants".to_string(),
+                ));
+            }
+            Some(parent_id)
+        }
+        Some(None) => None,
+        None => category.parent_id,
+    };
+
+    let category = sqlx::query_as::<_, Category>(
+        "UPDATE categories SET name = COALESCE($2, name), parent_id = $3 WHERE id = $1 RETURNING *",
+    )
+    .bind(id)
+    .bind(&payload.name)
+    .bind(parent_id)
+    .fetch_one(&mut *tx)
+    .await
+    .map_err(map_name_conflict)?;
+
+    tx.commit().await?;
+
+    Ok(Json(to_category_response(category)))
+}
+
+/// Deletes a category. Its products move to `reassign_to` (or the parent
+/// category when omitted) and its subcategories move up to its parent.
+pub async fn delete_category(
+    State(state): State<Arc<Config>>,
+    Path(id): Path<Uuid>,
+    Query(params): Query<DeleteCategoryRequest>,
+) -> Result<StatusCode, AppError> {
+    let mut tx = state.db_pool.begin().await?;
+
+    let category =
+        sqlx::query_as::<_, Category>("SELECT * FROM categories WHERE id = $1 FOR UPDATE")
+            .bind(id)
+            .fetch_optional(&mut *tx)
+            .await?
+            .ok_or(AppError::BadRequest("Category not found".to_string()))?;
+
+    if params.reassign_to == Some(id) {
+        return Err(AppError::BadRequest(
+            "Products cannot be reassigned to the category being deleted".to_string(),
+        ));
+    }
+
+    let has_products = sqlx::query_scalar::<_, bool>(
+        "SELECT EXISTS (SELECT 1 FROM products WHERE category_id = $1)",
+    )
+    .bind(id)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    if has_products {
+        let target = params
+            .reassign_to
+            .or(category.parent_id)
+            .ok_or(AppError::BadRequest(
+                "Category has products; pass reassign_to to move them".to_string(),
+            ))?;
+
+        sqlx::query("SELECT id FROM categories WHERE id = $1")
+            .bind(target)
+            .fetch_optional(&mut *tx)
+            .await?
+            .ok_or(AppError::BadRequest(
+                "Reassignment category not found".to_string(),
+            ))?;
+
+        sqlx::query("UPDATE products SET category_id = $2 WHERE category_id = $1")
+            .bind(id)
+            .bind(target)
+            .execute(&mut *tx)
             .await?;
+    }
+
+    sqlx::query("UPDATE categories SET parent_id = $2 WHERE parent_id = $1")
+        .bind(id)
+        .bind(category.parent_id)
+        .execute(&mut *tx)
+        .await
+        .map_err(map_name_conflict)?;
 
-    Ok(Json(CategoryResponse {
+    sqlx::query("DELETE FROM categories WHERE id = $1")
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn find_category(pool: &PgPool, id: Uuid) -> Result<Category, AppError> {
+    sqlx::query_as::<_, Category>("SELECT * FROM categories WHERE id = $1")
+        .bind(id)
+        .fetch_optional(pool)
+        .await?
+        .ok_or(AppError::BadRequest("Category not found".to_string()))
+}
+
+/// Whether `candidate` is `root` or lies anywhere below it.
+async fn is_in_subtree(
+    conn: &mut PgConnection,
+    root: Uuid,
+    candidate: Uuid,
+) -> Result<bool, AppError> {
+    let found = sqlx::query_scalar::<_, bool>(
+        "WITH RECURSIVE subtree AS (
+             SELECT id FROM categories WHERE id = $1
+             UNION
+             SELECT c.id FROM categories c JOIN subtree s ON c.parent_id = s.id
+         )
+         SELECT EXISTS (SELECT 1 FROM subtree WHERE id = $2)",
+    )
+    .bind(root)
+    .bind(candidate)
+    .fetch_one(conn)
+    .await?;
+
+    Ok(found)
+}
+
+fn build_tree(
+    children: &mut HashMap<Option<Uuid>, Vec<Category>>,
+    parent_id: Option<Uuid>,
+) -> Vec<CategoryTreeResponse> {
+    children
+        .remove(&parent_id)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|category| CategoryTreeResponse {
+            children: build_tree(children, Some(category.id)),
+            id: category.id,
+            name: category.name,
+            parent_id: category.parent_id,
+            created_at: category.created_at,
+        })
+        .collect()
+}
+
+fn to_category_response(category: Category) -> CategoryResponse {
+    CategoryResponse {
         id: category.id,
-        name: category.name.clone(),
+        name: category.name,
+        parent_id: category.parent_id,
         created_at: category.created_at,
-    }))
+    }
+}
+
+fn map_name_conflict(e: sqlx::Error) -> AppError {
+    if let Some(db_error) = e.as_database_error() {
+        if db_error.is_unique_violation() {
+            return AppError::BadRequest(
+                "A category with this name already exists here".to_string(),
+            );
+        }
+    }
+    AppError::Database(e)
 }
@@ -0,0 +1,114 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Extension,
+};
+use sqlx::PgPool;
+use tokio_stream::StreamExt;
+
+use crate::{config::Config, error::AppError, model::User, web::mw::AuthUser};
+use uuid::Uuid;
+
+/// Follows a seller, to be notified of each product they list. Following
+/// one already followed is a no-op.
+pub async fn follow_seller(
+    State(state): State<Arc<Config>>,
+    Extension(user): Extension<AuthUser>,
+    Path(seller_id): Path<Uuid>,
+) -> Result<StatusCode, AppError> {
+    if seller_id == user.id {
+        return Err(AppError::BadRequest(
+            "You cannot follow yourself".to_string(),
+        ));
+    }
+
+    let is_seller = sqlx::query_scalar::<_, bool>(
+        "SELECT EXISTS (
+             SELECT 1 FROM users WHERE id = $1 AND 'seller' = ANY(roles) AND deleted_at IS NULL
+         )",
+    )
+    .bind(seller_id)
+    .fetch_one(&state.db_pool)
+    .await?;
+
+    if !is_seller {
+        return Err(AppError::BadRequest("Seller not found".to_string()));
+    }
+
+    sqlx::query(
+        "INSERT INTO seller_follows (follower_id, seller_id) VALUES ($1, $2)
+         ON CONFLICT DO NOTHING",
+    )
+    .bind(user.id)
+    .bind(seller_id)
+    .execute(&state.db_pool)
+    .await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Stops following a seller; unfollowing one not followed is a no-op.
+pub async fn unfollow_seller(
+    State(state): State<Arc<Config>>,
+    Extension(user): Extension<AuthUser>,
+    Path(seller_id): Path<Uuid>,
+) -> Result<StatusCode, AppError> {
+    sqlx::query("DELETE FROM seller_follows WHERE follower_id = $1 AND seller_id = $2")
+        .bind(user.id)
+        .bind(seller_id)
+        .execute(&state.db_pool)
+        .await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Tells everyone following `seller` about a product they just listed. Runs
+/// in the background, one notification per follower; failures are logged
+/// and skipped.
+pub fn notify_followers(pool: PgPool, seller: User, product_name: String) {
+    tokio::spawn(async move {
+        let seller_name = seller
+            .display_name
+            .clone()
+            .unwrap_or_else(|| seller.username.clone());
+
+        let mut followers = sqlx::query_as::<_, User>(
+            "SELECT u.* FROM seller_follows f
+             JOIN users u ON u.id = f.follower_id
+             WHERE f.seller_id = $1 AND u.deleted_at IS NULL",
+        )
+        .bind(seller.id)
+        .fetch(&pool);
+
+        while let Some(follower) = followers.next().await {
+            let follower = match follower {
+                Ok(follower) => follower,
+                Err(e) => {
+                    tracing::error!("Failed to load followers of {}: {}", seller.id, e);
+                    return;
+                }
+            };
+
+            let notification_result =
+                crate::grpc_client::send_followed_seller_listing_notification(
+                    &follower.id.to_string(),
+                    &follower.username,
+                    &seller_name,
+                    &product_name,
+                    follower.email.as_deref().unwrap_or_default(),
+                    follower.locale.as_deref().unwrap_or_default(),
+                )
+                .await;
+
+            if let Err(e) = notification_result {
+                tracing::warn!(
+                    "Failed to notify follower {} of a new listing: {}",
+                    follower.id,
+                    e
+                );
+            }
+        }
+    });
+}
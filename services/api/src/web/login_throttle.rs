@@ -0,0 +1,274 @@
+//! Throttling for logins. Failed attempts, wrong passwords and wrong
+//! second-factor codes alike, are counted per username and per client IP;
+//! past a threshold each further failure doubles the wait before the next
+//! attempt, up to a lockout cap. Checks run before the password hash is
+//! touched, so a locked-out caller costs no password hashing work.
+//!
+//! The client IP is the peer address unless `TRUSTED_PROXY_HEADER` names a
+//! header the reverse proxy in front of the API sets, such as
+//! `X-Forwarded-For` or `X-Real-IP`; see [`ClientIp`]. Behind a proxy
+//! without it, every client shares the proxy's address, and the per-IP
+//! limit throttles logins for everyone at once.
+//!
+//! [`begin`] checks and records an attempt as a failure in one short
+//! transaction, holding a lock on the username and on the IP, so a burst of
+//! concurrent guesses cannot all pass the check before any of them counts.
+//! [`finish`] then records the real outcome once the password or code is
+//! checked.
+
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
+
+use axum::{
+    extract::{ConnectInfo, FromRequestParts},
+    http::{request::Parts, HeaderMap, HeaderName},
+};
+use chrono::{DateTime, Utc};
+use sqlx::{PgConnection, PgPool};
+use uuid::Uuid;
+
+use crate::{config::Config, error::AppError, model::LoginOutcome};
+
+/// How far back failures count. A completed login also resets the
+/// per-username count; a right password with a second factor still owed
+/// does not.
+const WINDOW_MINUTES: i32 = 30;
+/// Failures allowed before backoff starts.
+const USERNAME_FREE_ATTEMPTS: i64 = 5;
+/// Higher, since many users can share an address behind NAT.
+const IP_FREE_ATTEMPTS: i64 = 20;
+/// The first delay after the free attempts run out; doubles per failure.
+const BASE_DELAY_SECS: i64 = 1;
+/// The longest lockout a run of failures can cause.
+const MAX_DELAY_SECS: i64 = 15 * 60;
+
+/// The address a request came from, as far as throttling is concerned.
+///
+/// With `TRUSTED_PROXY_HEADER` set, this is the right-most address in that
+/// header: the one the proxy itself wrote, as anything to its left came
+/// from the client. A request without the header, such as one made on the
+/// server itself, falls back to the peer address. The header is only
+/// trustworthy because the API listens on loopback, where nothing but the
+/// proxy can reach it.
+#[derive(Debug, Clone, Copy)]
+pub struct ClientIp(pub IpAddr);
+
+impl FromRequestParts<Arc<Config>> for ClientIp {
+    type Rejection = <ConnectInfo<SocketAddr> as FromRequestParts<Arc<Config>>>::Rejection;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &Arc<Config>,
+    ) -> Result<Self, Self::Rejection> {
+        let ConnectInfo(peer) = ConnectInfo::<SocketAddr>::from_request_parts(parts, state).await?;
+
+        Ok(Self(client_ip(
+            state.trusted_proxy_header.as_ref(),
+            &parts.headers,
+            peer.ip(),
+        )))
+    }
+}
+
+fn client_ip(header: Option<&HeaderName>, headers: &HeaderMap, peer: IpAddr) -> IpAddr {
+    header
+        .and_then(|header| headers.get_all(header).iter().next_back())
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.rsplit(',').next())
+        .and_then(|ip| ip.trim().parse().ok())
+        .unwrap_or(peer)
+}
+
+/// Starts an attempt: rejects it with `TooManyRequests` if the username or
+/// the IP is still backing off, and otherwise records it as a failure until
+/// [`finish`] says otherwise. Returns the attempt's id.
+pub async fn begin(pool: &PgPool, username: &str, ip: IpAddr) -> Result<Uuid, AppError> {
+    let mut tx = pool.begin().await?;
+    lock(&mut tx, username, ip).await?;
+
+    let checked = check(&mut tx, username, ip).await;
+    let outcome = match checked {
+        Ok(()) => LoginOutcome::BadCredentials,
+        Err(_) => LoginOutcome::Throttled,
+    };
+
+    let attempt_id = sqlx::query_scalar::<_, Uuid>(
+        "INSERT INTO login_attempts (username, ip_address, outcome)
+         VALUES ($1, $2, $3)
+         RETURNING id",
+    )
+    .bind(username)
+    .bind(ip.to_string())
+    .bind(outcome)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+    checked?;
+
+    Ok(attempt_id)
+}
+
+/// Records how an attempt from [`begin`] turned out.
+pub async fn finish(
+    pool: &PgPool,
+    attempt_id: Uuid,
+    user_id: Option<Uuid>,
+    outcome: LoginOutcome,
+) -> Result<(), AppError> {
+    sqlx::query("UPDATE login_attempts SET user_id = $2, outcome = $3 WHERE id = $1")
+        .bind(attempt_id)
+        .bind(user_id)
+        .bind(outcome)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+/// Serializes attempts for `username` and for `ip` until the transaction
+/// ends. The username lock is always taken first, so two attempts cannot
+/// deadlock on each other.
+async fn lock(conn: &mut PgConnection, username: &str, ip: IpAddr) -> Result<(), AppError> {
+    sqlx::query("SELECT pg_advisory_xact_lock(hashtext('login_username:' || $1))")
+        .bind(username)
+        .execute(&mut *conn)
+        .await?;
+
+    sqlx::query("SELECT pg_advisory_xact_lock(hashtext('login_ip:' || $1))")
+        .bind(ip.to_string())
+        .execute(&mut *conn)
+        .await?;
+
+    Ok(())
+}
+
+async fn check(conn: &mut PgConnection, username: &str, ip: IpAddr) -> Result<(), AppError> {
+    let (username_failures, username_last) = sqlx::query_as::<_, (i64, Option<DateTime<Utc>>)>(
+        "SELECT COUNT(*), MAX(created_at) FROM login_attempts
+         WHERE username = $1
+           AND outcome IN ('bad_credentials', 'bad_code')
+           AND created_at > NOW() - make_interval(mins => $2)
+           AND created_at > COALESCE(
+               (SELECT MAX(created_at) FROM login_attempts
+                WHERE username = $1 AND outcome = 'success'),
+               '-infinity'
+           )",
+    )
+    .bind(username)
+    .bind(WINDOW_MINUTES)
+    .fetch_one(&mut *conn)
+    .await?;
+
+    let (ip_failures, ip_last) = sqlx::query_as::<_, (i64, Option<DateTime<Utc>>)>(
+        "SELECT COUNT(*), MAX(created_at) FROM login_attempts
+         WHERE ip_address = $1
+           AND outcome IN ('bad_credentials', 'bad_code')
+           AND created_at > NOW() - make_interval(mins => $2)",
+    )
+    .bind(ip.to_string())
+    .bind(WINDOW_MINUTES)
+    .fetch_one(&mut *conn)
+    .await?;
+
+    let now = Utc::now();
+    let retry_after_secs = [
+        blocked_for(
+            username_failures,
+            USERNAME_FREE_ATTEMPTS,
+            username_last,
+            now,
+        ),
+        blocked_for(ip_failures, IP_FREE_ATTEMPTS, ip_last, now),
+    ]
+    .into_iter()
+    .max()
+    .unwrap_or(0);
+
+    if retry_after_secs > 0 {
+        return Err(AppError::TooManyRequests { retry_after_secs });
+    }
+
+    Ok(())
+}
+
+/// Seconds left to wait after `failures` failures, the latest at `last`.
+fn blocked_for(
+    failures: i64,
+    free_attempts: i64,
+    last: Option<DateTime<Utc>>,
+    now: DateTime<Utc>,
+) -> u64 {
+    let Some(last) = last else {
+        return 0;
+    };
+    if failures < free_attempts {
+        return 0;
+    }
+
+    let doublings = (failures - free_attempts).min(20) as u32;
+    let delay = (BASE_DELAY_SECS << doublings).min(MAX_DELAY_SECS);
+    let elapsed = (now - last).num_seconds();
+
+    (delay - elapsed).max(0) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PEER: IpAddr = IpAddr::V4(std::net::Ipv4Addr::LOCALHOST);
+
+    fn header_map(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.append(*name, value.parse().unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn uses_the_peer_without_a_trusted_header() {
+        let headers = header_map(&[("x-forwarded-for", "203.0.113.7")]);
+
+        assert_eq!(client_ip(None, &headers, PEER), PEER);
+    }
+
+    #[test]
+    fn takes_the_address_the_proxy_appended() {
+        let header = HeaderName::from_static("x-forwarded-for");
+
+        let headers = header_map(&[("x-forwarded-for", "198.51.100.1, 203.0.113.7")]);
+        assert_eq!(
+            client_ip(Some(&header), &headers, PEER),
+            "203.0.113.7".parse::<IpAddr>().unwrap()
+        );
+
+        // A spoofed header sent by the client comes first
+        let headers = header_map(&[
+            ("x-forwarded-for", "198.51.100.1"),
+            ("x-forwarded-for", "2001:db8::7"),
+        ]);
+        assert_eq!(
+            client_ip(Some(&header), &headers, PEER),
+            "2001:db8::7".parse::<IpAddr>().unwrap()
+        );
+    }
+
+    #[test]
+    fn falls_back_to_the_peer_when_the_header_is_missing_or_invalid() {
+        let header = HeaderName::from_static("x-real-ip");
+
+        assert_eq!(client_ip(Some(&header), &HeaderMap::new(), PEER), PEER);
+        assert_eq!(
+            client_ip(
+                Some(&header),
+                &header_map(&[("x-real-ip", "unknown")]),
+                PEER
+            ),
+            PEER
+        );
+    }
+}
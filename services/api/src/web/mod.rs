@@ -1,5 +1,18 @@
+pub mod account;
+pub mod admin;
+pub mod api_key;
 pub mod auth;
+pub mod cart;
 pub mod category;
+pub mod follow;
+pub mod login_throttle;
 pub mod mw;
+pub mod notification;
+pub mod order;
+pub mod password;
 pub mod post;
+pub mod privacy;
 pub mod product;
+pub mod refund;
+pub mod two_factor;
+pub mod verification;
@@ -1,36 +1,438 @@
-use crate::config::Config;
+use crate::config::{Config, VerifiedAction};
 use crate::error::AppError;
+use crate::model::{ApiScope, Role};
 use crate::utils::jwt::decode_jwt;
+use crate::web::{
+    api_key::{authenticate_api_key, KEY_PREFIX},
+    auth::is_session_active,
+};
 use axum::{
-    extract::{Request, State},
-    http::{StatusCode, header},
+    extract::{Query, Request, State},
+    http::{header, HeaderValue, Method},
     middleware::Next,
     response::Response,
 };
+use serde::Deserialize;
 use std::sync::Arc;
+use uuid::Uuid;
+
+/// The authenticated caller, available to handlers as `Extension<AuthUser>`.
+#[derive(Debug, Clone)]
+pub struct AuthUser {
+    pub id: Uuid,
+    pub roles: Vec<Role>,
+    /// The login session, for callers with an access token.
+    pub session_id: Option<Uuid>,
+    /// Set when the caller authenticated with an API key rather than a
+    /// login session.
+    pub api_key_scopes: Option<Vec<ApiScope>>,
+}
+
+impl AuthUser {
+    /// Admins implicitly hold every role.
+    pub fn has_role(&self, role: Role) -> bool {
+        self.roles.contains(&Role::Admin) || self.roles.contains(&role)
+    }
+
+    pub fn is_admin(&self) -> bool {
+        self.roles.contains(&Role::Admin)
+    }
+}
 
+/// Authenticates the caller from an access token (`Authorization: Bearer`)
+/// or an API key (`X-API-Key`, or a bearer token starting with `rk_`).
 pub async fn auth_guard(
     State(state): State<Arc<Config>>,
     mut req: Request,
     next: Next,
 ) -> Result<Response, AppError> {
-    let token = req
+    let bearer = req
         .headers()
         .get(header::AUTHORIZATION)
         .and_then(|auth_header| auth_header.to_str().ok())
-        .and_then(|auth_value| {
-            if auth_value.starts_with("Bearer ") {
-                Some(auth_value[7..].to_owned())
-            } else {
-                None
+        .and_then(|auth_value| auth_value.strip_prefix("Bearer "));
+    let api_key = req
+        .headers()
+        .get("x-api-key")
+        .and_then(|value| value.to_str().ok())
+        .or(bearer.filter(|token| token.starts_with(KEY_PREFIX)))
+        .map(|key| key.to_owned());
+    let token = bearer.map(|token| token.to_owned());
+
+    let user = match (api_key, token) {
+        (Some(api_key), _) => authenticate_api_key(&state.db_pool, &api_key).await?,
+        (None, Some(token)) => {
+            let claims = decode_jwt(&token, &state.jwt_keys)?;
+
+            if !is_session_active(&state.db_pool, claims.sid).await? {
+                return Err(AppError::Unauthorized);
             }
-        })
-        .ok_or(AppError::Unauthorized)?;
 
-    let claims = decode_jwt(&token, &state.jwt_secret)?;
+            AuthUser {
+                id: claims.sub,
+                roles: claims.roles,
+                session_id: Some(claims.sid),
+                api_key_scopes: None,
+            }
+        }
+        (None, None) => return Err(AppError::Unauthorized),
+    };
 
     // Insert user_id into request extensions for handlers to use
-    req.extensions_mut().insert(claims.sub);
+    req.extensions_mut().insert(user.id);
+    req.extensions_mut().insert(user);
+
+    Ok(next.run(req).await)
+}
+
+#[derive(Debug, Deserialize)]
+struct AccessTokenQuery {
+    access_token: Option<String>,
+}
+
+/// Accepts the access token as `?access_token=` for clients that cannot set
+/// headers, such as the browser's `EventSource`. Only for routes that need
+/// it, and must run outside `auth_guard`.
+pub async fn access_token_from_query(mut req: Request, next: Next) -> Response {
+    if !req.headers().contains_key(header::AUTHORIZATION) {
+        let token = Query::<AccessTokenQuery>::try_from_uri(req.uri())
+            .ok()
+            .and_then(|Query(query)| query.access_token)
+            .and_then(|token| HeaderValue::from_str(&format!("Bearer {}", token)).ok());
+
+        if let Some(token) = token {
+            req.headers_mut().insert(header::AUTHORIZATION, token);
+        }
+    }
+
+    next.run(req).await
+}
+
+/// Rejects callers without `role`. Must run inside `auth_guard`, e.g.
+/// `post(handler).route_layer(from_fn_with_state(Role::Admin, mw::require_role))`.
+pub async fn require_role(
+    State(role): State<Role>,
+    req: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    let user = req
+        .extensions()
+        .get::<AuthUser>()
+        .ok_or(AppError::Unauthorized)?;
+
+    if !user.has_role(role) {
+        return Err(AppError::Forbidden);
+    }
+
+    Ok(next.run(req).await)
+}
+
+/// Rejects callers whose email is unverified, if the configured policy
+/// requires verification for `action`. Must run inside `auth_guard`.
+pub async fn require_verified_email(
+    State((state, action)): State<(Arc<Config>, VerifiedAction)>,
+    req: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    if !state.email_verification.requires(action) {
+        return Ok(next.run(req).await);
+    }
+
+    let user = req
+        .extensions()
+        .get::<AuthUser>()
+        .ok_or(AppError::Unauthorized)?;
+
+    let verified = sqlx::query_scalar::<_, bool>(
+        "SELECT email_verified_at IS NOT NULL FROM users WHERE id = $1",
+    )
+    .bind(user.id)
+    .fetch_optional(&state.db_pool)
+    .await?
+    .unwrap_or(false);
+
+    if !verified {
+        return Err(AppError::EmailNotVerified);
+    }
+
+    Ok(next.run(req).await)
+}
+
+/// The scopes an API key needs for a group of routes: `read` for GET and
+/// HEAD requests, `write` for everything else.
+#[derive(Debug, Clone, Copy)]
+pub struct ScopePair {
+    pub read: ApiScope,
+    pub write: ApiScope,
+}
+
+/// Limits API keys to the routes their scopes cover. Callers with a login
+/// session are unaffected. Must run inside `auth_guard`.
+pub async fn require_scope(
+    State(scopes): State<ScopePair>,
+    req: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    let user = req
+        .extensions()
+        .get::<AuthUser>()
+        .ok_or(AppError::Unauthorized)?;
+
+    if let Some(granted) = &user.api_key_scopes {
+        let needed = if matches!(*req.method(), Method::GET | Method::HEAD) {
+            scopes.read
+        } else {
+            scopes.write
+        };
+        if !granted.contains(&needed) {
+            return Err(AppError::Forbidden);
+        }
+    }
+
+    Ok(next.run(req).await)
+}
+
+/// For routes API keys must never reach, such as account security and key
+/// management. Must run inside `auth_guard`.
+pub async fn require_session(req: Request, next: Next) -> Result<Response, AppError> {
+    let user = req
+        .extensions()
+        .get::<AuthUser>()
+        .ok_or(AppError::Unauthorized)?;
+
+    if user.api_key_scopes.is_some() {
+        return Err(AppError::Forbidden);
+    }
 
     Ok(next.run(req).await)
 }
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::{to_bytes, Body},
+        http::{Request, StatusCode},
+        Router,
+    };
+    use serde_json::{json, Value};
+    use sqlx::PgPool;
+    use tokio::sync::mpsc;
+    use tower::ServiceExt;
+
+    use super::*;
+    use crate::{
+        config::EmailVerificationPolicy,
+        model::User,
+        payments::mock::MockPaymentProvider,
+        utils::{hash::PasswordPolicy, jwt::JwtKeys},
+        web::auth::start_session,
+    };
+
+    struct TestApp {
+        app: Router,
+        pool: PgPool,
+        /// An access token for an admin's login session.
+        session_token: String,
+        user_id: Uuid,
+    }
+
+    impl TestApp {
+        async fn new(pool: PgPool) -> Self {
+            let password_policy = PasswordPolicy::from_env().unwrap();
+            let (webhooks, _) = mpsc::unbounded_channel();
+            let state = Arc::new(Config {
+                db_pool: pool.clone(),
+                jwt_keys: Arc::new(JwtKeys::ephemeral().unwrap()),
+                dummy_password_hash: password_policy.dummy_hash().unwrap(),
+                password_policy,
+                payment_provider: Arc::new(MockPaymentProvider::new(webhooks)),
+                email_verification: EmailVerificationPolicy::default(),
+                trusted_proxy_header: None,
+            });
+
+            let user = sqlx::query_as::<_, User>(
+                "INSERT INTO users (username, password_hash, roles)
+                 VALUES ('admin', 'unused', '{customer,admin}')
+                 RETURNING *",
+            )
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+            let session = start_session(&state, &user).await.unwrap();
+
+            Self {
+                app: crate::app(state),
+                pool,
+                session_token: session.token,
+                user_id: user.id,
+            }
+        }
+
+        async fn send(&self, request: Request<Body>) -> (StatusCode, Value) {
+            let response = self.app.clone().oneshot(request).await.unwrap();
+            let status = response.status();
+            let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            let body = serde_json::from_slice(&body).unwrap_or(Value::Null);
+            (status, body)
+        }
+
+        async fn with_session(&self, method: &str, uri: &str, body: Value) -> StatusCode {
+            self.send(json_request(method, uri, body, &self.session_token))
+                .await
+                .0
+        }
+
+        async fn with_key(&self, key: &str, method: &str, uri: &str, body: Value) -> StatusCode {
+            let request = Request::builder()
+                .method(method)
+                .uri(uri)
+                .header("x-api-key", key)
+                .header("content-type", "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap();
+            self.send(request).await.0
+        }
+
+        /// Creates a key through the API, returning its id and the full key.
+        async fn create_key(&self, scopes: &[&str]) -> (Uuid, String) {
+            let (status, body) = self
+                .send(json_request(
+                    "POST",
+                    "/api-keys",
+                    json!({ "name": "test", "scopes": scopes }),
+                    &self.session_token,
+                ))
+                .await;
+            assert_eq!(status, StatusCode::OK, "{}", body);
+
+            (
+                body["id"].as_str().unwrap().parse().unwrap(),
+                body["key"].as_str().unwrap().to_string(),
+            )
+        }
+    }
+
+    fn json_request(method: &str, uri: &str, body: Value, token: &str) -> Request<Body> {
+        Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("authorization", format!("Bearer {}", token))
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    fn new_post() -> Value {
+        json!({ "title": "Hello", "body": "World" })
+    }
+
+    #[sqlx::test]
+    async fn read_scope_does_not_allow_writes(pool: PgPool) {
+        let app = TestApp::new(pool).await;
+        let (_, read_key) = app.create_key(&["posts:read"]).await;
+        let (_, write_key) = app.create_key(&["posts:write"]).await;
+
+        assert_eq!(
+            app.with_key(&read_key, "GET", "/posts", Value::Null).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            app.with_key(&read_key, "POST", "/posts", new_post()).await,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(
+            app.with_key(&write_key, "POST", "/posts", new_post()).await,
+            StatusCode::OK
+        );
+        // Write scopes do not imply read scopes
+        assert_eq!(
+            app.with_key(&write_key, "GET", "/posts", Value::Null).await,
+            StatusCode::FORBIDDEN
+        );
+    }
+
+    #[sqlx::test]
+    async fn keys_cannot_reach_session_only_routes(pool: PgPool) {
+        let app = TestApp::new(pool).await;
+        let (_, key) = app.create_key(&["posts:read", "posts:write"]).await;
+        let admin_roles = format!("/admin/users/{}/roles", app.user_id);
+
+        for (method, uri, body) in [
+            ("GET", "/me", Value::Null),
+            ("GET", "/api-keys", Value::Null),
+            (
+                "POST",
+                "/api-keys",
+                json!({ "name": "escalate", "scopes": ["orders:write"] }),
+            ),
+            ("GET", admin_roles.as_str(), Value::Null),
+        ] {
+            assert_eq!(
+                app.with_key(&key, method, uri, body.clone()).await,
+                StatusCode::FORBIDDEN,
+                "{} {} with an API key",
+                method,
+                uri
+            );
+            assert!(
+                app.with_session(method, uri, body).await.is_success(),
+                "{} {} with a session",
+                method,
+                uri
+            );
+        }
+    }
+
+    #[sqlx::test]
+    async fn revoked_keys_are_unauthorized(pool: PgPool) {
+        let app = TestApp::new(pool).await;
+        let (key_id, key) = app.create_key(&["posts:read"]).await;
+        assert_eq!(
+            app.with_key(&key, "GET", "/posts", Value::Null).await,
+            StatusCode::OK
+        );
+
+        let revoke = app
+            .with_session("DELETE", &format!("/api-keys/{}", key_id), Value::Null)
+            .await;
+        assert!(revoke.is_success());
+
+        assert_eq!(
+            app.with_key(&key, "GET", "/posts", Value::Null).await,
+            StatusCode::UNAUTHORIZED
+        );
+    }
+
+    #[sqlx::test]
+    async fn expired_keys_are_unauthorized(pool: PgPool) {
+        let app = TestApp::new(pool).await;
+        let (key_id, key) = app.create_key(&["posts:read"]).await;
+
+        sqlx::query("UPDATE api_keys SET expires_at = NOW() - INTERVAL '1 second' WHERE id = $1")
+            .bind(key_id)
+            .execute(&app.pool)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            app.with_key(&key, "GET", "/posts", Value::Null).await,
+            StatusCode::UNAUTHORIZED
+        );
+    }
+
+    #[sqlx::test]
+    async fn malformed_and_unknown_keys_are_unauthorized(pool: PgPool) {
+        let app = TestApp::new(pool).await;
+        let (_, key) = app.create_key(&["posts:read"]).await;
+        let wrong_secret = format!("{}x", key);
+
+        for key in ["rk_", "rk_nounderscore", "not_a_key", wrong_secret.as_str()] {
+            assert_eq!(
+                app.with_key(key, "GET", "/posts", Value::Null).await,
+                StatusCode::UNAUTHORIZED,
+                "{}",
+                key
+            );
+        }
+    }
+}
@@ -0,0 +1,236 @@
+use std::collections::HashMap;
+
+use axum::{
+    extract::{Path, Query},
+    response::sse::{Event, KeepAlive, Sse},
+    Extension, Json,
+};
+use chrono::{DateTime, Utc};
+use tokio_stream::{Stream, StreamExt};
+use tonic::Code;
+
+use crate::{
+    dtos::{
+        DeliveryAttemptResponse, ExportedNotification, MarkNotificationsReadRequest,
+        MarkNotificationsReadResponse, NotificationQuery, NotificationResponse,
+        PreviewTemplateRequest, TemplatePreviewResponse, UnreadCountResponse,
+    },
+    error::AppError,
+    grpc_client::{
+        self,
+        notification::{DeliveryAttempt, Notification},
+    },
+};
+use uuid::Uuid;
+
+pub async fn get_notifications(
+    Extension(user_id): Extension<Uuid>,
+    Query(query): Query<NotificationQuery>,
+) -> Result<Json<Vec<NotificationResponse>>, AppError> {
+    let notifications = grpc_client::list_notifications(
+        &user_id.to_string(),
+        query.unread,
+        query.limit.unwrap_or_default(),
+        query.offset.unwrap_or_default(),
+    )
+    .await
+    .map_err(unavailable)?;
+
+    let response = notifications
+        .into_iter()
+        .map(to_notification_response)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(Json(response))
+}
+
+pub async fn get_unread_count(
+    Extension(user_id): Extension<Uuid>,
+) -> Result<Json<UnreadCountResponse>, AppError> {
+    let unread = grpc_client::count_unread_notifications(&user_id.to_string())
+        .await
+        .map_err(unavailable)?;
+
+    Ok(Json(UnreadCountResponse { unread }))
+}
+
+pub async fn mark_read(
+    Extension(user_id): Extension<Uuid>,
+    Json(payload): Json<MarkNotificationsReadRequest>,
+) -> Result<Json<MarkNotificationsReadResponse>, AppError> {
+    if !payload.all && payload.ids.is_empty() {
+        return Err(AppError::BadRequest(
+            "Provide notification ids or set all".to_string(),
+        ));
+    }
+
+    let ids = payload.ids.iter().map(Uuid::to_string).collect();
+    let updated = grpc_client::mark_notifications_read(&user_id.to_string(), ids, payload.all)
+        .await
+        .map_err(unavailable)?;
+
+    Ok(Json(MarkNotificationsReadResponse { updated }))
+}
+
+/// Pushes each new notification to the client as a server-sent event named
+/// `notification`. Missed events are not replayed; clients fetch the inbox
+/// after reconnecting.
+pub async fn stream_notifications(
+    Extension(user_id): Extension<Uuid>,
+) -> Result<Sse<impl Stream<Item = Result<Event, axum::Error>>>, AppError> {
+    let notifications = grpc_client::subscribe_notifications(&user_id.to_string())
+        .await
+        .map_err(unavailable)?;
+
+    let events = notifications.map(|received| {
+        let notification = received.map_err(axum::Error::new)?;
+        let notification = to_notification_response(notification)
+            .map_err(|_| axum::Error::new("Invalid notification"))?;
+
+        Event::default()
+            .event("notification")
+            .id(notification.id.to_string())
+            .json_data(notification)
+    });
+
+    Ok(Sse::new(events).keep_alive(KeepAlive::default()))
+}
+
+/// Recent delivery attempts across every channel for a user, newest first,
+/// so admins can see why a message never arrived.
+pub async fn get_delivery_attempts(
+    Path(user_id): Path<Uuid>,
+    Query(query): Query<NotificationQuery>,
+) -> Result<Json<Vec<DeliveryAttemptResponse>>, AppError> {
+    let attempts =
+        grpc_client::list_delivery_attempts(&user_id.to_string(), query.limit.unwrap_or_default())
+            .await
+            .map_err(unavailable)?;
+
+    let response = attempts
+        .into_iter()
+        .map(to_delivery_attempt_response)
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(Json(response))
+}
+
+/// Renders a notification template with sample data so admins can check
+/// wording and translations before they reach users.
+pub async fn preview_template(
+    Json(payload): Json<PreviewTemplateRequest>,
+) -> Result<Json<TemplatePreviewResponse>, AppError> {
+    let preview = grpc_client::preview_template(
+        &payload.kind,
+        &payload.channel,
+        payload.locale.as_deref().unwrap_or_default(),
+        payload.variables,
+    )
+    .await
+    .map_err(|e| match e.downcast_ref::<tonic::Status>() {
+        // Unknown kind or channel, or a template the variables don't satisfy
+        Some(status) if matches!(status.code(), Code::NotFound | Code::InvalidArgument) => {
+            AppError::BadRequest(status.message().to_string())
+        }
+        _ => unavailable(e),
+    })?;
+
+    Ok(Json(TemplatePreviewResponse {
+        locale: preview.locale,
+        subject: preview.subject,
+        text: preview.text,
+        html: preview.html,
+    }))
+}
+
+/// Every notification stored for the user, with its delivery attempts, for
+/// data exports.
+pub async fn fetch_all_notifications(user_id: Uuid) -> Result<Vec<ExportedNotification>, AppError> {
+    let export = grpc_client::export_user_notifications(&user_id.to_string())
+        .await
+        .map_err(unavailable)?;
+
+    let mut attempts = HashMap::<Uuid, Vec<DeliveryAttemptResponse>>::new();
+    for attempt in export.attempts {
+        let attempt = to_delivery_attempt_response(attempt)?;
+        attempts
+            .entry(attempt.notification_id)
+            .or_default()
+            .push(attempt);
+    }
+
+    export
+        .notifications
+        .into_iter()
+        .map(|notification| {
+            let in_inbox = notification.in_inbox;
+            let notification = to_notification_response(notification)?;
+            Ok(ExportedNotification {
+                delivery_attempts: attempts.remove(&notification.id).unwrap_or_default(),
+                notification,
+                in_inbox,
+            })
+        })
+        .collect()
+}
+
+fn to_notification_response(notification: Notification) -> Result<NotificationResponse, AppError> {
+    let invalid = |field: &str| {
+        tracing::error!("Notification {} has an invalid {}", notification.id, field);
+        AppError::InternalServerError
+    };
+
+    let id = Uuid::parse_str(&notification.id).map_err(|_| invalid("id"))?;
+    let created_at =
+        parse_timestamp(&notification.created_at).ok_or_else(|| invalid("created_at"))?;
+    let read_at = match &notification.read_at {
+        Some(read_at) => Some(parse_timestamp(read_at).ok_or_else(|| invalid("read_at"))?),
+        None => None,
+    };
+
+    Ok(NotificationResponse {
+        id,
+        kind: notification.kind,
+        title: notification.title,
+        body: notification.body,
+        created_at,
+        read_at,
+    })
+}
+
+fn to_delivery_attempt_response(
+    attempt: DeliveryAttempt,
+) -> Result<DeliveryAttemptResponse, AppError> {
+    let invalid = |field: &str| {
+        tracing::error!("Delivery attempt {} has an invalid {}", attempt.id, field);
+        AppError::InternalServerError
+    };
+
+    let id = Uuid::parse_str(&attempt.id).map_err(|_| invalid("id"))?;
+    let notification_id =
+        Uuid::parse_str(&attempt.notification_id).map_err(|_| invalid("notification_id"))?;
+    let attempted_at =
+        parse_timestamp(&attempt.attempted_at).ok_or_else(|| invalid("attempted_at"))?;
+
+    Ok(DeliveryAttemptResponse {
+        id,
+        notification_id,
+        kind: attempt.kind,
+        channel: attempt.channel,
+        attempt: attempt.attempt,
+        status: attempt.status,
+        error: attempt.error,
+        attempted_at,
+    })
+}
+
+fn parse_timestamp(value: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(value)
+        .ok()
+        .map(|timestamp| timestamp.with_timezone(&Utc))
+}
+
+fn unavailable(e: Box<dyn std::error::Error>) -> AppError {
+    tracing::error!("Notification service request failed: {}", e);
+    AppError::ServiceUnavailable("Notifications are unavailable right now".to_string())
+}
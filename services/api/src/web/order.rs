@@ -0,0 +1,504 @@
+use std::{collections::BTreeMap, sync::Arc};
+
+use axum::{
+    extract::{Path, Query, State},
+    Extension, Json,
+};
+use rust_decimal::Decimal;
+use sqlx::{PgConnection, PgPool, Postgres, QueryBuilder};
+
+use crate::{
+    config::Config,
+    dtos::{
+        CancelOrderRequest, CreateOrderRequest, OrderItemResponse, OrderResponse,
+        OrderStatusHistoryResponse, Page, PaginationRequest, UpdateOrderStatusRequest,
+    },
+    error::AppError,
+    model::{Order, OrderItem, OrderStatus, OrderStatusHistory, Product},
+    payments::{charge_order, complete_refund, refund_captured_payment},
+    utils::pagination::{finish_page, page_size, Cursor, PageStart},
+    web::{cart::get_or_create_cart, mw::AuthUser},
+};
+use uuid::Uuid;
+
+pub async fn create_order(
+    State(state): State<Arc<Config>>,
+    Extension(user_id): Extension<Uuid>,
+    Json(payload): Json<CreateOrderRequest>,
+) -> Result<Json<OrderResponse>, AppError> {
+    // Merge duplicate lines and keep them sorted by product id, so concurrent
+    // checkouts always lock product rows in the same order and cannot deadlock.
+    let mut requested: BTreeMap<Uuid, i32> = BTreeMap::new();
+    let cart_id = match payload.items {
+        Some(items) => {
+            for item in items {
+                if item.quantity <= 0 {
+                    return Err(AppError::BadRequest(
+                        "Quantity must be greater than zero".to_string(),
+                    ));
+                }
+                add_quantity(&mut requested, item.product_id, item.quantity)?;
+            }
+            None
+        }
+        None => Some(get_or_create_cart(&state.db_pool, user_id).await?.id),
+    };
+
+    let mut tx = state.db_pool.begin().await?;
+
+    // The cart is read under lock in the same transaction that empties it, so
+    // a concurrent quantity change waits and items added meanwhile are kept
+    if let Some(cart_id) = cart_id {
+        let lines = sqlx::query_as::<_, (Uuid, i32)>(
+            "SELECT product_id, quantity FROM cart_items WHERE cart_id = $1 ORDER BY product_id FOR UPDATE",
+        )
+        .bind(cart_id)
+        .fetch_all(&mut *tx)
+        .await?;
+
+        for (product_id, quantity) in lines {
+            add_quantity(&mut requested, product_id, quantity)?;
+        }
+    }
+
+    if requested.is_empty() {
+        return Err(AppError::BadRequest("No items to order".to_string()));
+    }
+
+    let product_ids: Vec<Uuid> = requested.keys().copied().collect();
+    let products = sqlx::query_as::<_, Product>(
+        "SELECT * FROM products WHERE id = ANY($1) AND deleted_at IS NULL ORDER BY id FOR UPDATE",
+    )
+    .bind(&product_ids)
+    .fetch_all(&mut *tx)
+    .await?;
+
+    if products.len() != requested.len() {
+        return Err(AppError::BadRequest("Product not found".to_string()));
+    }
+
+    let mut total_amount = Decimal::ZERO;
+    for product in &products {
+        let quantity = requested[&product.id];
+        if quantity > product.stock_quantity {
+            return Err(AppError::BadRequest(format!(
+                "Only {} of {} in stock",
+                product.stock_quantity, product.name
+            )));
+        }
+        total_amount += product.price * Decimal::from(quantity);
+    }
+
+    let order = sqlx::query_as::<_, Order>(
+        "INSERT INTO orders (user_id, total_amount) VALUES ($1, $2) RETURNING *",
+    )
+    .bind(user_id)
+    .bind(total_amount)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    record_status_change(&mut tx, order.id, None, order.status, Some(user_id), None).await?;
+
+    let mut items = Vec::with_capacity(products.len());
+    for product in &products {
+        let quantity = requested[&product.id];
+
+        sqlx::query(
+            "UPDATE products SET stock_quantity = stock_quantity - $2, updated_at = NOW() WHERE id = $1",
+        )
+        .bind(product.id)
+        .bind(quantity)
+        .execute(&mut *tx)
+        .await?;
+
+        let item = sqlx::query_as::<_, OrderItem>(
+            "INSERT INTO order_items (order_id, product_id, product_name, unit_price, quantity, line_total)
+             VALUES ($1, $2, $3, $4, $5, $6) RETURNING *",
+        )
+        .bind(order.id)
+        .bind(product.id)
+        .bind(&product.name)
+        .bind(product.price)
+        .bind(quantity)
+        .bind(product.price * Decimal::from(quantity))
+        .fetch_one(&mut *tx)
+        .await?;
+
+        items.push(item);
+    }
+
+    if let Some(cart_id) = cart_id {
+        sqlx::query("DELETE FROM cart_items WHERE cart_id = $1 AND product_id = ANY($2)")
+            .bind(cart_id)
+            .bind(&product_ids)
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    tx.commit().await?;
+
+    // Stock is already reserved; a failed payment cancels the order again
+    let order = charge_order(&state, order, &payload.payment_method).await?;
+
+    Ok(Json(to_order_response(order, items)))
+}
+
+pub async fn get_orders(
+    State(state): State<Arc<Config>>,
+    Extension(user_id): Extension<Uuid>,
+    Query(pagination): Query<PaginationRequest>,
+) -> Result<Json<Page<OrderResponse>>, AppError> {
+    let limit = page_size(pagination.limit);
+    let start = PageStart::from_request(pagination.cursor.as_deref(), pagination.offset)?;
+
+    let mut query = QueryBuilder::<Postgres>::new("SELECT * FROM orders WHERE user_id = ");
+    query.push_bind(user_id);
+    if let PageStart::After(cursor) = start {
+        query
+            .push(" AND (created_at, id) < (")
+            .push_bind(cursor.created_at)
+            .push(", ")
+            .push_bind(cursor.id)
+            .push(")");
+    }
+    query
+        .push(" ORDER BY created_at DESC, id DESC LIMIT ")
+        .push_bind(limit + 1);
+    if let PageStart::Offset(offset) = start {
+        query.push(" OFFSET ").push_bind(offset);
+    }
+
+    let orders = query
+        .build_query_as::<Order>()
+        .fetch_all(&state.db_pool)
+        .await?;
+
+    let (orders, next_cursor) = finish_page(orders, limit, |o| Cursor {
+        created_at: o.created_at,
+        id: o.id,
+    });
+
+    let mut items = Vec::with_capacity(orders.len());
+    for order in orders {
+        let order_items = fetch_order_items(&state.db_pool, order.id).await?;
+        items.push(to_order_response(order, order_items));
+    }
+
+    Ok(Json(start.page(items, next_cursor)))
+}
+
+pub async fn get_order_by_id(
+    State(state): State<Arc<Config>>,
+    Extension(user): Extension<AuthUser>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<OrderResponse>, AppError> {
+    load_order_access(&state.db_pool, id, &user).await?;
+
+    let order = sqlx::query_as::<_, Order>("SELECT * FROM orders WHERE id = $1")
+        .bind(id)
+        .fetch_one(&state.db_pool)
+        .await?;
+
+    let items = fetch_order_items(&state.db_pool, order.id).await?;
+
+    Ok(Json(to_order_response(order, items)))
+}
+
+pub async fn update_order_status(
+    State(state): State<Arc<Config>>,
+    Extension(user): Extension<AuthUser>,
+    Path(id): Path<Uuid>,
+    Json(payload): Json<UpdateOrderStatusRequest>,
+) -> Result<Json<OrderResponse>, AppError> {
+    // Payment, cancellation and refunds drive these states themselves
+    if matches!(
+        payload.status,
+        OrderStatus::Paid | OrderStatus::Cancelled | OrderStatus::Refunded
+    ) {
+        return Err(AppError::BadRequest(format!(
+            "Orders cannot be marked {} directly",
+            payload.status
+        )));
+    }
+
+    let access = load_order_access(&state.db_pool, id, &user).await?;
+    if !access.can_manage_whole_order() {
+        return Err(AppError::Forbidden);
+    }
+
+    let mut tx = state.db_pool.begin().await?;
+    let order = transition_order(
+        &mut tx,
+        id,
+        payload.status,
+        Some(user.id),
+        payload.note.as_deref(),
+    )
+    .await?;
+    tx.commit().await?;
+
+    let items = fetch_order_items(&state.db_pool, order.id).await?;
+
+    Ok(Json(to_order_response(order, items)))
+}
+
+pub async fn cancel_order(
+    State(state): State<Arc<Config>>,
+    Extension(user): Extension<AuthUser>,
+    Path(id): Path<Uuid>,
+    Json(payload): Json<CancelOrderRequest>,
+) -> Result<Json<OrderResponse>, AppError> {
+    // The buyer may cancel, and so may a seller who sold everything on the
+    // order; cancelling refunds and restocks every item
+    let access = load_order_access(&state.db_pool, id, &user).await?;
+    if !access.is_buyer && !access.can_manage_whole_order() {
+        return Err(AppError::Forbidden);
+    }
+
+    let mut tx = state.db_pool.begin().await?;
+    let order = transition_order(
+        &mut tx,
+        id,
+        OrderStatus::Cancelled,
+        Some(user.id),
+        payload.reason.as_deref(),
+    )
+    .await?;
+    let refund = refund_captured_payment(&mut tx, id, Some(user.id)).await?;
+    tx.commit().await?;
+
+    // The order stays cancelled even if the provider refuses; the failed
+    // refund is on record for someone to follow up
+    if let Some(refund) = refund {
+        if let Err(e) = complete_refund(&state, refund.id).await {
+            tracing::error!("Failed to refund cancelled order {}: {:?}", id, e);
+        }
+    }
+
+    let items = fetch_order_items(&state.db_pool, order.id).await?;
+
+    Ok(Json(to_order_response(order, items)))
+}
+
+pub async fn get_order_history(
+    State(state): State<Arc<Config>>,
+    Extension(user): Extension<AuthUser>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<Vec<OrderStatusHistoryResponse>>, AppError> {
+    load_order_access(&state.db_pool, id, &user).await?;
+
+    let history = sqlx::query_as::<_, OrderStatusHistory>(
+        "SELECT * FROM order_status_history WHERE order_id = $1 ORDER BY created_at",
+    )
+    .bind(id)
+    .fetch_all(&state.db_pool)
+    .await?;
+
+    let response = history
+        .into_iter()
+        .map(|h| OrderStatusHistoryResponse {
+            from_status: h.from_status,
+            to_status: h.to_status,
+            changed_by: h.changed_by,
+            note: h.note,
+            created_at: h.created_at,
+        })
+        .collect();
+
+    Ok(Json(response))
+}
+
+/// Moves an order to `next` if the state machine allows it, restocking the
+/// items on cancellation and recording the change in the status history.
+///
+/// Runs on the caller's connection so it can share a transaction with any
+/// other work tied to the change; `changed_by` is `None` for system changes.
+pub async fn transition_order(
+    conn: &mut PgConnection,
+    order_id: Uuid,
+    next: OrderStatus,
+    changed_by: Option<Uuid>,
+    note: Option<&str>,
+) -> Result<Order, AppError> {
+    let order = sqlx::query_as::<_, Order>("SELECT * FROM orders WHERE id = $1 FOR UPDATE")
+        .bind(order_id)
+        .fetch_optional(&mut *conn)
+        .await?
+        .ok_or(AppError::BadRequest("Order not found".to_string()))?;
+
+    if !order.status.can_transition_to(next) {
+        return Err(AppError::InvalidStatusTransition {
+            from: order.status,
+            to: next,
+        });
+    }
+
+    // Units already put back by an earlier refund are not restocked twice
+    if next == OrderStatus::Cancelled {
+        sqlx::query(
+            "UPDATE products p
+             SET stock_quantity = p.stock_quantity + oi.quantity - oi.restocked_quantity,
+                 updated_at = NOW()
+             FROM order_items oi
+             WHERE oi.order_id = $1 AND p.id = oi.product_id",
+        )
+        .bind(order_id)
+        .execute(&mut *conn)
+        .await?;
+
+        sqlx::query("UPDATE order_items SET restocked_quantity = quantity WHERE order_id = $1")
+            .bind(order_id)
+            .execute(&mut *conn)
+            .await?;
+    }
+
+    let updated = sqlx::query_as::<_, Order>(
+        "UPDATE orders SET status = $2, updated_at = NOW() WHERE id = $1 RETURNING *",
+    )
+    .bind(order_id)
+    .bind(next)
+    .fetch_one(&mut *conn)
+    .await?;
+
+    record_status_change(conn, order_id, Some(order.status), next, changed_by, note).await?;
+
+    Ok(updated)
+}
+
+fn add_quantity(
+    requested: &mut BTreeMap<Uuid, i32>,
+    product_id: Uuid,
+    quantity: i32,
+) -> Result<(), AppError> {
+    let total = requested.entry(product_id).or_default();
+    *total = total
+        .checked_add(quantity)
+        .ok_or(AppError::BadRequest("Quantity is too large".to_string()))?;
+
+    Ok(())
+}
+
+async fn record_status_change(
+    conn: &mut PgConnection,
+    order_id: Uuid,
+    from: Option<OrderStatus>,
+    to: OrderStatus,
+    changed_by: Option<Uuid>,
+    note: Option<&str>,
+) -> Result<(), AppError> {
+    sqlx::query(
+        "INSERT INTO order_status_history (order_id, from_status, to_status, changed_by, note)
+         VALUES ($1, $2, $3, $4, $5)",
+    )
+    .bind(order_id)
+    .bind(from)
+    .bind(to)
+    .bind(changed_by)
+    .bind(note)
+    .execute(conn)
+    .await?;
+
+    Ok(())
+}
+
+/// How the calling user is related to an order.
+pub struct OrderAccess {
+    pub is_buyer: bool,
+    pub is_admin: bool,
+    /// The order's items the caller sold.
+    pub sold_items: Vec<Uuid>,
+    /// Whether the caller sold every item on the order.
+    pub sold_all: bool,
+}
+
+impl OrderAccess {
+    /// An admin, or a seller of at least one of the order's items.
+    pub fn can_manage(&self) -> bool {
+        self.is_admin || !self.sold_items.is_empty()
+    }
+
+    /// An admin, or the only seller on the order. Sellers sharing an order
+    /// cannot act on each other's items through it.
+    pub fn can_manage_whole_order(&self) -> bool {
+        self.is_admin || self.sold_all
+    }
+
+    pub fn can_manage_item(&self, order_item_id: Uuid) -> bool {
+        self.is_admin || self.sold_items.contains(&order_item_id)
+    }
+}
+
+/// Loads the caller's relationship to an order. Users who are neither the
+/// buyer, a seller of one of its items nor an admin get the same error as
+/// for a missing order, so order ids cannot be probed.
+pub async fn load_order_access(
+    pool: &PgPool,
+    order_id: Uuid,
+    user: &AuthUser,
+) -> Result<OrderAccess, AppError> {
+    let (is_buyer, sold_items, sold_by_others) = sqlx::query_as::<_, (bool, Vec<Uuid>, bool)>(
+        "SELECT o.user_id = $2,
+                ARRAY(
+                    SELECT oi.id FROM order_items oi
+                    JOIN products p ON p.id = oi.product_id
+                    WHERE oi.order_id = o.id AND p.user_id = $2
+                ),
+                EXISTS (
+                    SELECT 1 FROM order_items oi
+                    JOIN products p ON p.id = oi.product_id
+                    WHERE oi.order_id = o.id AND p.user_id <> $2
+                )
+         FROM orders o
+         WHERE o.id = $1",
+    )
+    .bind(order_id)
+    .bind(user.id)
+    .fetch_optional(pool)
+    .await?
+    .ok_or(AppError::BadRequest("Order not found".to_string()))?;
+
+    let access = OrderAccess {
+        is_buyer,
+        is_admin: user.is_admin(),
+        sold_all: !sold_items.is_empty() && !sold_by_others,
+        sold_items,
+    };
+    if !access.is_buyer && !access.can_manage() {
+        return Err(AppError::BadRequest("Order not found".to_string()));
+    }
+
+    Ok(access)
+}
+
+pub async fn fetch_order_items(pool: &PgPool, order_id: Uuid) -> Result<Vec<OrderItem>, AppError> {
+    let items = sqlx::query_as::<_, OrderItem>(
+        "SELECT * FROM order_items WHERE order_id = $1 ORDER BY product_name",
+    )
+    .bind(order_id)
+    .fetch_all(pool)
+    .await?;
+
+    Ok(items)
+}
+
+fn to_order_response(order: Order, items: Vec<OrderItem>) -> OrderResponse {
+    OrderResponse {
+        id: order.id,
+        status: order.status,
+        total_amount: order.total_amount,
+        refunded_amount: order.refunded_amount,
+        items: items
+            .into_iter()
+            .map(|item| OrderItemResponse {
+                id: item.id,
+                product_id: item.product_id,
+                product_name: item.product_name,
+                unit_price: item.unit_price,
+                quantity: item.quantity,
+                line_total: item.line_total,
+                refunded_quantity: item.refunded_quantity,
+            })
+            .collect(),
+        created_at: order.created_at,
+    }
+}
@@ -0,0 +1,138 @@
+use std::sync::Arc;
+
+use axum::{extract::State, http::StatusCode, Json};
+use chrono::{Duration, Utc};
+
+use crate::{
+    config::Config,
+    dtos::{ForgotPasswordRequest, ResetPasswordRequest},
+    error::AppError,
+    model::User,
+    utils::{
+        hash::hash_password,
+        token::{generate_token, hash_token},
+    },
+    web::auth::revoke_user_sessions,
+};
+use uuid::Uuid;
+
+const RESET_TOKEN_TTL_MINUTES: i64 = 30;
+
+/// Starts a password reset. The response is the same whether or not the
+/// username exists, and is sent before the username is even looked up:
+/// the lookup, the token and its delivery all happen in the background, so
+/// response times do not give the username away either.
+pub async fn forgot_password(
+    State(state): State<Arc<Config>>,
+    Json(payload): Json<ForgotPasswordRequest>,
+) -> Result<StatusCode, AppError> {
+    tokio::spawn(async move {
+        if let Err(e) = start_password_reset(&state, &payload.username).await {
+            tracing::error!("Failed to start password reset: {:?}", e);
+        }
+    });
+
+    Ok(StatusCode::ACCEPTED)
+}
+
+async fn start_password_reset(state: &Config, username: &str) -> Result<(), AppError> {
+    let user =
+        sqlx::query_as::<_, User>("SELECT * FROM users WHERE username = $1 AND deleted_at IS NULL")
+            .bind(username)
+            .fetch_optional(&state.db_pool)
+            .await?;
+
+    let Some(user) = user else {
+        return Ok(());
+    };
+    // The token is as good as the password, so it only goes to an address
+    // the user has proven they own
+    let email = match &user.email {
+        Some(email) if user.email_verified_at.is_some() => email.clone(),
+        _ => return Ok(()),
+    };
+
+    let token = generate_token();
+    let expires_at = Utc::now() + Duration::minutes(RESET_TOKEN_TTL_MINUTES);
+
+    let mut tx = state.db_pool.begin().await?;
+
+    // Only the most recently requested token is valid
+    sqlx::query("DELETE FROM password_reset_tokens WHERE user_id = $1 AND used_at IS NULL")
+        .bind(user.id)
+        .execute(&mut *tx)
+        .await?;
+
+    sqlx::query(
+        "INSERT INTO password_reset_tokens (user_id, token_hash, expires_at) VALUES ($1, $2, $3)",
+    )
+    .bind(user.id)
+    .bind(hash_token(&token))
+    .bind(expires_at)
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    let notification_result = crate::grpc_client::send_password_reset_notification(
+        &user.id.to_string(),
+        &user.username,
+        &token,
+        &expires_at.to_rfc3339(),
+        &email,
+        user.locale.as_deref().unwrap_or_default(),
+    )
+    .await;
+
+    if let Err(e) = notification_result {
+        tracing::warn!("Failed to send password reset notification: {}", e);
+    }
+
+    Ok(())
+}
+
+/// Sets a new password using a reset token. The token is consumed and every
+/// existing session and API key is revoked, so anyone who got into the
+/// account is locked out again.
+pub async fn reset_password(
+    State(state): State<Arc<Config>>,
+    Json(payload): Json<ResetPasswordRequest>,
+) -> Result<StatusCode, AppError> {
+    let invalid = || AppError::BadRequest("Invalid or expired reset token".to_string());
+
+    let mut tx = state.db_pool.begin().await?;
+
+    let (token_id, user_id) = sqlx::query_as::<_, (Uuid, Uuid)>(
+        "SELECT id, user_id FROM password_reset_tokens
+         WHERE token_hash = $1 AND used_at IS NULL AND expires_at > NOW()
+         FOR UPDATE",
+    )
+    .bind(hash_token(&payload.token))
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or_else(invalid)?;
+
+    let password_hash = hash_password(&state.password_policy, &payload.new_password).await?;
+
+    sqlx::query("UPDATE users SET password_hash = $2 WHERE id = $1")
+        .bind(user_id)
+        .bind(&password_hash)
+        .execute(&mut *tx)
+        .await?;
+
+    sqlx::query("UPDATE password_reset_tokens SET used_at = NOW() WHERE id = $1")
+        .bind(token_id)
+        .execute(&mut *tx)
+        .await?;
+
+    sqlx::query("UPDATE api_keys SET revoked_at = NOW() WHERE user_id = $1 AND revoked_at IS NULL")
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+
+    revoke_user_sessions(&mut tx, user_id).await?;
+
+    tx.commit().await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
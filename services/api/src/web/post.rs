@@ -1,13 +1,15 @@
 use crate::{
     config::Config,
-    dtos::{CreatePostRequest, PaginationRequest, PostResponse},
+    dtos::{CreatePostRequest, Page, PaginationRequest, PostResponse},
     error::AppError,
     model::Post,
+    utils::pagination::{finish_page, page_size, Cursor, PageStart},
 };
 use axum::{
     Json,
     extract::{Extension, Path, Query, State},
 };
+use sqlx::{Postgres, QueryBuilder};
 use std::sync::Arc;
 use uuid::Uuid;
 
@@ -19,7 +21,7 @@ pub async fn create_post(
     let post = sqlx::query_as::<_, Post>(
         "INSERT INTO posts (user_id, title, body) VALUES ($1, $2, $3) RETURNING *",
     )
-    .bind(&user_id)
+    .bind(user_id)
     .bind(&payload.title)
     .bind(&payload.body)
     .fetch_one(&state.db_pool)
@@ -37,19 +39,37 @@ pub async fn create_post(
 pub async fn get_posts(
     State(state): State<Arc<Config>>,
     Query(pagination): Query<PaginationRequest>,
-) -> Result<Json<Vec<PostResponse>>, AppError> {
-    let limit = pagination.limit.unwrap_or(10);
-    let offset = pagination.offset.unwrap_or(0);
+) -> Result<Json<Page<PostResponse>>, AppError> {
+    let limit = page_size(pagination.limit);
+    let start = PageStart::from_request(pagination.cursor.as_deref(), pagination.offset)?;
 
-    let posts = sqlx::query_as::<_, Post>(
-        "SELECT * FROM posts ORDER BY created_at DESC LIMIT $1 OFFSET $2",
-    )
-    .bind(limit)
-    .bind(offset)
-    .fetch_all(&state.db_pool)
-    .await?;
+    let mut query = QueryBuilder::<Postgres>::new("SELECT * FROM posts");
+    if let PageStart::After(cursor) = start {
+        query
+            .push(" WHERE (created_at, id) < (")
+            .push_bind(cursor.created_at)
+            .push(", ")
+            .push_bind(cursor.id)
+            .push(")");
+    }
+    query
+        .push(" ORDER BY created_at DESC, id DESC LIMIT ")
+        .push_bind(limit + 1);
+    if let PageStart::Offset(offset) = start {
+        query.push(" OFFSET ").push_bind(offset);
+    }
+
+    let posts = query
+        .build_query_as::<Post>()
+        .fetch_all(&state.db_pool)
+        .await?;
+
+    let (posts, next_cursor) = finish_page(posts, limit, |p| Cursor {
+        created_at: p.created_at,
+        id: p.id,
+    });
 
-    let response = posts
+    let items = posts
         .into_iter()
         .map(|p| PostResponse {
             id: p.id,
@@ -60,7 +80,7 @@ pub async fn get_posts(
         })
         .collect();
 
-    Ok(Json(response))
+    Ok(Json(start.page(items, next_cursor)))
 }
 
 pub async fn get_post_by_id(
@@ -68,7 +88,7 @@ pub async fn get_post_by_id(
     Path(id): Path<Uuid>,
 ) -> Result<Json<PostResponse>, AppError> {
     let post = sqlx::query_as::<_, Post>("SELECT * FROM posts WHERE id = $1")
-        .bind(&id)
+        .bind(id)
         .fetch_optional(&state.db_pool)
         .await?
         .ok_or(AppError::BadRequest("Post not found".to_string()))?;
@@ -0,0 +1,156 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::State,
+    http::header,
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use chrono::Utc;
+use sqlx::PgPool;
+
+use crate::{
+    config::Config,
+    dtos::{ExportedOrder, UserDataExport},
+    error::AppError,
+    model::{
+        ApiKey, Cart, LoginAttempt, Order, OrderItem, OrderStatusHistory, Post, Product, Refund,
+        SellerFollow, Session, User,
+    },
+    web::{cart::fetch_cart_lines, mw::AuthUser, notification::fetch_all_notifications},
+};
+use uuid::Uuid;
+
+/// Downloads everything stored about the caller.
+pub async fn export_my_data(
+    State(state): State<Arc<Config>>,
+    Extension(user): Extension<AuthUser>,
+) -> Result<Response, AppError> {
+    let export = collect_user_data(&state.db_pool, user.id).await?;
+
+    Ok(export_response(export))
+}
+
+/// Gathers every row tied to `user_id`, plus the user's notifications and
+/// their delivery attempts from the notification service. Orders carry their
+/// items, history and refunds so the archive reads on its own.
+pub async fn collect_user_data(pool: &PgPool, user_id: Uuid) -> Result<UserDataExport, AppError> {
+    let profile = sqlx::query_as::<_, User>("SELECT * FROM users WHERE id = $1")
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await?
+        .ok_or(AppError::BadRequest("User not found".to_string()))?;
+
+    let posts =
+        sqlx::query_as::<_, Post>("SELECT * FROM posts WHERE user_id = $1 ORDER BY created_at")
+            .bind(user_id)
+            .fetch_all(pool)
+            .await?;
+
+    let products = sqlx::query_as::<_, Product>(
+        "SELECT * FROM products WHERE user_id = $1 ORDER BY created_at",
+    )
+    .bind(user_id)
+    .fetch_all(pool)
+    .await?;
+
+    let cart = sqlx::query_as::<_, Cart>("SELECT * FROM carts WHERE user_id = $1")
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await?;
+    let cart = match cart {
+        Some(cart) => fetch_cart_lines(pool, cart.id).await?,
+        None => Vec::new(),
+    };
+
+    let orders =
+        sqlx::query_as::<_, Order>("SELECT * FROM orders WHERE user_id = $1 ORDER BY created_at")
+            .bind(user_id)
+            .fetch_all(pool)
+            .await?;
+
+    let mut exported_orders = Vec::with_capacity(orders.len());
+    for order in orders {
+        let items = sqlx::query_as::<_, OrderItem>("SELECT * FROM order_items WHERE order_id = $1")
+            .bind(order.id)
+            .fetch_all(pool)
+            .await?;
+        let status_history = sqlx::query_as::<_, OrderStatusHistory>(
+            "SELECT * FROM order_status_history WHERE order_id = $1 ORDER BY created_at",
+        )
+        .bind(order.id)
+        .fetch_all(pool)
+        .await?;
+        let refunds = sqlx::query_as::<_, Refund>(
+            "SELECT * FROM refunds WHERE order_id = $1 ORDER BY created_at",
+        )
+        .bind(order.id)
+        .fetch_all(pool)
+        .await?;
+
+        exported_orders.push(ExportedOrder {
+            order,
+            items,
+            status_history,
+            refunds,
+        });
+    }
+
+    let sessions = sqlx::query_as::<_, Session>(
+        "SELECT * FROM sessions WHERE user_id = $1 ORDER BY created_at",
+    )
+    .bind(user_id)
+    .fetch_all(pool)
+    .await?;
+
+    let api_keys = sqlx::query_as::<_, ApiKey>(
+        "SELECT * FROM api_keys WHERE user_id = $1 ORDER BY created_at",
+    )
+    .bind(user_id)
+    .fetch_all(pool)
+    .await?;
+
+    let login_attempts = sqlx::query_as::<_, LoginAttempt>(
+        "SELECT * FROM login_attempts
+         WHERE user_id = $1 OR username = $2
+         ORDER BY created_at",
+    )
+    .bind(user_id)
+    .bind(&profile.username)
+    .fetch_all(pool)
+    .await?;
+
+    let following = sqlx::query_as::<_, SellerFollow>(
+        "SELECT * FROM seller_follows WHERE follower_id = $1 ORDER BY created_at",
+    )
+    .bind(user_id)
+    .fetch_all(pool)
+    .await?;
+
+    let notifications = fetch_all_notifications(user_id).await?;
+
+    Ok(UserDataExport {
+        exported_at: Utc::now(),
+        profile,
+        posts,
+        products,
+        cart,
+        orders: exported_orders,
+        sessions,
+        api_keys,
+        login_attempts,
+        following,
+        notifications,
+    })
+}
+
+/// Serves an export as a JSON file download.
+pub fn export_response(export: UserDataExport) -> Response {
+    let filename = format!(
+        "attachment; filename=\"user-{}-{}.json\"",
+        export.profile.id,
+        export.exported_at.format("%Y%m%d%H%M%S")
+    );
+
+    ([(header::CONTENT_DISPOSITION, filename)], Json(export)).into_response()
+}
@@ -2,36 +2,50 @@ use std::sync::Arc;
 
 use axum::{
     extract::{Path, Query, State},
+    http::StatusCode,
     Extension, Json,
 };
 
 use crate::{
     config::Config,
-    dtos::{CreateProductRequest, PaginationRequest, ProductResponse},
+    dtos::{
+        CreateProductRequest, Page, ProductListRequest, ProductResponse, ProductSearchResponse,
+        ProductSort, SearchProductsRequest, UpdateProductRequest,
+    },
     error::AppError,
-    model::{Product, User},
+    model::{Product, ProductSearchHit, User},
+    utils::pagination::{finish_page, page_size, Cursor, PageStart},
+    web::{category::find_category, follow::notify_followers, mw::AuthUser},
 };
+use sqlx::{PgPool, Postgres, QueryBuilder};
 use uuid::Uuid;
 
+/// Delimit matched terms in `ts_headline` output until it is escaped.
+const HIGHLIGHT_START: char = '\u{2}';
+const HIGHLIGHT_STOP: char = '\u{3}';
+
 pub async fn create_product(
     State(state): State<Arc<Config>>,
     Extension(user_id): Extension<Uuid>,
     Json(payload): Json<CreateProductRequest>,
 ) -> Result<Json<ProductResponse>, AppError> {
+    // An unknown category would otherwise surface as a foreign key error
+    find_category(&state.db_pool, payload.category_id).await?;
+
     let product = sqlx::query_as::<_, Product>(
         "INSERT INTO products (user_id, category_id, name, description, price, stock_quantity) VALUES ($1, $2, $3, $4, $5, $6) RETURNING *",
     )
-    .bind(&user_id)
-    .bind(&payload.category_id)
+    .bind(user_id)
+    .bind(payload.category_id)
     .bind(&payload.name)
     .bind(&payload.description)
-    .bind(&payload.price)
-    .bind(&payload.stock_quantity)
+    .bind(payload.price)
+    .bind(payload.stock_quantity)
     .fetch_one(&state.db_pool)
     .await?;
 
     let user = sqlx::query_as::<_, User>("SELECT * FROM users WHERE id = $1")
-        .bind(&user_id)
+        .bind(user_id)
         .fetch_optional(&state.db_pool)
         .await?
         .ok_or(AppError::BadRequest("User not found".to_string()))?;
@@ -43,6 +57,8 @@ pub async fn create_product(
         &user_id.to_string(),
         &product.name,
         &user.username,
+        user.email.as_deref().unwrap_or_default(),
+        user.locale.as_deref().unwrap_or_default(),
     )
     .await;
 
@@ -51,6 +67,8 @@ pub async fn create_product(
         tracing::warn!("Failed to send notification: {}", e);
     }
 
+    notify_followers(state.db_pool.clone(), user, product.name.clone());
+
     Ok(Json(ProductResponse {
         id: product.id,
         category_id: product.category_id,
@@ -63,20 +81,105 @@ pub async fn create_product(
 
 pub async fn get_products(
     State(state): State<Arc<Config>>,
-    Query(pagination): Query<PaginationRequest>,
-) -> Result<Json<Vec<ProductResponse>>, AppError> {
-    let limit = pagination.limit.unwrap_or(10);
-    let offset = pagination.offset.unwrap_or(0);
+    Query(filters): Query<ProductListRequest>,
+) -> Result<Json<Page<ProductResponse>>, AppError> {
+    let limit = page_size(filters.limit);
+    let start = PageStart::from_request(filters.cursor.as_deref(), filters.offset)?;
 
-    let products = sqlx::query_as::<_, Product>(
-        "SELECT * FROM products ORDER BY created_at DESC LIMIT $1 OFFSET $2",
-    )
-    .bind(limit)
-    .bind(offset)
-    .fetch_all(&state.db_pool)
-    .await?;
+    // Cursors encode (created_at, id), so they only make sense for that order
+    let keyset = matches!(filters.sort, ProductSort::Newest);
+    if !matches!(start, PageStart::Offset(_)) && !keyset {
+        return Err(AppError::BadRequest(
+            "cursor can only be used with the newest sort".to_string(),
+        ));
+    }
+
+    if let (Some(min), Some(max)) = (filters.min_price, filters.max_price) {
+        if min > max {
+            return Err(AppError::BadRequest(
+                "min_price cannot be greater than max_price".to_string(),
+            ));
+        }
+    }
+
+    let mut query =
+        QueryBuilder::<Postgres>::new("SELECT * FROM products WHERE deleted_at IS NULL");
+
+    if let Some(category_id) = filters.category_id {
+        if filters.include_descendants {
+            query
+                .push(
+                    " AND category_id IN (
+                        WITH RECURSIVE subtree AS (
+                            SELECT id FROM categories WHERE id = ",
+                )
+                .push_bind(category_id)
+                .push(
+                    " UNION
+                            SELECT c.id FROM categories c JOIN subtree s ON c.parent_id = s.id
+                        )
+                        SELECT id FROM subtree
+                    )",
+                );
+        } else {
+            query.push(" AND category_id = ").push_bind(category_id);
+        }
+    }
+    if let Some(min_price) = filters.min_price {
+        query.push(" AND price >= ").push_bind(min_price);
+    }
+    if let Some(max_price) = filters.max_price {
+        query.push(" AND price <= ").push_bind(max_price);
+    }
+    if filters.in_stock == Some(true) {
+        query.push(" AND stock_quantity > 0");
+    }
+    if let Some(seller_id) = filters.user_id {
+        query.push(" AND user_id = ").push_bind(seller_id);
+    }
+    if let Some(created_after) = filters.created_after {
+        query.push(" AND created_at >= ").push_bind(created_after);
+    }
+    if let Some(created_before) = filters.created_before {
+        query.push(" AND created_at < ").push_bind(created_before);
+    }
+    if let PageStart::After(cursor) = start {
+        query
+            .push(" AND (created_at, id) < (")
+            .push_bind(cursor.created_at)
+            .push(", ")
+            .push_bind(cursor.id)
+            .push(")");
+    }
+
+    // Sort keys come from a closed enum, never from user text; id breaks ties
+    // so pages stay stable
+    query.push(match filters.sort {
+        ProductSort::Newest => " ORDER BY created_at DESC, id DESC",
+        ProductSort::PriceAsc => " ORDER BY price ASC, id",
+        ProductSort::PriceDesc => " ORDER BY price DESC, id",
+        ProductSort::NameAsc => " ORDER BY name ASC, id",
+        ProductSort::NameDesc => " ORDER BY name DESC, id",
+        ProductSort::StockAsc => " ORDER BY stock_quantity ASC, id",
+        ProductSort::StockDesc => " ORDER BY stock_quantity DESC, id",
+    });
 
-    let response = products
+    query.push(" LIMIT ").push_bind(limit + 1);
+    if let PageStart::Offset(offset) = start {
+        query.push(" OFFSET ").push_bind(offset);
+    }
+
+    let products = query
+        .build_query_as::<Product>()
+        .fetch_all(&state.db_pool)
+        .await?;
+
+    let (products, next_cursor) = finish_page(products, limit, |p| Cursor {
+        created_at: p.created_at,
+        id: p.id,
+    });
+
+    let items = products
         .into_iter()
         .map(|p| ProductResponse {
             id: p.id,
@@ -88,18 +191,95 @@ pub async fn get_products(
         })
         .collect();
 
+    Ok(Json(start.page(items, next_cursor)))
+}
+
+pub async fn search_products(
+    State(state): State<Arc<Config>>,
+    Query(search): Query<SearchProductsRequest>,
+) -> Result<Json<Vec<ProductSearchResponse>>, AppError> {
+    let q = search.q.trim();
+    if q.is_empty() {
+        return Err(AppError::BadRequest("Search query is required".to_string()));
+    }
+
+    let limit = page_size(search.limit);
+    let offset = search.offset.unwrap_or(0).max(0);
+
+    // websearch_to_tsquery accepts free-form user input (quotes, OR, -term)
+    // without ever raising a syntax error. Matches are delimited with
+    // control characters rather than tags, since names and descriptions are
+    // seller text that still has to be HTML-escaped
+    let selection = format!(
+        "StartSel=\"{}\", StopSel=\"{}\"",
+        HIGHLIGHT_START, HIGHLIGHT_STOP
+    );
+    let hits = sqlx::query_as::<_, ProductSearchHit>(
+        "SELECT p.*,
+                ts_rank_cd(p.search_vector, query) AS rank,
+                ts_headline('english', p.name, query, $4 || ', HighlightAll=true') AS name_highlight,
+                ts_headline('english', p.description, query, $4 || ', MaxFragments=2, MaxWords=30, MinWords=10') AS snippet
+         FROM products p, websearch_to_tsquery('english', $1) AS query
+         WHERE p.search_vector @@ query AND p.deleted_at IS NULL
+         ORDER BY rank DESC, p.created_at DESC
+         LIMIT $2 OFFSET $3",
+    )
+    .bind(q)
+    .bind(limit)
+    .bind(offset)
+    .bind(&selection)
+    .fetch_all(&state.db_pool)
+    .await?;
+
+    let response = hits
+        .into_iter()
+        .map(|hit| ProductSearchResponse {
+            product: ProductResponse {
+                id: hit.product.id,
+                category_id: hit.product.category_id,
+                name: hit.product.name,
+                description: hit.product.description,
+                price: hit.product.price,
+                stock_quantity: hit.product.stock_quantity,
+            },
+            rank: hit.rank,
+            name_highlight: highlight_html(&hit.name_highlight),
+            snippet: highlight_html(&hit.snippet),
+        })
+        .collect();
+
     Ok(Json(response))
 }
 
+/// HTML-escapes a `ts_headline` result and turns its match delimiters into
+/// `<mark>` tags, so the only markup in it is ours.
+fn highlight_html(headline: &str) -> String {
+    let mut html = String::with_capacity(headline.len());
+    for c in headline.chars() {
+        match c {
+            HIGHLIGHT_START => html.push_str("<mark>"),
+            HIGHLIGHT_STOP => html.push_str("</mark>"),
+            '&' => html.push_str("&amp;"),
+            '<' => html.push_str("&lt;"),
+            '>' => html.push_str("&gt;"),
+            '"' => html.push_str("&quot;"),
+            '\'' => html.push_str("&#39;"),
+            c => html.push(c),
+        }
+    }
+    html
+}
+
 pub async fn get_product_by_id(
     State(state): State<Arc<Config>>,
     Path(id): Path<Uuid>,
 ) -> Result<Json<ProductResponse>, AppError> {
-    let product = sqlx::query_as::<_, Product>("SELECT * FROM products WHERE id = $1")
-        .bind(&id)
-        .fetch_optional(&state.db_pool)
-        .await?
-        .ok_or(AppError::BadRequest("Product not found".to_string()))?;
+    let product =
+        sqlx::query_as::<_, Product>("SELECT * FROM products WHERE id = $1 AND deleted_at IS NULL")
+            .bind(id)
+            .fetch_optional(&state.db_pool)
+            .await?
+            .ok_or(AppError::BadRequest("Product not found".to_string()))?;
 
     Ok(Json(ProductResponse {
         id: product.id,
@@ -110,3 +290,94 @@ pub async fn get_product_by_id(
         stock_quantity: product.stock_quantity,
     }))
 }
+
+pub async fn update_product(
+    State(state): State<Arc<Config>>,
+    Extension(user): Extension<AuthUser>,
+    Path(id): Path<Uuid>,
+    Json(payload): Json<UpdateProductRequest>,
+) -> Result<Json<ProductResponse>, AppError> {
+    find_owned_product(&state.db_pool, id, &user).await?;
+
+    if payload.price.is_some_and(|price| price < 0.0) {
+        return Err(AppError::BadRequest("Price cannot be negative".to_string()));
+    }
+    if payload.stock_quantity.is_some_and(|stock| stock < 0) {
+        return Err(AppError::BadRequest(
+            "Stock quantity cannot be negative".to_string(),
+        ));
+    }
+    if let Some(category_id) = payload.category_id {
+        find_category(&state.db_pool, category_id).await?;
+    }
+
+    let product = sqlx::query_as::<_, Product>(
+        "UPDATE products
+         SET name = COALESCE($2, name),
+             description = COALESCE($3, description),
+             category_id = COALESCE($4, category_id),
+             price = COALESCE($5, price),
+             stock_quantity = COALESCE($6, stock_quantity)
+         WHERE id = $1
+         RETURNING *",
+    )
+    .bind(id)
+    .bind(&payload.name)
+    .bind(&payload.description)
+    .bind(payload.category_id)
+    .bind(payload.price)
+    .bind(payload.stock_quantity)
+    .fetch_one(&state.db_pool)
+    .await?;
+
+    Ok(Json(ProductResponse {
+        id: product.id,
+        category_id: product.category_id,
+        name: product.name,
+        description: product.description,
+        price: product.price,
+        stock_quantity: product.stock_quantity,
+    }))
+}
+
+/// Products are only ever soft-deleted so past orders keep resolving them.
+pub async fn delete_product(
+    State(state): State<Arc<Config>>,
+    Extension(user): Extension<AuthUser>,
+    Path(id): Path<Uuid>,
+) -> Result<StatusCode, AppError> {
+    find_owned_product(&state.db_pool, id, &user).await?;
+
+    let mut tx = state.db_pool.begin().await?;
+
+    sqlx::query("UPDATE products SET deleted_at = NOW() WHERE id = $1")
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+
+    // A retired product can no longer be bought, so drop it from every cart
+    sqlx::query("DELETE FROM cart_items WHERE product_id = $1")
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Loads a live product the caller may modify: its owner, or any admin.
+async fn find_owned_product(pool: &PgPool, id: Uuid, user: &AuthUser) -> Result<Product, AppError> {
+    let product =
+        sqlx::query_as::<_, Product>("SELECT * FROM products WHERE id = $1 AND deleted_at IS NULL")
+            .bind(id)
+            .fetch_optional(pool)
+            .await?
+            .ok_or(AppError::BadRequest("Product not found".to_string()))?;
+
+    if product.user_id != user.id && !user.is_admin() {
+        return Err(AppError::Forbidden);
+    }
+
+    Ok(product)
+}
@@ -0,0 +1,236 @@
+use std::{collections::BTreeMap, sync::Arc};
+
+use axum::{
+    extract::{Path, State},
+    Extension, Json,
+};
+use rust_decimal::Decimal;
+
+use crate::{
+    config::Config,
+    dtos::{CreateRefundRequest, RefundItemResponse, RefundResponse},
+    error::AppError,
+    model::{Order, OrderItem, OrderStatus, Refund, RefundItem, RefundStatus},
+    payments::{complete_refund, record_refund, NewRefund, RefundLine},
+    web::{mw::AuthUser, order::load_order_access},
+};
+use uuid::Uuid;
+
+pub async fn create_refund(
+    State(state): State<Arc<Config>>,
+    Extension(user): Extension<AuthUser>,
+    Path(order_id): Path<Uuid>,
+    Json(payload): Json<CreateRefundRequest>,
+) -> Result<Json<RefundResponse>, AppError> {
+    // Admins may refund anything; sellers only the items they sold
+    let access = load_order_access(&state.db_pool, order_id, &user).await?;
+    if !access.can_manage() {
+        return Err(AppError::Forbidden);
+    }
+
+    let mut tx = state.db_pool.begin().await?;
+
+    let order = sqlx::query_as::<_, Order>("SELECT * FROM orders WHERE id = $1 FOR UPDATE")
+        .bind(order_id)
+        .fetch_one(&mut *tx)
+        .await?;
+
+    if !matches!(
+        order.status,
+        OrderStatus::Paid | OrderStatus::Fulfilled | OrderStatus::Shipped | OrderStatus::Delivered
+    ) {
+        return Err(AppError::BadRequest(format!(
+            "Cannot refund an order that is {}",
+            order.status
+        )));
+    }
+
+    let order_items =
+        sqlx::query_as::<_, OrderItem>("SELECT * FROM order_items WHERE order_id = $1 FOR UPDATE")
+            .bind(order_id)
+            .fetch_all(&mut *tx)
+            .await?;
+
+    let (amount, lines) = match (payload.items, payload.amount) {
+        (Some(_), Some(_)) => {
+            return Err(AppError::BadRequest(
+                "Refund either line items or an amount, not both".to_string(),
+            ));
+        }
+        (Some(requested), None) => {
+            let mut quantities: BTreeMap<Uuid, i32> = BTreeMap::new();
+            for item in requested {
+                if item.quantity <= 0 {
+                    return Err(AppError::BadRequest(
+                        "Quantity must be greater than zero".to_string(),
+                    ));
+                }
+                if !access.can_manage_item(item.order_item_id) {
+                    return Err(AppError::Forbidden);
+                }
+                let total = quantities.entry(item.order_item_id).or_default();
+                *total = total
+                    .checked_add(item.quantity)
+                    .ok_or(AppError::BadRequest("Quantity is too large".to_string()))?;
+            }
+
+            let mut lines = Vec::with_capacity(quantities.len());
+            for (order_item_id, quantity) in quantities {
+                let item = order_items
+                    .iter()
+                    .find(|item| item.id == order_item_id)
+                    .ok_or(AppError::BadRequest("Order item not found".to_string()))?;
+
+                let refundable = item.quantity - item.refunded_quantity;
+                if quantity > refundable {
+                    return Err(AppError::BadRequest(format!(
+                        "Only {} of {} can still be refunded",
+                        refundable, item.product_name
+                    )));
+                }
+
+                lines.push(RefundLine {
+                    order_item_id,
+                    quantity,
+                    amount: item.unit_price * Decimal::from(quantity),
+                });
+            }
+
+            (lines.iter().map(|line| line.amount).sum(), lines)
+        }
+        (None, Some(amount)) => {
+            if !access.is_admin {
+                return Err(AppError::Forbidden);
+            }
+            if payload.restock {
+                return Err(AppError::BadRequest(
+                    "Restocking requires refunding line items".to_string(),
+                ));
+            }
+            (amount.round_dp(2), Vec::new())
+        }
+        // Refund everything that has not been refunded yet; for a seller,
+        // everything of theirs
+        (None, None) => {
+            let lines: Vec<RefundLine> = order_items
+                .iter()
+                .filter(|item| access.can_manage_item(item.id))
+                .filter(|item| item.quantity > item.refunded_quantity)
+                .map(|item| {
+                    let quantity = item.quantity - item.refunded_quantity;
+                    RefundLine {
+                        order_item_id: item.id,
+                        quantity,
+                        amount: item.unit_price * Decimal::from(quantity),
+                    }
+                })
+                .collect();
+
+            let amount = if access.is_admin {
+                order.total_amount - order.refunded_amount
+            } else {
+                lines.iter().map(|line| line.amount).sum()
+            };
+            (amount, lines)
+        }
+    };
+
+    let refund = record_refund(
+        &mut tx,
+        order_id,
+        NewRefund {
+            amount,
+            lines,
+            restock: payload.restock,
+            reason: payload.reason.as_deref(),
+            created_by: Some(user.id),
+        },
+    )
+    .await?;
+
+    tx.commit().await?;
+
+    // The provider is only asked once the refund is on record; if it cannot
+    // be reached the refund stays pending and is retried later
+    let refund = complete_refund(&state, refund.id).await?;
+    let items = sqlx::query_as::<_, RefundItem>("SELECT * FROM refund_items WHERE refund_id = $1")
+        .bind(refund.id)
+        .fetch_all(&state.db_pool)
+        .await?;
+
+    if refund.status != RefundStatus::Succeeded {
+        return Ok(Json(to_refund_response(refund, items)));
+    }
+
+    let (username, email, locale) = sqlx::query_as::<_, (String, Option<String>, Option<String>)>(
+        "SELECT username, email, locale FROM users WHERE id = $1",
+    )
+    .bind(order.user_id)
+    .fetch_one(&state.db_pool)
+    .await?;
+
+    // Log if notification fails, but don't fail the refund
+    let notification_result = crate::grpc_client::send_refund_notification(
+        &order.user_id.to_string(),
+        &username,
+        &order_id.to_string(),
+        &refund.amount.to_string(),
+        refund.reason.as_deref().unwrap_or_default(),
+        email.as_deref().unwrap_or_default(),
+        locale.as_deref().unwrap_or_default(),
+    )
+    .await;
+
+    if let Err(e) = notification_result {
+        tracing::warn!("Failed to send refund notification: {}", e);
+    }
+
+    Ok(Json(to_refund_response(refund, items)))
+}
+
+pub async fn get_refunds(
+    State(state): State<Arc<Config>>,
+    Extension(user): Extension<AuthUser>,
+    Path(order_id): Path<Uuid>,
+) -> Result<Json<Vec<RefundResponse>>, AppError> {
+    load_order_access(&state.db_pool, order_id, &user).await?;
+
+    let refunds = sqlx::query_as::<_, Refund>(
+        "SELECT * FROM refunds WHERE order_id = $1 ORDER BY created_at",
+    )
+    .bind(order_id)
+    .fetch_all(&state.db_pool)
+    .await?;
+
+    let mut response = Vec::with_capacity(refunds.len());
+    for refund in refunds {
+        let items =
+            sqlx::query_as::<_, RefundItem>("SELECT * FROM refund_items WHERE refund_id = $1")
+                .bind(refund.id)
+                .fetch_all(&state.db_pool)
+                .await?;
+        response.push(to_refund_response(refund, items));
+    }
+
+    Ok(Json(response))
+}
+
+fn to_refund_response(refund: Refund, items: Vec<RefundItem>) -> RefundResponse {
+    RefundResponse {
+        id: refund.id,
+        order_id: refund.order_id,
+        amount: refund.amount,
+        reason: refund.reason,
+        restocked: refund.restocked,
+        status: refund.status,
+        items: items
+            .into_iter()
+            .map(|item| RefundItemResponse {
+                order_item_id: item.order_item_id,
+                quantity: item.quantity,
+                amount: item.amount,
+            })
+            .collect(),
+        created_at: refund.created_at,
+    }
+}
@@ -0,0 +1,391 @@
+use std::sync::Arc;
+
+use axum::{extract::State, http::StatusCode, Extension, Json};
+use chrono::{Duration, Utc};
+use rand::{rngs::OsRng, Rng};
+use sqlx::{PgConnection, PgPool};
+
+use crate::{
+    config::Config,
+    dtos::{
+        AuthResponse, MfaChallengeResponse, MfaLoginRequest, RecoveryCodesResponse,
+        TotpCodeRequest, TotpEnrollmentResponse,
+    },
+    error::AppError,
+    model::{LoginOutcome, User},
+    utils::{
+        token::{generate_token, hash_token},
+        totp,
+    },
+    web::{
+        auth::start_session,
+        login_throttle::{self, ClientIp},
+        mw::AuthUser,
+    },
+};
+use uuid::Uuid;
+
+const CHALLENGE_TTL_MINUTES: i64 = 5;
+/// Wrong codes allowed per challenge before it is thrown away.
+const MAX_CHALLENGE_ATTEMPTS: i32 = 5;
+const RECOVERY_CODE_COUNT: usize = 10;
+
+/// Starts enrolment with a fresh secret. 2FA stays off until the secret is
+/// confirmed with a code, so starting over simply replaces it.
+pub async fn enroll_totp(
+    State(state): State<Arc<Config>>,
+    Extension(user): Extension<AuthUser>,
+) -> Result<Json<TotpEnrollmentResponse>, AppError> {
+    let user = find_user(&state.db_pool, user.id).await?;
+    if user.totp_enabled_at.is_some() {
+        return Err(AppError::BadRequest(
+            "Two-factor authentication is already enabled".to_string(),
+        ));
+    }
+
+    let secret = totp::generate_secret();
+
+    sqlx::query("UPDATE users SET totp_secret = $2, totp_last_step = NULL WHERE id = $1")
+        .bind(user.id)
+        .bind(&secret)
+        .execute(&state.db_pool)
+        .await?;
+
+    Ok(Json(TotpEnrollmentResponse {
+        otpauth_uri: totp::otpauth_uri(&secret, &user.username),
+        secret,
+    }))
+}
+
+/// Turns 2FA on once the user proves their authenticator works, and hands
+/// out the recovery codes.
+pub async fn confirm_totp(
+    State(state): State<Arc<Config>>,
+    Extension(user): Extension<AuthUser>,
+    Json(payload): Json<TotpCodeRequest>,
+) -> Result<Json<RecoveryCodesResponse>, AppError> {
+    let mut tx = state.db_pool.begin().await?;
+    let user = lock_user(&mut tx, user.id).await?;
+
+    if user.totp_enabled_at.is_some() {
+        return Err(AppError::BadRequest(
+            "Two-factor authentication is already enabled".to_string(),
+        ));
+    }
+    let secret = user.totp_secret.as_deref().ok_or(AppError::BadRequest(
+        "Start enrolment before confirming it".to_string(),
+    ))?;
+
+    let step = totp::verify(secret, &payload.code, Utc::now().timestamp(), None)
+        .ok_or(AppError::BadRequest("Invalid code".to_string()))?;
+
+    sqlx::query("UPDATE users SET totp_enabled_at = NOW(), totp_last_step = $2 WHERE id = $1")
+        .bind(user.id)
+        .bind(step)
+        .execute(&mut *tx)
+        .await?;
+
+    let recovery_codes = replace_recovery_codes(&mut tx, user.id).await?;
+
+    tx.commit().await?;
+
+    Ok(Json(RecoveryCodesResponse { recovery_codes }))
+}
+
+/// Turns 2FA off. Takes an authenticator or recovery code, so a lost phone
+/// does not lock the user into 2FA. Wrong codes count towards the login
+/// throttle, so a stolen session cannot guess its way to turning 2FA off.
+pub async fn disable_totp(
+    State(state): State<Arc<Config>>,
+    ClientIp(ip): ClientIp,
+    Extension(user): Extension<AuthUser>,
+    Json(payload): Json<TotpCodeRequest>,
+) -> Result<StatusCode, AppError> {
+    let mut tx = state.db_pool.begin().await?;
+    let user = lock_enabled_user(&mut tx, user.id).await?;
+    let attempt_id = login_throttle::begin(&state.db_pool, &user.username, ip).await?;
+
+    if !verify_second_factor(&mut tx, &user, &payload.code).await? {
+        tx.rollback().await?;
+        return Err(bad_code(&state.db_pool, attempt_id, user.id).await);
+    }
+
+    sqlx::query(
+        "UPDATE users SET totp_secret = NULL, totp_enabled_at = NULL, totp_last_step = NULL
+         WHERE id = $1",
+    )
+    .bind(user.id)
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query("DELETE FROM recovery_codes WHERE user_id = $1")
+        .bind(user.id)
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+
+    login_throttle::finish(
+        &state.db_pool,
+        attempt_id,
+        Some(user.id),
+        LoginOutcome::Success,
+    )
+    .await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Replaces all recovery codes, used or not. Wrong codes count towards the
+/// login throttle, as for [`disable_totp`].
+pub async fn regenerate_recovery_codes(
+    State(state): State<Arc<Config>>,
+    ClientIp(ip): ClientIp,
+    Extension(user): Extension<AuthUser>,
+    Json(payload): Json<TotpCodeRequest>,
+) -> Result<Json<RecoveryCodesResponse>, AppError> {
+    let mut tx = state.db_pool.begin().await?;
+    let user = lock_enabled_user(&mut tx, user.id).await?;
+    let attempt_id = login_throttle::begin(&state.db_pool, &user.username, ip).await?;
+
+    if !verify_totp(&mut tx, &user, &payload.code).await? {
+        tx.rollback().await?;
+        return Err(bad_code(&state.db_pool, attempt_id, user.id).await);
+    }
+
+    let recovery_codes = replace_recovery_codes(&mut tx, user.id).await?;
+
+    tx.commit().await?;
+
+    login_throttle::finish(
+        &state.db_pool,
+        attempt_id,
+        Some(user.id),
+        LoginOutcome::Success,
+    )
+    .await?;
+
+    Ok(Json(RecoveryCodesResponse { recovery_codes }))
+}
+
+/// Records a wrong code against the login throttle and returns the error to
+/// answer with. The caller must not hold a lock on the user's row, which
+/// recording the attempt needs.
+async fn bad_code(pool: &PgPool, attempt_id: Uuid, user_id: Uuid) -> AppError {
+    match login_throttle::finish(pool, attempt_id, Some(user_id), LoginOutcome::BadCode).await {
+        Ok(()) => AppError::BadRequest("Invalid code".to_string()),
+        Err(e) => e,
+    }
+}
+
+/// Second login step: trades a challenge token and a code for a session.
+pub async fn login_with_code(
+    State(state): State<Arc<Config>>,
+    ClientIp(ip): ClientIp,
+    Json(payload): Json<MfaLoginRequest>,
+) -> Result<Json<AuthResponse>, AppError> {
+    // Wrong codes count towards the login throttle for the account, so a
+    // fresh challenge does not buy a fresh set of guesses
+    let username = sqlx::query_scalar::<_, String>(
+        "SELECT u.username FROM mfa_challenges c
+         JOIN users u ON u.id = c.user_id
+         WHERE c.token_hash = $1 AND c.expires_at > NOW()",
+    )
+    .bind(hash_token(&payload.challenge_token))
+    .fetch_optional(&state.db_pool)
+    .await?
+    .ok_or(AppError::Unauthorized)?;
+    let attempt_id = login_throttle::begin(&state.db_pool, &username, ip).await?;
+
+    let mut tx = state.db_pool.begin().await?;
+
+    let (challenge_id, user_id) = sqlx::query_as::<_, (Uuid, Uuid)>(
+        "SELECT id, user_id FROM mfa_challenges
+         WHERE token_hash = $1 AND expires_at > NOW()
+         FOR UPDATE",
+    )
+    .bind(hash_token(&payload.challenge_token))
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or(AppError::Unauthorized)?;
+
+    let user = lock_user(&mut tx, user_id).await?;
+
+    if !verify_second_factor(&mut tx, &user, &payload.code).await? {
+        let attempts = sqlx::query_scalar::<_, i32>(
+            "UPDATE mfa_challenges SET attempts = attempts + 1 WHERE id = $1 RETURNING attempts",
+        )
+        .bind(challenge_id)
+        .fetch_one(&mut *tx)
+        .await?;
+
+        if attempts >= MAX_CHALLENGE_ATTEMPTS {
+            sqlx::query("DELETE FROM mfa_challenges WHERE id = $1")
+                .bind(challenge_id)
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
+
+        login_throttle::finish(
+            &state.db_pool,
+            attempt_id,
+            Some(user.id),
+            LoginOutcome::BadCode,
+        )
+        .await?;
+        return Err(AppError::Unauthorized);
+    }
+
+    sqlx::query("DELETE FROM mfa_challenges WHERE id = $1")
+        .bind(challenge_id)
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+
+    login_throttle::finish(
+        &state.db_pool,
+        attempt_id,
+        Some(user.id),
+        LoginOutcome::Success,
+    )
+    .await?;
+
+    Ok(Json(start_session(&state, &user).await?))
+}
+
+/// Issued instead of tokens when a user with 2FA gets their password right.
+pub async fn create_mfa_challenge(
+    pool: &PgPool,
+    user_id: Uuid,
+) -> Result<MfaChallengeResponse, AppError> {
+    let challenge_token = generate_token();
+
+    sqlx::query("INSERT INTO mfa_challenges (user_id, token_hash, expires_at) VALUES ($1, $2, $3)")
+        .bind(user_id)
+        .bind(hash_token(&challenge_token))
+        .bind(Utc::now() + Duration::minutes(CHALLENGE_TTL_MINUTES))
+        .execute(pool)
+        .await?;
+
+    Ok(MfaChallengeResponse {
+        mfa_required: true,
+        challenge_token,
+        expires_in: CHALLENGE_TTL_MINUTES * 60,
+    })
+}
+
+/// Accepts either a current authenticator code or an unused recovery code,
+/// consuming whichever matched. `user` must be locked by the caller.
+async fn verify_second_factor(
+    conn: &mut PgConnection,
+    user: &User,
+    code: &str,
+) -> Result<bool, AppError> {
+    if verify_totp(conn, user, code).await? {
+        return Ok(true);
+    }
+
+    let used = sqlx::query(
+        "UPDATE recovery_codes SET used_at = NOW()
+         WHERE id = (
+             SELECT id FROM recovery_codes
+             WHERE user_id = $1 AND code_hash = $2 AND used_at IS NULL
+             LIMIT 1
+         )",
+    )
+    .bind(user.id)
+    .bind(hash_token(&normalize_recovery_code(code)))
+    .execute(conn)
+    .await?;
+
+    Ok(used.rows_affected() == 1)
+}
+
+async fn verify_totp(conn: &mut PgConnection, user: &User, code: &str) -> Result<bool, AppError> {
+    let Some(secret) = user.totp_secret.as_deref() else {
+        return Ok(false);
+    };
+    let Some(step) = totp::verify(secret, code, Utc::now().timestamp(), user.totp_last_step) else {
+        return Ok(false);
+    };
+
+    sqlx::query("UPDATE users SET totp_last_step = $2 WHERE id = $1")
+        .bind(user.id)
+        .bind(step)
+        .execute(conn)
+        .await?;
+
+    Ok(true)
+}
+
+async fn replace_recovery_codes(
+    conn: &mut PgConnection,
+    user_id: Uuid,
+) -> Result<Vec<String>, AppError> {
+    sqlx::query("DELETE FROM recovery_codes WHERE user_id = $1")
+        .bind(user_id)
+        .execute(&mut *conn)
+        .await?;
+
+    let codes: Vec<String> = (0..RECOVERY_CODE_COUNT)
+        .map(|_| generate_recovery_code())
+        .collect();
+    let hashes: Vec<String> = codes
+        .iter()
+        .map(|code| hash_token(&normalize_recovery_code(code)))
+        .collect();
+
+    sqlx::query("INSERT INTO recovery_codes (user_id, code_hash) SELECT $1, UNNEST($2::text[])")
+        .bind(user_id)
+        .bind(&hashes)
+        .execute(&mut *conn)
+        .await?;
+
+    Ok(codes)
+}
+
+/// Ten base32 characters (50 bits) shown as `xxxxx-xxxxx`.
+fn generate_recovery_code() -> String {
+    const ALPHABET: &[u8] = b"abcdefghijklmnopqrstuvwxyz234567";
+    let mut rng = OsRng;
+    let chars: String = (0..10)
+        .map(|_| ALPHABET[rng.gen_range(0..ALPHABET.len())] as char)
+        .collect();
+    format!("{}-{}", &chars[..5], &chars[5..])
+}
+
+/// Users may type recovery codes with or without the dash, in any case.
+fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+async fn find_user(pool: &PgPool, user_id: Uuid) -> Result<User, AppError> {
+    sqlx::query_as::<_, User>("SELECT * FROM users WHERE id = $1")
+        .bind(user_id)
+        .fetch_one(pool)
+        .await
+        .map_err(AppError::from)
+}
+
+async fn lock_user(conn: &mut PgConnection, user_id: Uuid) -> Result<User, AppError> {
+    sqlx::query_as::<_, User>("SELECT * FROM users WHERE id = $1 FOR UPDATE")
+        .bind(user_id)
+        .fetch_one(conn)
+        .await
+        .map_err(AppError::from)
+}
+
+async fn lock_enabled_user(conn: &mut PgConnection, user_id: Uuid) -> Result<User, AppError> {
+    let user = lock_user(conn, user_id).await?;
+    if user.totp_enabled_at.is_none() {
+        return Err(AppError::BadRequest(
+            "Two-factor authentication is not enabled".to_string(),
+        ));
+    }
+    Ok(user)
+}
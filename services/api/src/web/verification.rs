@@ -0,0 +1,151 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    Extension, Json,
+};
+use chrono::{DateTime, Duration, Utc};
+use sqlx::PgPool;
+
+use crate::{
+    config::Config,
+    dtos::{VerifyEmailRequest, VerifyEmailResponse},
+    error::AppError,
+    model::User,
+    utils::token::{generate_token, hash_token},
+    web::mw::AuthUser,
+};
+use uuid::Uuid;
+
+const VERIFICATION_TOKEN_TTL_HOURS: i64 = 24;
+
+/// Marks the address a verification token was issued for as verified. A
+/// pending address replaces the current one at this point.
+pub async fn verify_email(
+    State(state): State<Arc<Config>>,
+    Query(params): Query<VerifyEmailRequest>,
+) -> Result<Json<VerifyEmailResponse>, AppError> {
+    let invalid = || AppError::BadRequest("Invalid or expired verification token".to_string());
+
+    let mut tx = state.db_pool.begin().await?;
+
+    let (token_id, user_id, email) = sqlx::query_as::<_, (Uuid, Uuid, String)>(
+        "SELECT id, user_id, email FROM email_verification_tokens
+         WHERE token_hash = $1 AND used_at IS NULL AND expires_at > NOW()
+         FOR UPDATE",
+    )
+    .bind(hash_token(&params.token))
+    .fetch_optional(&mut *tx)
+    .await?
+    .ok_or_else(invalid)?;
+
+    // Only counts if the user has not changed their address since
+    let verified_at = sqlx::query_scalar::<_, DateTime<Utc>>(
+        "UPDATE users
+         SET email_verified_at = CASE WHEN email = $2
+                 THEN COALESCE(email_verified_at, NOW())
+                 ELSE NOW() END,
+             email = $2,
+             pending_email = NULL
+         WHERE id = $1 AND COALESCE(pending_email, email) = $2
+         RETURNING email_verified_at",
+    )
+    .bind(user_id)
+    .bind(&email)
+    .fetch_optional(&mut *tx)
+    .await
+    .map_err(|e| {
+        // Someone else verified the same address first
+        if let Some(db_error) = e.as_database_error() {
+            if db_error.is_unique_violation() {
+                return AppError::BadRequest("Email already in use".to_string());
+            }
+        }
+        AppError::Database(e)
+    })?
+    .ok_or_else(invalid)?;
+
+    sqlx::query("UPDATE email_verification_tokens SET used_at = NOW() WHERE id = $1")
+        .bind(token_id)
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+
+    Ok(Json(VerifyEmailResponse { email, verified_at }))
+}
+
+/// Sends a fresh verification email, replacing any earlier link.
+pub async fn resend_verification(
+    State(state): State<Arc<Config>>,
+    Extension(user): Extension<AuthUser>,
+) -> Result<StatusCode, AppError> {
+    let user = sqlx::query_as::<_, User>("SELECT * FROM users WHERE id = $1")
+        .bind(user.id)
+        .fetch_one(&state.db_pool)
+        .await?;
+
+    if user.pending_email.is_none() && user.email_verified_at.is_some() {
+        return Err(AppError::BadRequest(
+            "Email is already verified".to_string(),
+        ));
+    }
+
+    start_email_verification(&state.db_pool, &user).await?;
+
+    Ok(StatusCode::ACCEPTED)
+}
+
+/// Issues a verification token for the user's pending email, or else the
+/// current one, and sends it in the background. Users without an email are
+/// skipped.
+pub async fn start_email_verification(pool: &PgPool, user: &User) -> Result<(), AppError> {
+    let Some(email) = user.pending_email.clone().or_else(|| user.email.clone()) else {
+        return Ok(());
+    };
+
+    let token = generate_token();
+    let expires_at = Utc::now() + Duration::hours(VERIFICATION_TOKEN_TTL_HOURS);
+
+    let mut tx = pool.begin().await?;
+
+    sqlx::query("DELETE FROM email_verification_tokens WHERE user_id = $1 AND used_at IS NULL")
+        .bind(user.id)
+        .execute(&mut *tx)
+        .await?;
+
+    sqlx::query(
+        "INSERT INTO email_verification_tokens (user_id, email, token_hash, expires_at)
+         VALUES ($1, $2, $3, $4)",
+    )
+    .bind(user.id)
+    .bind(&email)
+    .bind(hash_token(&token))
+    .bind(expires_at)
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    let user_id = user.id;
+    let username = user.username.clone();
+    let locale = user.locale.clone().unwrap_or_default();
+    tokio::spawn(async move {
+        let notification_result = crate::grpc_client::send_email_verification_notification(
+            &user_id.to_string(),
+            &username,
+            &email,
+            &token,
+            &expires_at.to_rfc3339(),
+            &locale,
+        )
+        .await;
+
+        if let Err(e) = notification_result {
+            tracing::warn!("Failed to send email verification notification: {}", e);
+        }
+    });
+
+    Ok(())
+}
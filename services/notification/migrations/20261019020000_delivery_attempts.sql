@@ -0,0 +1,19 @@
+-- Add migration script here
+-- Only notifications routed to the in-app channel show up in the inbox.
+-- Existing rows predate routing and were all in-app.
+ALTER TABLE notifications ADD COLUMN in_inbox INTEGER NOT NULL DEFAULT 1;
+
+-- Every try at delivering a notification over a channel, kept so missing
+-- emails and webhooks can be traced
+CREATE TABLE delivery_attempts (
+    id TEXT PRIMARY KEY NOT NULL,
+    notification_id TEXT NOT NULL REFERENCES notifications(id) ON DELETE CASCADE,
+    channel TEXT NOT NULL,
+    attempt INTEGER NOT NULL,
+    -- delivered, failed or skipped
+    status TEXT NOT NULL,
+    error TEXT,
+    attempted_at TEXT NOT NULL
+);
+
+CREATE INDEX idx_delivery_attempts_notification_id ON delivery_attempts(notification_id);
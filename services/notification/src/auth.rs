@@ -0,0 +1,104 @@
+use std::{env, sync::Arc};
+
+use tonic::{service::Interceptor, Request, Status};
+
+/// Checks that each call carries the shared secret from
+/// `NOTIFICATION_SERVICE_TOKEN` as `authorization: Bearer <token>`. Only
+/// trusted services such as the API know it; they act for any user, so
+/// nothing else may call in.
+#[derive(Clone)]
+pub struct ServiceAuth {
+    token: Arc<str>,
+}
+
+impl ServiceAuth {
+    pub fn from_env() -> Result<Self, String> {
+        let token = env::var("NOTIFICATION_SERVICE_TOKEN")
+            .ok()
+            .filter(|token| !token.trim().is_empty())
+            .ok_or("NOTIFICATION_SERVICE_TOKEN must be set")?;
+
+        Ok(Self::new(&token))
+    }
+
+    pub fn new(token: &str) -> Self {
+        Self {
+            token: token.into(),
+        }
+    }
+}
+
+impl Interceptor for ServiceAuth {
+    fn call(&mut self, request: Request<()>) -> Result<Request<()>, Status> {
+        let presented = request
+            .metadata()
+            .get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+
+        match presented {
+            Some(token) if constant_time_eq(token.as_bytes(), self.token.as_bytes()) => Ok(request),
+            _ => Err(Status::unauthenticated("Invalid service token")),
+        }
+    }
+}
+
+/// Compares without stopping at the first differing byte, so response
+/// times do not reveal how much of a guess was right.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn auth() -> ServiceAuth {
+        ServiceAuth::new("s3cret-token")
+    }
+
+    fn request(authorization: Option<&str>) -> Request<()> {
+        let mut request = Request::new(());
+        if let Some(value) = authorization {
+            request
+                .metadata_mut()
+                .insert("authorization", value.parse().unwrap());
+        }
+        request
+    }
+
+    #[test]
+    fn accepts_the_service_token() {
+        assert!(auth().call(request(Some("Bearer s3cret-token"))).is_ok());
+    }
+
+    #[test]
+    fn rejects_missing_or_wrong_tokens() {
+        for authorization in [
+            None,
+            Some(""),
+            Some("Bearer "),
+            Some("Bearer wrong-token"),
+            Some("Bearer s3cret"),
+            Some("Bearer s3cret-token-and-more"),
+            Some("Basic s3cret-token"),
+            Some("s3cret-token"),
+        ] {
+            let status = auth().call(request(authorization)).unwrap_err();
+            assert_eq!(
+                status.code(),
+                tonic::Code::Unauthenticated,
+                "{:?}",
+                authorization
+            );
+        }
+    }
+
+    #[test]
+    fn constant_time_eq_compares_whole_values() {
+        assert!(constant_time_eq(b"abc", b"abc"));
+        assert!(!constant_time_eq(b"abc", b"abd"));
+        assert!(!constant_time_eq(b"abc", b"ab"));
+        assert!(constant_time_eq(b"", b""));
+    }
+}
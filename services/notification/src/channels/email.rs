@@ -0,0 +1,267 @@
+use std::env;
+
+use lettre::{
+    message::{header::ContentType, Mailbox, MultiPart},
+    transport::smtp::authentication::Credentials,
+    AsyncSmtpTransport, AsyncTransport, Message as Email, Tokio1Executor,
+};
+
+use super::{Channel, ChannelKind, DeliveryError, Message};
+
+/// Sends email over SMTP, with an HTML alternative when the kind's templates
+/// have one. Configured from the environment:
+///
+/// - `SMTP_HOST`: enables the channel; unset leaves email off
+/// - `SMTP_TLS`: `starttls` (default), `tls`, or `none` for a local mail
+///   sink such as Mailpit
+/// - `SMTP_PORT`: defaults to 587, 465 or 25 to match `SMTP_TLS`
+/// - `SMTP_USERNAME`, `SMTP_PASSWORD`: optional credentials
+/// - `SMTP_FROM`: the sender, e.g. `Shop <no-reply@example.com>`
+pub struct EmailChannel {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+
+impl EmailChannel {
+    pub fn from_env() -> Result<Option<Self>, String> {
+        let Ok(host) = env::var("SMTP_HOST") else {
+            return Ok(None);
+        };
+
+        let from = env::var("SMTP_FROM")
+            .map_err(|_| "SMTP_FROM must be set when SMTP_HOST is".to_string())?
+            .parse::<Mailbox>()
+            .map_err(|e| format!("Invalid SMTP_FROM: {}", e))?;
+
+        let (builder, default_port) = match env::var("SMTP_TLS").as_deref() {
+            Err(_) | Ok("starttls") => (
+                AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&host)
+                    .map_err(|e| e.to_string())?,
+                587,
+            ),
+            Ok("tls") => (
+                AsyncSmtpTransport::<Tokio1Executor>::relay(&host).map_err(|e| e.to_string())?,
+                465,
+            ),
+            Ok("none") => (
+                AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(&host),
+                25,
+            ),
+            Ok(other) => return Err(format!("Unknown SMTP_TLS: {}", other)),
+        };
+
+        let port = match env::var("SMTP_PORT") {
+            Ok(port) => port
+                .parse()
+                .map_err(|_| "SMTP_PORT must be a port number".to_string())?,
+            Err(_) => default_port,
+        };
+
+        let mut builder = builder.port(port);
+        if let (Ok(username), Ok(password)) = (env::var("SMTP_USERNAME"), env::var("SMTP_PASSWORD"))
+        {
+            builder = builder.credentials(Credentials::new(username, password));
+        }
+
+        tracing::info!("Email notifications go through {}:{}", host, port);
+
+        Ok(Some(Self {
+            transport: builder.build(),
+            from,
+        }))
+    }
+}
+
+#[tonic::async_trait]
+impl Channel for EmailChannel {
+    fn kind(&self) -> ChannelKind {
+        ChannelKind::Email
+    }
+
+    async fn deliver(&self, message: &Message) -> Result<(), DeliveryError> {
+        let Some(address) = &message.email else {
+            return Err(DeliveryError::Skipped(
+                "User has no email address".to_string(),
+            ));
+        };
+        let to = address
+            .parse::<Mailbox>()
+            .map_err(|e| DeliveryError::Permanent(format!("Invalid address {}: {}", address, e)))?;
+
+        let notification = &message.notification;
+        let (subject, text, html) = match &message.email_content {
+            Some(content) => (&content.subject, &content.text, content.html.as_ref()),
+            None => (&notification.title, &notification.body, None),
+        };
+
+        let builder = Email::builder()
+            .from(self.from.clone())
+            .to(to)
+            .subject(subject);
+        let email = match html {
+            Some(html) => builder.multipart(MultiPart::alternative_plain_html(
+                text.clone(),
+                html.clone(),
+            )),
+            None => builder.header(ContentType::TEXT_PLAIN).body(text.clone()),
+        }
+        .map_err(|e| DeliveryError::Permanent(e.to_string()))?;
+
+        self.transport.send(email).await.map_err(|e| {
+            if e.is_permanent() {
+                DeliveryError::Permanent(e.to_string())
+            } else {
+                DeliveryError::Transient(e.to_string())
+            }
+        })?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Utc;
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+        net::TcpListener,
+        task::JoinHandle,
+    };
+
+    use super::*;
+    use crate::{store::StoredNotification, templates::Rendered};
+
+    /// A one-connection SMTP server on a local port that answers `RCPT TO`
+    /// with `rcpt_reply` and returns the message it was handed, if any.
+    async fn mail_sink(rcpt_reply: &'static str) -> (u16, JoinHandle<Option<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        let sink = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let (read, mut write) = stream.into_split();
+            let mut lines = BufReader::new(read).lines();
+            let mut data = None;
+
+            write.write_all(b"220 sink ESMTP\r\n").await.unwrap();
+            while let Ok(Some(line)) = lines.next_line().await {
+                let reply = match line
+                    .get(..4)
+                    .unwrap_or_default()
+                    .to_ascii_uppercase()
+                    .as_str()
+                {
+                    "RCPT" => format!("{}\r\n", rcpt_reply),
+                    "DATA" => {
+                        write.write_all(b"354 go ahead\r\n").await.unwrap();
+                        let mut body = String::new();
+                        while let Ok(Some(line)) = lines.next_line().await {
+                            if line == "." {
+                                break;
+                            }
+                            body.push_str(&line);
+                            body.push('\n');
+                        }
+                        data = Some(body);
+                        "250 queued\r\n".to_string()
+                    }
+                    "QUIT" => {
+                        write.write_all(b"221 bye\r\n").await.unwrap();
+                        break;
+                    }
+                    _ => "250 ok\r\n".to_string(),
+                };
+                write.write_all(reply.as_bytes()).await.unwrap();
+            }
+
+            data
+        });
+
+        (port, sink)
+    }
+
+    fn channel(port: u16) -> EmailChannel {
+        EmailChannel {
+            transport: AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous("127.0.0.1")
+                .port(port)
+                .build(),
+            from: "Shop <no-reply@example.com>".parse().unwrap(),
+        }
+    }
+
+    fn message(email: Option<&str>, email_content: Option<Rendered>) -> Message {
+        Message {
+            notification: StoredNotification {
+                id: "n-1".to_string(),
+                user_id: "user-1".to_string(),
+                kind: "refund".to_string(),
+                title: "Refund issued".to_string(),
+                body: "Your refund is on its way.".to_string(),
+                created_at: Utc::now(),
+                read_at: None,
+                in_inbox: false,
+            },
+            email: email.map(str::to_string),
+            email_content,
+        }
+    }
+
+    #[tokio::test]
+    async fn sends_the_stored_copy_through_smtp() {
+        let (port, sink) = mail_sink("250 ok").await;
+
+        channel(port)
+            .deliver(&message(Some("alice@example.com"), None))
+            .await
+            .unwrap();
+
+        let data = sink.await.unwrap().expect("no message reached the sink");
+        assert!(data.contains("To: alice@example.com"));
+        assert!(data.contains("Subject: Refund issued"));
+        assert!(data.contains("Your refund is on its way."));
+    }
+
+    #[tokio::test]
+    async fn sends_rendered_content_with_an_html_alternative() {
+        let (port, sink) = mail_sink("250 ok").await;
+        let content = Rendered {
+            locale: "en".to_string(),
+            subject: "Reset your password".to_string(),
+            text: "Use this link to reset it.".to_string(),
+            html: Some("<p>Use this link to reset it.</p>".to_string()),
+        };
+
+        channel(port)
+            .deliver(&message(Some("alice@example.com"), Some(content)))
+            .await
+            .unwrap();
+
+        let data = sink.await.unwrap().expect("no message reached the sink");
+        assert!(data.contains("Subject: Reset your password"));
+        assert!(data.contains("multipart/alternative"));
+        assert!(data.contains("<p>Use this link to reset it.</p>"));
+        assert!(!data.contains("Refund issued"));
+    }
+
+    #[tokio::test]
+    async fn classifies_smtp_rejections() {
+        let (port, _sink) = mail_sink("550 no such user").await;
+        let result = channel(port)
+            .deliver(&message(Some("nobody@example.com"), None))
+            .await;
+        assert!(matches!(result, Err(DeliveryError::Permanent(_))));
+
+        let (port, _sink) = mail_sink("451 try again later").await;
+        let result = channel(port)
+            .deliver(&message(Some("alice@example.com"), None))
+            .await;
+        assert!(matches!(result, Err(DeliveryError::Transient(_))));
+    }
+
+    #[tokio::test]
+    async fn skips_users_without_an_address() {
+        let result = channel(1).deliver(&message(None, None)).await;
+
+        assert!(matches!(result, Err(DeliveryError::Skipped(_))));
+    }
+}
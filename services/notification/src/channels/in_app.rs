@@ -0,0 +1,41 @@
+use crate::{
+    hub::NotificationHub,
+    store::{NotificationStore, StoredNotification},
+};
+
+use super::{Channel, ChannelKind, DeliveryError, Message};
+
+/// Puts the notification in the user's inbox and pushes it to any open
+/// live subscriptions.
+#[derive(Debug, Clone)]
+pub struct InAppChannel {
+    store: NotificationStore,
+    hub: NotificationHub,
+}
+
+impl InAppChannel {
+    pub fn new(store: NotificationStore, hub: NotificationHub) -> Self {
+        Self { store, hub }
+    }
+}
+
+#[tonic::async_trait]
+impl Channel for InAppChannel {
+    fn kind(&self) -> ChannelKind {
+        ChannelKind::InApp
+    }
+
+    async fn deliver(&self, message: &Message) -> Result<(), DeliveryError> {
+        self.store
+            .add_to_inbox(&message.notification.id)
+            .await
+            .map_err(|e| DeliveryError::Transient(e.to_string()))?;
+
+        self.hub.publish(StoredNotification {
+            in_inbox: true,
+            ..message.notification.clone()
+        });
+
+        Ok(())
+    }
+}
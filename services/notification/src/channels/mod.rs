@@ -0,0 +1,90 @@
+use std::{fmt, str::FromStr};
+
+use crate::{store::StoredNotification, templates::Rendered};
+
+mod email;
+mod in_app;
+mod webhook;
+
+pub use email::EmailChannel;
+pub use in_app::InAppChannel;
+pub use webhook::WebhookChannel;
+
+/// The ways a notification can reach a user.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ChannelKind {
+    Email,
+    Webhook,
+    InApp,
+}
+
+impl ChannelKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ChannelKind::Email => "email",
+            ChannelKind::Webhook => "webhook",
+            ChannelKind::InApp => "in_app",
+        }
+    }
+}
+
+impl fmt::Display for ChannelKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for ChannelKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "email" => Ok(ChannelKind::Email),
+            "webhook" => Ok(ChannelKind::Webhook),
+            "in_app" => Ok(ChannelKind::InApp),
+            other => Err(format!("Unknown notification channel: {}", other)),
+        }
+    }
+}
+
+/// A notification on its way out, with what channels need beyond the
+/// stored copy.
+#[derive(Debug, Clone)]
+pub struct Message {
+    pub notification: StoredNotification,
+    /// Where the email channel sends to, if the user has an address.
+    pub email: Option<String>,
+    /// The kind's email templates rendered for the recipient. Only email
+    /// sees this, so unlike the stored copy it may carry a reset token.
+    /// Without email templates the stored copy is sent instead.
+    pub email_content: Option<Rendered>,
+}
+
+/// Why a delivery did not happen.
+#[derive(Debug)]
+pub enum DeliveryError {
+    /// There was nothing to deliver to; not an error worth retrying.
+    Skipped(String),
+    /// The receiving end refused the message; retrying will not help.
+    Permanent(String),
+    /// A network or server problem that may clear up on retry.
+    Transient(String),
+}
+
+impl DeliveryError {
+    pub fn message(&self) -> &str {
+        match self {
+            DeliveryError::Skipped(msg)
+            | DeliveryError::Permanent(msg)
+            | DeliveryError::Transient(msg) => msg,
+        }
+    }
+}
+
+/// Something that can hand a notification to a user.
+#[tonic::async_trait]
+pub trait Channel: Send + Sync {
+    fn kind(&self) -> ChannelKind;
+
+    async fn deliver(&self, message: &Message) -> Result<(), DeliveryError>;
+}
@@ -0,0 +1,79 @@
+use std::{env, time::Duration};
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use super::{Channel, ChannelKind, DeliveryError, Message};
+
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// POSTs each notification as JSON to `NOTIFICATION_WEBHOOK_URL`, if set.
+/// Only the stored copy is sent, never the email content.
+pub struct WebhookChannel {
+    client: reqwest::Client,
+    url: String,
+}
+
+#[derive(Debug, Serialize)]
+struct WebhookPayload<'a> {
+    id: &'a str,
+    user_id: &'a str,
+    kind: &'a str,
+    title: &'a str,
+    body: &'a str,
+    created_at: DateTime<Utc>,
+}
+
+impl WebhookChannel {
+    pub fn from_env() -> Result<Option<Self>, String> {
+        let Ok(url) = env::var("NOTIFICATION_WEBHOOK_URL") else {
+            return Ok(None);
+        };
+
+        let client = reqwest::Client::builder()
+            .timeout(WEBHOOK_TIMEOUT)
+            .build()
+            .map_err(|e| e.to_string())?;
+
+        Ok(Some(Self { client, url }))
+    }
+}
+
+#[tonic::async_trait]
+impl Channel for WebhookChannel {
+    fn kind(&self) -> ChannelKind {
+        ChannelKind::Webhook
+    }
+
+    async fn deliver(&self, message: &Message) -> Result<(), DeliveryError> {
+        let notification = &message.notification;
+        let payload = WebhookPayload {
+            id: &notification.id,
+            user_id: &notification.user_id,
+            kind: &notification.kind,
+            title: &notification.title,
+            body: &notification.body,
+            created_at: notification.created_at,
+        };
+
+        let response = self
+            .client
+            .post(&self.url)
+            .json(&payload)
+            .send()
+            .await
+            .map_err(|e| DeliveryError::Transient(e.to_string()))?;
+
+        let status = response.status();
+        if status.is_success() {
+            return Ok(());
+        }
+
+        let error = format!("Webhook returned {}", status);
+        if status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS {
+            Err(DeliveryError::Transient(error))
+        } else {
+            Err(DeliveryError::Permanent(error))
+        }
+    }
+}
@@ -0,0 +1,271 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use crate::{
+    channels::{Channel, ChannelKind, DeliveryError, Message},
+    routing::RoutingRules,
+    store::{DeliveryStatus, NotificationStore},
+};
+
+/// Tries per channel before giving up on a transient failure.
+const MAX_ATTEMPTS: u32 = 3;
+/// Delay before the first retry; doubles for each one after.
+const RETRY_BASE_DELAY: Duration = Duration::from_secs(2);
+
+/// Sends stored notifications out on the channels their routing rules name,
+/// recording every attempt.
+pub struct Dispatcher {
+    store: NotificationStore,
+    routes: RoutingRules,
+    channels: HashMap<ChannelKind, Arc<dyn Channel>>,
+    retry_base_delay: Duration,
+}
+
+impl Dispatcher {
+    pub fn new(store: NotificationStore, routes: RoutingRules) -> Self {
+        Self {
+            store,
+            routes,
+            channels: HashMap::new(),
+            retry_base_delay: RETRY_BASE_DELAY,
+        }
+    }
+
+    pub fn with_channel(mut self, channel: Arc<dyn Channel>) -> Self {
+        self.channels.insert(channel.kind(), channel);
+        self
+    }
+
+    /// Delivers in the background, one task per channel, so a slow mail
+    /// server neither holds up the caller nor the other channels.
+    pub fn dispatch(self: &Arc<Self>, message: Message) {
+        let message = Arc::new(message);
+
+        for &kind in self.routes.channels_for(&message.notification.kind) {
+            let dispatcher = Arc::clone(self);
+            let message = Arc::clone(&message);
+            tokio::spawn(async move { dispatcher.deliver(kind, &message).await });
+        }
+    }
+
+    async fn deliver(&self, kind: ChannelKind, message: &Message) {
+        let Some(channel) = self.channels.get(&kind) else {
+            self.record(
+                message,
+                kind,
+                1,
+                DeliveryStatus::Skipped,
+                Some("Channel is not configured"),
+            )
+            .await;
+            return;
+        };
+
+        for attempt in 1..=MAX_ATTEMPTS {
+            let result = channel.deliver(message).await;
+
+            let status = match &result {
+                Ok(()) => DeliveryStatus::Delivered,
+                Err(DeliveryError::Skipped(_)) => DeliveryStatus::Skipped,
+                Err(_) => DeliveryStatus::Failed,
+            };
+            let error = result.as_ref().err().map(DeliveryError::message);
+            self.record(message, kind, attempt, status, error).await;
+
+            match result {
+                Err(DeliveryError::Transient(_)) if attempt < MAX_ATTEMPTS => {
+                    tokio::time::sleep(self.retry_base_delay * 2u32.pow(attempt - 1)).await;
+                }
+                _ => return,
+            }
+        }
+    }
+
+    async fn record(
+        &self,
+        message: &Message,
+        kind: ChannelKind,
+        attempt: u32,
+        status: DeliveryStatus,
+        error: Option<&str>,
+    ) {
+        let notification_id = &message.notification.id;
+
+        if status == DeliveryStatus::Failed {
+            tracing::warn!(
+                "Delivering notification {} by {} failed (attempt {}): {}",
+                notification_id,
+                kind,
+                attempt,
+                error.unwrap_or_default()
+            );
+        }
+
+        let recorded = self
+            .store
+            .record_attempt(notification_id, kind.as_str(), attempt, status, error)
+            .await;
+
+        if let Err(e) = recorded {
+            tracing::error!(
+                "Failed to record delivery attempt for notification {}: {}",
+                notification_id,
+                e
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::VecDeque, sync::Mutex};
+
+    use tokio::time::Instant;
+
+    use super::*;
+    use crate::store::NewNotification;
+
+    /// Answers each delivery with the next scripted result, noting when it
+    /// was called.
+    struct ScriptedChannel {
+        results: Mutex<VecDeque<Result<(), DeliveryError>>>,
+        calls: Mutex<Vec<Instant>>,
+    }
+
+    impl ScriptedChannel {
+        fn new(results: Vec<Result<(), DeliveryError>>) -> Arc<Self> {
+            Arc::new(Self {
+                results: Mutex::new(results.into()),
+                calls: Mutex::new(Vec::new()),
+            })
+        }
+
+        fn calls(&self) -> Vec<Instant> {
+            self.calls.lock().unwrap().clone()
+        }
+    }
+
+    #[tonic::async_trait]
+    impl Channel for ScriptedChannel {
+        fn kind(&self) -> ChannelKind {
+            ChannelKind::Webhook
+        }
+
+        async fn deliver(&self, _message: &Message) -> Result<(), DeliveryError> {
+            self.calls.lock().unwrap().push(Instant::now());
+            self.results.lock().unwrap().pop_front().unwrap_or(Ok(()))
+        }
+    }
+
+    async fn setup(channel: Arc<ScriptedChannel>) -> (Dispatcher, NotificationStore, Message) {
+        let store = NotificationStore::connect("sqlite::memory:").await.unwrap();
+        let mut dispatcher = Dispatcher::new(
+            store.clone(),
+            RoutingRules::parse("refund=webhook").unwrap(),
+        )
+        .with_channel(channel);
+        dispatcher.retry_base_delay = Duration::from_millis(20);
+
+        let notification = store
+            .insert(NewNotification {
+                user_id: "user-1",
+                kind: "refund",
+                title: "Refund issued".to_string(),
+                body: "Your refund is on its way.".to_string(),
+            })
+            .await
+            .unwrap();
+        let message = Message {
+            notification,
+            email: None,
+            email_content: None,
+        };
+
+        (dispatcher, store, message)
+    }
+
+    /// The recorded attempts as `(attempt, status)`, oldest first.
+    async fn attempts(store: &NotificationStore) -> Vec<(u32, String)> {
+        let mut attempts = store.list_attempts("user-1", 10).await.unwrap();
+        attempts.reverse();
+        attempts
+            .into_iter()
+            .map(|attempt| (attempt.attempt, attempt.status))
+            .collect()
+    }
+
+    fn transient() -> Result<(), DeliveryError> {
+        Err(DeliveryError::Transient("connection reset".to_string()))
+    }
+
+    #[tokio::test]
+    async fn retries_transient_failures_with_backoff() {
+        let channel = ScriptedChannel::new(vec![transient(), transient(), Ok(())]);
+        let (dispatcher, store, message) = setup(channel.clone()).await;
+
+        dispatcher.deliver(ChannelKind::Webhook, &message).await;
+
+        let calls = channel.calls();
+        assert_eq!(calls.len(), 3);
+        assert!(calls[1] - calls[0] >= Duration::from_millis(20));
+        assert!(calls[2] - calls[1] >= Duration::from_millis(40));
+        assert_eq!(
+            attempts(&store).await,
+            [
+                (1, "failed".to_string()),
+                (2, "failed".to_string()),
+                (3, "delivered".to_string()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_the_last_attempt() {
+        let channel = ScriptedChannel::new(vec![transient(), transient(), transient(), Ok(())]);
+        let (dispatcher, store, message) = setup(channel.clone()).await;
+
+        dispatcher.deliver(ChannelKind::Webhook, &message).await;
+
+        assert_eq!(channel.calls().len(), MAX_ATTEMPTS as usize);
+        assert_eq!(
+            attempts(&store).await,
+            [
+                (1, "failed".to_string()),
+                (2, "failed".to_string()),
+                (3, "failed".to_string()),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn does_not_retry_permanent_failures_or_skips() {
+        for (result, status) in [
+            (
+                Err(DeliveryError::Permanent("410 Gone".to_string())),
+                "failed",
+            ),
+            (
+                Err(DeliveryError::Skipped("No webhook".to_string())),
+                "skipped",
+            ),
+        ] {
+            let channel = ScriptedChannel::new(vec![result]);
+            let (dispatcher, store, message) = setup(channel.clone()).await;
+
+            dispatcher.deliver(ChannelKind::Webhook, &message).await;
+
+            assert_eq!(channel.calls().len(), 1);
+            assert_eq!(attempts(&store).await, [(1, status.to_string())]);
+        }
+    }
+
+    #[tokio::test]
+    async fn records_unconfigured_channels_as_skipped() {
+        let channel = ScriptedChannel::new(Vec::new());
+        let (dispatcher, store, message) = setup(channel.clone()).await;
+
+        dispatcher.deliver(ChannelKind::Email, &message).await;
+
+        assert!(channel.calls().is_empty());
+        assert_eq!(attempts(&store).await, [(1, "skipped".to_string())]);
+    }
+}
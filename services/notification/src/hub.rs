@@ -0,0 +1,65 @@
+use tokio::sync::{broadcast, mpsc};
+
+use crate::store::StoredNotification;
+
+/// How many notifications can be in flight before a slow subscriber starts
+/// missing some.
+const HUB_CAPACITY: usize = 1024;
+/// Per-subscriber buffer between the hub and the gRPC stream.
+const SUBSCRIBER_BUFFER: usize = 32;
+
+/// Fans newly stored notifications out to live subscribers. Nothing is
+/// buffered for users who are not connected; they catch up from the store.
+#[derive(Debug, Clone)]
+pub struct NotificationHub {
+    sender: broadcast::Sender<StoredNotification>,
+}
+
+impl Default for NotificationHub {
+    fn default() -> Self {
+        let (sender, _) = broadcast::channel(HUB_CAPACITY);
+        Self { sender }
+    }
+}
+
+impl NotificationHub {
+    pub fn publish(&self, notification: StoredNotification) {
+        // Fails only when nobody is listening, which is fine
+        let _ = self.sender.send(notification);
+    }
+
+    /// Notifications for `user_id` from now on. Forwarding stops once the
+    /// returned receiver is dropped.
+    pub fn subscribe(&self, user_id: String) -> mpsc::Receiver<StoredNotification> {
+        let mut hub = self.sender.subscribe();
+        let (tx, rx) = mpsc::channel(SUBSCRIBER_BUFFER);
+
+        tokio::spawn(async move {
+            loop {
+                let received = tokio::select! {
+                    _ = tx.closed() => break,
+                    received = hub.recv() => received,
+                };
+
+                match received {
+                    Ok(notification) if notification.user_id == user_id => {
+                        if tx.send(notification).await.is_err() {
+                            break;
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        tracing::warn!(
+                            "Subscriber for user {} fell behind and missed {} notifications",
+                            user_id,
+                            skipped
+                        );
+                    }
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+            }
+        });
+
+        rx
+    }
+}
@@ -1,20 +1,116 @@
+use std::sync::Arc;
+
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::StreamExt;
 use tonic::{transport::Server, Request, Response, Status};
 
+mod auth;
+mod channels;
+mod dispatcher;
+mod hub;
+mod routing;
+mod store;
+mod templates;
+
 // Include the generated protobuf code
 pub mod notification {
     tonic::include_proto!("notification");
 }
 
+use auth::ServiceAuth;
+use channels::{ChannelKind, EmailChannel, InAppChannel, Message, WebhookChannel};
+use dispatcher::Dispatcher;
+use hub::NotificationHub;
 use notification::{
     notification_service_server::{NotificationService, NotificationServiceServer},
-    ProductNotificationRequest, ProductNotificationResponse,
+    CountUnreadNotificationsRequest, CountUnreadNotificationsResponse,
+    DeleteUserNotificationsRequest, DeleteUserNotificationsResponse, DeliveryAttempt,
+    EmailVerificationNotificationRequest, EmailVerificationNotificationResponse,
+    ExportUserNotificationsRequest, ExportUserNotificationsResponse,
+    FollowedSellerListingNotificationRequest, FollowedSellerListingNotificationResponse,
+    ListDeliveryAttemptsRequest, ListDeliveryAttemptsResponse, ListNotificationsRequest,
+    ListNotificationsResponse, MarkNotificationsReadRequest, MarkNotificationsReadResponse,
+    Notification, PasswordResetNotificationRequest, PasswordResetNotificationResponse,
+    PreviewTemplateRequest, PreviewTemplateResponse, ProductNotificationRequest,
+    ProductNotificationResponse, RefundNotificationRequest, RefundNotificationResponse,
+    SubscribeNotificationsRequest,
 };
+use routing::RoutingRules;
+use store::{NewNotification, NotificationStore, StoredDeliveryAttempt, StoredNotification};
+use templates::{TemplateError, Templates, Variables};
+
+/// Loopback unless `NOTIFICATION_GRPC_ADDR` says otherwise; the service is
+/// only meant to be reached by the API.
+const DEFAULT_GRPC_ADDR: &str = "127.0.0.1:50051";
+const DEFAULT_PAGE_SIZE: u32 = 20;
+const MAX_PAGE_SIZE: u32 = 100;
+
+pub struct NotificationServiceImpl {
+    store: NotificationStore,
+    hub: NotificationHub,
+    dispatcher: Arc<Dispatcher>,
+    templates: Templates,
+}
+
+impl NotificationServiceImpl {
+    /// Renders the templates for `kind` in the user's locale, stores the
+    /// in-app copy and hands it to the dispatcher, which delivers it on
+    /// whichever channels the kind is routed to. `email` and `locale` may be
+    /// empty.
+    async fn save(
+        &self,
+        user_id: &str,
+        kind: &str,
+        email: &str,
+        locale: &str,
+        variables: Variables,
+    ) -> Result<(), Status> {
+        if user_id.is_empty() {
+            return Err(Status::invalid_argument("user_id is required"));
+        }
+
+        let stored_copy = self
+            .templates
+            .render(kind, ChannelKind::InApp, locale, &variables)
+            .map_err(render_failed)?;
+        // Kinds without email templates send the stored copy by email
+        let email_content = self
+            .templates
+            .render(kind, ChannelKind::Email, locale, &variables);
+        let email_content = match email_content {
+            Ok(content) => Some(content),
+            Err(TemplateError::Missing { .. }) => None,
+            Err(e) => return Err(render_failed(e)),
+        };
+
+        let notification = NewNotification {
+            user_id,
+            kind,
+            title: stored_copy.subject,
+            body: stored_copy.text,
+        };
+        let stored = self.store.insert(notification).await.map_err(|e| {
+            tracing::error!("Failed to store notification: {}", e);
+            Status::internal("Failed to store notification")
+        })?;
+
+        self.dispatcher.dispatch(Message {
+            notification: stored,
+            email: Some(email.to_string()).filter(|email| !email.is_empty()),
+            email_content,
+        });
 
-#[derive(Debug, Default)]
-pub struct NotificationServiceImpl {}
+        Ok(())
+    }
+}
+
+type NotificationStream =
+    std::pin::Pin<Box<dyn tokio_stream::Stream<Item = Result<Notification, Status>> + Send>>;
 
 #[tonic::async_trait]
 impl NotificationService for NotificationServiceImpl {
+    type SubscribeNotificationsStream = NotificationStream;
+
     async fn send_product_notification(
         &self,
         request: Request<ProductNotificationRequest>,
@@ -29,6 +125,19 @@ impl NotificationService for NotificationServiceImpl {
         println!("   Timestamp: {}", chrono::Utc::now());
         println!("---");
 
+        self.save(
+            &req.user_id,
+            "product_listed",
+            &req.email,
+            &req.locale,
+            variables([
+                ("username", &req.username),
+                ("email", &req.email),
+                ("product_name", &req.name),
+            ]),
+        )
+        .await?;
+
         // Return success response
         let response = ProductNotificationResponse {
             success: true,
@@ -37,6 +146,383 @@ impl NotificationService for NotificationServiceImpl {
 
         Ok(Response::new(response))
     }
+
+    async fn send_followed_seller_listing_notification(
+        &self,
+        request: Request<FollowedSellerListingNotificationRequest>,
+    ) -> Result<Response<FollowedSellerListingNotificationResponse>, Status> {
+        let req = request.into_inner();
+
+        self.save(
+            &req.user_id,
+            "followed_seller_listed",
+            &req.email,
+            &req.locale,
+            variables([
+                ("username", &req.username),
+                ("email", &req.email),
+                ("seller_name", &req.seller_name),
+                ("product_name", &req.product_name),
+            ]),
+        )
+        .await?;
+
+        let response = FollowedSellerListingNotificationResponse {
+            success: true,
+            message: format!("Listing notification received for: {}", req.product_name),
+        };
+
+        Ok(Response::new(response))
+    }
+
+    async fn send_refund_notification(
+        &self,
+        request: Request<RefundNotificationRequest>,
+    ) -> Result<Response<RefundNotificationResponse>, Status> {
+        let req = request.into_inner();
+
+        println!("💸 REFUND NOTIFICATION RECEIVED:");
+        println!("   User ID: {}", req.user_id);
+        println!("   Username: {}", req.username);
+        println!("   Order ID: {}", req.order_id);
+        println!("   Amount: {}", req.amount);
+        println!("   Reason: {}", req.reason);
+        println!("   Timestamp: {}", chrono::Utc::now());
+        println!("---");
+
+        self.save(
+            &req.user_id,
+            "refund",
+            &req.email,
+            &req.locale,
+            variables([
+                ("username", &req.username),
+                ("email", &req.email),
+                ("order_id", &req.order_id),
+                ("amount", &req.amount),
+                ("reason", &req.reason),
+            ]),
+        )
+        .await?;
+
+        let response = RefundNotificationResponse {
+            success: true,
+            message: format!("Refund notification received for order: {}", req.order_id),
+        };
+
+        Ok(Response::new(response))
+    }
+
+    async fn send_password_reset_notification(
+        &self,
+        request: Request<PasswordResetNotificationRequest>,
+    ) -> Result<Response<PasswordResetNotificationResponse>, Status> {
+        let req = request.into_inner();
+
+        // The token is as good as the password, so only the user id is logged
+        println!("🔑 PASSWORD RESET NOTIFICATION RECEIVED:");
+        println!("   User ID: {}", req.user_id);
+        println!("   Timestamp: {}", chrono::Utc::now());
+        println!("---");
+
+        // Only the email templates use the token; the in-app copy is stored
+        // and may go out by webhook
+        self.save(
+            &req.user_id,
+            "password_reset",
+            &req.email,
+            &req.locale,
+            variables([
+                ("username", &req.username),
+                ("email", &req.email),
+                ("reset_token", &req.reset_token),
+                ("expires_at", &req.expires_at),
+            ]),
+        )
+        .await?;
+
+        let response = PasswordResetNotificationResponse {
+            success: true,
+            message: format!("Password reset notification received for: {}", req.username),
+        };
+
+        Ok(Response::new(response))
+    }
+
+    async fn send_email_verification_notification(
+        &self,
+        request: Request<EmailVerificationNotificationRequest>,
+    ) -> Result<Response<EmailVerificationNotificationResponse>, Status> {
+        let req = request.into_inner();
+
+        // The token proves ownership of the address, so it is never logged
+        println!("✉️ EMAIL VERIFICATION NOTIFICATION RECEIVED:");
+        println!("   User ID: {}", req.user_id);
+        println!("   Timestamp: {}", chrono::Utc::now());
+        println!("---");
+
+        self.save(
+            &req.user_id,
+            "email_verification",
+            &req.email,
+            &req.locale,
+            variables([
+                ("username", &req.username),
+                ("email", &req.email),
+                ("verification_token", &req.verification_token),
+                ("expires_at", &req.expires_at),
+            ]),
+        )
+        .await?;
+
+        let response = EmailVerificationNotificationResponse {
+            success: true,
+            message: format!(
+                "Email verification notification received for: {}",
+                req.email
+            ),
+        };
+
+        Ok(Response::new(response))
+    }
+
+    async fn list_notifications(
+        &self,
+        request: Request<ListNotificationsRequest>,
+    ) -> Result<Response<ListNotificationsResponse>, Status> {
+        let req = request.into_inner();
+
+        let limit = match req.limit {
+            0 => DEFAULT_PAGE_SIZE,
+            limit => limit.min(MAX_PAGE_SIZE),
+        };
+
+        let notifications = self
+            .store
+            .list(&req.user_id, req.unread_only, limit, req.offset)
+            .await
+            .map_err(internal)?;
+
+        let response = ListNotificationsResponse {
+            notifications: notifications.into_iter().map(to_proto).collect(),
+        };
+
+        Ok(Response::new(response))
+    }
+
+    async fn mark_notifications_read(
+        &self,
+        request: Request<MarkNotificationsReadRequest>,
+    ) -> Result<Response<MarkNotificationsReadResponse>, Status> {
+        let req = request.into_inner();
+
+        let updated = if req.all {
+            self.store.mark_all_read(&req.user_id).await
+        } else {
+            self.store.mark_read(&req.user_id, &req.ids).await
+        }
+        .map_err(internal)?;
+
+        let response = MarkNotificationsReadResponse {
+            updated: updated as u32,
+        };
+
+        Ok(Response::new(response))
+    }
+
+    async fn count_unread_notifications(
+        &self,
+        request: Request<CountUnreadNotificationsRequest>,
+    ) -> Result<Response<CountUnreadNotificationsResponse>, Status> {
+        let req = request.into_inner();
+
+        let unread = self
+            .store
+            .count_unread(&req.user_id)
+            .await
+            .map_err(internal)?;
+
+        let response = CountUnreadNotificationsResponse {
+            unread: unread as u32,
+        };
+
+        Ok(Response::new(response))
+    }
+
+    async fn delete_user_notifications(
+        &self,
+        request: Request<DeleteUserNotificationsRequest>,
+    ) -> Result<Response<DeleteUserNotificationsResponse>, Status> {
+        let req = request.into_inner();
+
+        if req.user_id.is_empty() {
+            return Err(Status::invalid_argument("user_id is required"));
+        }
+
+        let deleted = self
+            .store
+            .delete_for_user(&req.user_id)
+            .await
+            .map_err(internal)?;
+
+        println!(
+            "🗑️ Deleted {} notifications of user {}",
+            deleted, req.user_id
+        );
+
+        let response = DeleteUserNotificationsResponse {
+            deleted: deleted as u32,
+        };
+
+        Ok(Response::new(response))
+    }
+
+    /// Everything stored about the user, for data exports: notifications
+    /// that never reached the inbox too, and every delivery attempt.
+    async fn export_user_notifications(
+        &self,
+        request: Request<ExportUserNotificationsRequest>,
+    ) -> Result<Response<ExportUserNotificationsResponse>, Status> {
+        let req = request.into_inner();
+
+        if req.user_id.is_empty() {
+            return Err(Status::invalid_argument("user_id is required"));
+        }
+
+        let notifications = self.store.list_all(&req.user_id).await.map_err(internal)?;
+        let attempts = self
+            .store
+            .list_all_attempts(&req.user_id)
+            .await
+            .map_err(internal)?;
+
+        let response = ExportUserNotificationsResponse {
+            notifications: notifications.into_iter().map(to_proto).collect(),
+            attempts: attempts.into_iter().map(attempt_to_proto).collect(),
+        };
+
+        Ok(Response::new(response))
+    }
+
+    async fn subscribe_notifications(
+        &self,
+        request: Request<SubscribeNotificationsRequest>,
+    ) -> Result<Response<Self::SubscribeNotificationsStream>, Status> {
+        let req = request.into_inner();
+
+        if req.user_id.is_empty() {
+            return Err(Status::invalid_argument("user_id is required"));
+        }
+
+        println!("📡 User {} subscribed to notifications", req.user_id);
+
+        let stream = ReceiverStream::new(self.hub.subscribe(req.user_id))
+            .map(to_proto)
+            .map(Ok);
+
+        Ok(Response::new(Box::pin(stream)))
+    }
+
+    async fn list_delivery_attempts(
+        &self,
+        request: Request<ListDeliveryAttemptsRequest>,
+    ) -> Result<Response<ListDeliveryAttemptsResponse>, Status> {
+        let req = request.into_inner();
+
+        let limit = match req.limit {
+            0 => DEFAULT_PAGE_SIZE,
+            limit => limit.min(MAX_PAGE_SIZE),
+        };
+
+        let attempts = self
+            .store
+            .list_attempts(&req.user_id, limit)
+            .await
+            .map_err(internal)?;
+
+        let response = ListDeliveryAttemptsResponse {
+            attempts: attempts.into_iter().map(attempt_to_proto).collect(),
+        };
+
+        Ok(Response::new(response))
+    }
+
+    /// Renders a template with sample values for the event kind, so admins
+    /// can check a new or translated template before it goes out.
+    async fn preview_template(
+        &self,
+        request: Request<PreviewTemplateRequest>,
+    ) -> Result<Response<PreviewTemplateResponse>, Status> {
+        let req = request.into_inner();
+
+        let channel = req
+            .channel
+            .parse::<ChannelKind>()
+            .map_err(Status::invalid_argument)?;
+
+        let mut variables = templates::sample_variables(&req.kind);
+        variables.extend(req.variables);
+
+        let rendered = self
+            .templates
+            .render(&req.kind, channel, &req.locale, &variables)
+            .map_err(|e| match e {
+                TemplateError::Missing { .. } => Status::not_found(e.to_string()),
+                TemplateError::Render(_) => Status::invalid_argument(e.to_string()),
+            })?;
+
+        let response = PreviewTemplateResponse {
+            locale: rendered.locale,
+            subject: rendered.subject,
+            text: rendered.text,
+            html: rendered.html,
+        };
+
+        Ok(Response::new(response))
+    }
+}
+
+fn variables<const N: usize>(values: [(&str, &str); N]) -> Variables {
+    values
+        .into_iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect()
+}
+
+fn attempt_to_proto(attempt: StoredDeliveryAttempt) -> DeliveryAttempt {
+    DeliveryAttempt {
+        id: attempt.id,
+        notification_id: attempt.notification_id,
+        kind: attempt.kind,
+        channel: attempt.channel,
+        attempt: attempt.attempt,
+        status: attempt.status,
+        error: attempt.error,
+        attempted_at: attempt.attempted_at.to_rfc3339(),
+    }
+}
+
+fn to_proto(notification: StoredNotification) -> Notification {
+    Notification {
+        id: notification.id,
+        user_id: notification.user_id,
+        kind: notification.kind,
+        title: notification.title,
+        body: notification.body,
+        created_at: notification.created_at.to_rfc3339(),
+        read_at: notification.read_at.map(|read_at| read_at.to_rfc3339()),
+        in_inbox: notification.in_inbox,
+    }
+}
+
+fn render_failed(e: TemplateError) -> Status {
+    tracing::error!("Failed to render notification: {}", e);
+    Status::internal("Failed to render notification")
+}
+
+fn internal(e: sqlx::Error) -> Status {
+    tracing::error!("Notification store error: {}", e);
+    Status::internal("Notification store error")
 }
 
 #[tokio::main]
@@ -44,15 +530,124 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     // Initialize tracing
     tracing_subscriber::fmt::init();
 
-    let addr = "0.0.0.0:50051".parse()?;
-    let notification_service = NotificationServiceImpl::default();
+    let database_url = std::env::var("NOTIFICATION_DATABASE_URL")
+        .unwrap_or_else(|_| store::DEFAULT_DATABASE_URL.to_string());
+    let store = NotificationStore::connect(&database_url).await?;
+    let hub = NotificationHub::default();
+    let templates = Templates::from_env()?;
+    let auth = ServiceAuth::from_env()?;
+
+    // In-app delivery is always available; email and webhooks only when
+    // configured
+    let mut dispatcher = Dispatcher::new(store.clone(), RoutingRules::from_env()?)
+        .with_channel(Arc::new(InAppChannel::new(store.clone(), hub.clone())));
+    if let Some(email) = EmailChannel::from_env()? {
+        dispatcher = dispatcher.with_channel(Arc::new(email));
+    }
+    if let Some(webhook) = WebhookChannel::from_env()? {
+        dispatcher = dispatcher.with_channel(Arc::new(webhook));
+    }
+
+    let addr = std::env::var("NOTIFICATION_GRPC_ADDR")
+        .unwrap_or_else(|_| DEFAULT_GRPC_ADDR.to_string())
+        .parse()?;
+    let notification_service = NotificationServiceImpl {
+        store,
+        hub,
+        dispatcher: Arc::new(dispatcher),
+        templates,
+    };
 
     println!("🚀 Notification Service starting on {}", addr);
 
     Server::builder()
-        .add_service(NotificationServiceServer::new(notification_service))
+        .add_service(NotificationServiceServer::with_interceptor(
+            notification_service,
+            auth,
+        ))
         .serve(addr)
         .await?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use tokio::net::TcpListener;
+    use tonic::{transport::server::TcpIncoming, Code};
+
+    use super::*;
+    use notification::notification_service_client::NotificationServiceClient;
+
+    #[test]
+    fn binds_to_loopback_by_default() {
+        let addr: SocketAddr = DEFAULT_GRPC_ADDR.parse().unwrap();
+
+        assert!(addr.ip().is_loopback());
+    }
+
+    /// Serves the service behind `ServiceAuth` on a free local port and
+    /// returns its address.
+    async fn serve(token: &str) -> SocketAddr {
+        let store = NotificationStore::connect("sqlite::memory:").await.unwrap();
+        let hub = NotificationHub::default();
+        let dispatcher = Dispatcher::new(store.clone(), RoutingRules::parse("").unwrap());
+        let service = NotificationServiceImpl {
+            store,
+            hub,
+            dispatcher: Arc::new(dispatcher),
+            templates: Templates::load(
+                std::path::Path::new(templates::DEFAULT_TEMPLATE_DIR),
+                templates::DEFAULT_LOCALE,
+            )
+            .unwrap(),
+        };
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let incoming = TcpIncoming::from_listener(listener, true, None).unwrap();
+        let auth = ServiceAuth::new(token);
+
+        tokio::spawn(
+            Server::builder()
+                .add_service(NotificationServiceServer::with_interceptor(service, auth))
+                .serve_with_incoming(incoming),
+        );
+
+        addr
+    }
+
+    async fn count_unread(addr: SocketAddr, authorization: Option<&str>) -> Result<u32, Code> {
+        let mut client = NotificationServiceClient::connect(format!("http://{}", addr))
+            .await
+            .unwrap();
+        let mut request = tonic::Request::new(CountUnreadNotificationsRequest {
+            user_id: "user-1".to_string(),
+        });
+        if let Some(value) = authorization {
+            request
+                .metadata_mut()
+                .insert("authorization", value.parse().unwrap());
+        }
+
+        client
+            .count_unread_notifications(request)
+            .await
+            .map(|response| response.into_inner().unread)
+            .map_err(|status| status.code())
+    }
+
+    #[tokio::test]
+    async fn only_callers_with_the_service_token_get_through() {
+        let addr = serve("s3cret-token").await;
+
+        assert_eq!(count_unread(addr, None).await, Err(Code::Unauthenticated));
+        assert_eq!(
+            count_unread(addr, Some("Bearer wrong-token")).await,
+            Err(Code::Unauthenticated)
+        );
+        assert_eq!(count_unread(addr, Some("Bearer s3cret-token")).await, Ok(0));
+    }
+}
@@ -0,0 +1,112 @@
+use std::{collections::HashMap, env};
+
+use crate::channels::ChannelKind;
+
+/// Used when `NOTIFICATION_ROUTES` is unset. Secrets such as reset tokens
+/// only travel by email.
+pub const DEFAULT_ROUTES: &str = "product_listed=in_app,webhook;\
+     refund=in_app,email,webhook;\
+     password_reset=email;\
+     email_verification=email;\
+     *=in_app";
+
+/// Which channels each kind of notification goes out on.
+///
+/// Read from `NOTIFICATION_ROUTES` as `kind=channel,channel;kind=channel`,
+/// where channels are `email`, `webhook` and `in_app`. A `*` rule covers
+/// kinds without a rule of their own; without one they go nowhere.
+#[derive(Debug, Clone)]
+pub struct RoutingRules {
+    routes: HashMap<String, Vec<ChannelKind>>,
+    fallback: Vec<ChannelKind>,
+}
+
+impl RoutingRules {
+    pub fn from_env() -> Result<Self, String> {
+        let rules = env::var("NOTIFICATION_ROUTES").unwrap_or_else(|_| DEFAULT_ROUTES.to_string());
+        Self::parse(&rules)
+    }
+
+    pub fn parse(rules: &str) -> Result<Self, String> {
+        let mut routes = HashMap::new();
+        let mut fallback = Vec::new();
+
+        for rule in rules
+            .split(';')
+            .map(str::trim)
+            .filter(|rule| !rule.is_empty())
+        {
+            let (kind, channels) = rule
+                .split_once('=')
+                .ok_or_else(|| format!("Routing rule {} is missing '='", rule))?;
+
+            let channels = channels
+                .split(',')
+                .map(str::trim)
+                .filter(|channel| !channel.is_empty())
+                .map(str::parse)
+                .collect::<Result<Vec<ChannelKind>, _>>()?;
+
+            match kind.trim() {
+                "*" => fallback = channels,
+                kind => {
+                    routes.insert(kind.to_string(), channels);
+                }
+            }
+        }
+
+        Ok(Self { routes, fallback })
+    }
+
+    pub fn channels_for(&self, kind: &str) -> &[ChannelKind] {
+        self.routes.get(kind).unwrap_or(&self.fallback)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_rules_and_the_fallback() {
+        let rules =
+            RoutingRules::parse(" refund = in_app, email ; password_reset=email; *=in_app ")
+                .unwrap();
+
+        assert_eq!(
+            rules.channels_for("refund"),
+            [ChannelKind::InApp, ChannelKind::Email]
+        );
+        assert_eq!(rules.channels_for("password_reset"), [ChannelKind::Email]);
+        assert_eq!(rules.channels_for("anything_else"), [ChannelKind::InApp]);
+    }
+
+    #[test]
+    fn kinds_without_a_rule_go_nowhere_without_a_fallback() {
+        let rules = RoutingRules::parse("refund=webhook;").unwrap();
+
+        assert_eq!(rules.channels_for("refund"), [ChannelKind::Webhook]);
+        assert!(rules.channels_for("product_listed").is_empty());
+    }
+
+    #[test]
+    fn an_empty_channel_list_turns_a_kind_off() {
+        let rules = RoutingRules::parse("*=in_app;product_listed=").unwrap();
+
+        assert!(rules.channels_for("product_listed").is_empty());
+    }
+
+    #[test]
+    fn rejects_malformed_rules() {
+        assert!(RoutingRules::parse("refund").is_err());
+        assert!(RoutingRules::parse("refund=in_app,carrier_pigeon").is_err());
+    }
+
+    #[test]
+    fn default_routes_parse() {
+        let rules = RoutingRules::parse(DEFAULT_ROUTES).unwrap();
+
+        assert_eq!(rules.channels_for("password_reset"), [ChannelKind::Email]);
+        assert_eq!(rules.channels_for("unknown_kind"), [ChannelKind::InApp]);
+    }
+}
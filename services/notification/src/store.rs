@@ -0,0 +1,269 @@
+use std::str::FromStr;
+
+use chrono::{DateTime, Utc};
+use sqlx::{
+    sqlite::{SqliteConnectOptions, SqlitePoolOptions},
+    FromRow, QueryBuilder, Sqlite, SqlitePool,
+};
+use uuid::Uuid;
+
+/// Where notifications are kept unless `NOTIFICATION_DATABASE_URL` says
+/// otherwise.
+pub const DEFAULT_DATABASE_URL: &str = "sqlite://notifications.db";
+
+#[derive(Debug, Clone, FromRow)]
+pub struct StoredNotification {
+    pub id: String,
+    pub user_id: String,
+    pub kind: String,
+    pub title: String,
+    pub body: String,
+    pub created_at: DateTime<Utc>,
+    pub read_at: Option<DateTime<Utc>>,
+    pub in_inbox: bool,
+}
+
+/// A delivery attempt together with the kind of notification it was for.
+#[derive(Debug, Clone, FromRow)]
+pub struct StoredDeliveryAttempt {
+    pub id: String,
+    pub notification_id: String,
+    pub kind: String,
+    pub channel: String,
+    pub attempt: u32,
+    pub status: String,
+    pub error: Option<String>,
+    pub attempted_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeliveryStatus {
+    Delivered,
+    Failed,
+    /// The channel had nothing to deliver to, such as a user with no email.
+    Skipped,
+}
+
+impl DeliveryStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DeliveryStatus::Delivered => "delivered",
+            DeliveryStatus::Failed => "failed",
+            DeliveryStatus::Skipped => "skipped",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct NewNotification<'a> {
+    pub user_id: &'a str,
+    pub kind: &'a str,
+    pub title: String,
+    pub body: String,
+}
+
+/// Persists notifications with their read state.
+#[derive(Debug, Clone)]
+pub struct NotificationStore {
+    pool: SqlitePool,
+}
+
+impl NotificationStore {
+    /// Opens (creating if needed) the database and brings its schema up to
+    /// date.
+    pub async fn connect(url: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let options = SqliteConnectOptions::from_str(url)?.create_if_missing(true);
+        let pool = SqlitePoolOptions::new().connect_with(options).await?;
+
+        sqlx::migrate!("./migrations").run(&pool).await?;
+
+        Ok(Self { pool })
+    }
+
+    /// Records a notification. It stays out of the inbox until the in-app
+    /// channel delivers it.
+    pub async fn insert(
+        &self,
+        notification: NewNotification<'_>,
+    ) -> Result<StoredNotification, sqlx::Error> {
+        sqlx::query_as::<_, StoredNotification>(
+            "INSERT INTO notifications (id, user_id, kind, title, body, created_at, in_inbox)
+             VALUES (?, ?, ?, ?, ?, ?, 0)
+             RETURNING *",
+        )
+        .bind(Uuid::new_v4().to_string())
+        .bind(notification.user_id)
+        .bind(notification.kind)
+        .bind(notification.title)
+        .bind(notification.body)
+        .bind(Utc::now())
+        .fetch_one(&self.pool)
+        .await
+    }
+
+    pub async fn add_to_inbox(&self, id: &str) -> Result<(), sqlx::Error> {
+        sqlx::query("UPDATE notifications SET in_inbox = 1 WHERE id = ?")
+            .bind(id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    /// A page of the user's inbox, newest first.
+    pub async fn list(
+        &self,
+        user_id: &str,
+        unread_only: bool,
+        limit: u32,
+        offset: u32,
+    ) -> Result<Vec<StoredNotification>, sqlx::Error> {
+        sqlx::query_as::<_, StoredNotification>(
+            "SELECT * FROM notifications
+             WHERE user_id = ? AND in_inbox = 1 AND (NOT ? OR read_at IS NULL)
+             ORDER BY created_at DESC, rowid DESC
+             LIMIT ? OFFSET ?",
+        )
+        .bind(user_id)
+        .bind(unread_only)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    /// Marks the given notifications read, ignoring ids that belong to
+    /// someone else or are already read. Returns how many changed.
+    pub async fn mark_read(&self, user_id: &str, ids: &[String]) -> Result<u64, sqlx::Error> {
+        if ids.is_empty() {
+            return Ok(0);
+        }
+
+        let mut query = QueryBuilder::<Sqlite>::new("UPDATE notifications SET read_at = ");
+        query
+            .push_bind(Utc::now())
+            .push(" WHERE user_id = ")
+            .push_bind(user_id)
+            .push(" AND in_inbox = 1 AND read_at IS NULL AND id IN (");
+        let mut separated = query.separated(", ");
+        for id in ids {
+            separated.push_bind(id);
+        }
+        separated.push_unseparated(")");
+
+        let result = query.build().execute(&self.pool).await?;
+
+        Ok(result.rows_affected())
+    }
+
+    pub async fn mark_all_read(&self, user_id: &str) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query(
+            "UPDATE notifications SET read_at = ?
+             WHERE user_id = ? AND in_inbox = 1 AND read_at IS NULL",
+        )
+        .bind(Utc::now())
+        .bind(user_id)
+        .execute(&self.pool)
+        .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    pub async fn count_unread(&self, user_id: &str) -> Result<u64, sqlx::Error> {
+        let count = sqlx::query_scalar::<_, i64>(
+            "SELECT COUNT(*) FROM notifications
+             WHERE user_id = ? AND in_inbox = 1 AND read_at IS NULL",
+        )
+        .bind(user_id)
+        .fetch_one(&self.pool)
+        .await?;
+
+        Ok(count as u64)
+    }
+
+    pub async fn record_attempt(
+        &self,
+        notification_id: &str,
+        channel: &str,
+        attempt: u32,
+        status: DeliveryStatus,
+        error: Option<&str>,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query(
+            "INSERT INTO delivery_attempts
+                 (id, notification_id, channel, attempt, status, error, attempted_at)
+             VALUES (?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(Uuid::new_v4().to_string())
+        .bind(notification_id)
+        .bind(channel)
+        .bind(attempt)
+        .bind(status.as_str())
+        .bind(error)
+        .bind(Utc::now())
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// The user's most recent delivery attempts across all channels.
+    pub async fn list_attempts(
+        &self,
+        user_id: &str,
+        limit: u32,
+    ) -> Result<Vec<StoredDeliveryAttempt>, sqlx::Error> {
+        sqlx::query_as::<_, StoredDeliveryAttempt>(
+            "SELECT a.*, n.kind
+             FROM delivery_attempts a
+             JOIN notifications n ON n.id = a.notification_id
+             WHERE n.user_id = ?
+             ORDER BY a.attempted_at DESC, a.rowid DESC
+             LIMIT ?",
+        )
+        .bind(user_id)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    /// Every notification stored for the user, in or out of the inbox,
+    /// oldest first.
+    pub async fn list_all(&self, user_id: &str) -> Result<Vec<StoredNotification>, sqlx::Error> {
+        sqlx::query_as::<_, StoredNotification>(
+            "SELECT * FROM notifications
+             WHERE user_id = ?
+             ORDER BY created_at, rowid",
+        )
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    /// Every delivery attempt for the user's notifications, oldest first.
+    pub async fn list_all_attempts(
+        &self,
+        user_id: &str,
+    ) -> Result<Vec<StoredDeliveryAttempt>, sqlx::Error> {
+        sqlx::query_as::<_, StoredDeliveryAttempt>(
+            "SELECT a.*, n.kind
+             FROM delivery_attempts a
+             JOIN notifications n ON n.id = a.notification_id
+             WHERE n.user_id = ?
+             ORDER BY a.attempted_at, a.rowid",
+        )
+        .bind(user_id)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    /// Removes the user's notifications along with their delivery attempts.
+    pub async fn delete_for_user(&self, user_id: &str) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query("DELETE FROM notifications WHERE user_id = ?")
+            .bind(user_id)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(result.rows_affected())
+    }
+}
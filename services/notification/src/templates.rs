@@ -0,0 +1,380 @@
+use std::{collections::BTreeMap, env, fmt, fs, path::Path};
+
+use minijinja::{Environment, UndefinedBehavior};
+
+use crate::channels::ChannelKind;
+
+/// Used when `NOTIFICATION_TEMPLATE_DIR` is unset.
+pub const DEFAULT_TEMPLATE_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/templates");
+/// Used when `NOTIFICATION_DEFAULT_LOCALE` is unset.
+pub const DEFAULT_LOCALE: &str = "en";
+
+/// Values a template can refer to by name, e.g. `{{ order_id }}`.
+pub type Variables = BTreeMap<String, String>;
+
+/// The parts of a notification, each from its own template file.
+const SUBJECT: &str = "subject.txt";
+const TEXT: &str = "text.txt";
+const HTML: &str = "html";
+
+/// A notification rendered for one channel.
+#[derive(Debug, Clone)]
+pub struct Rendered {
+    /// The locale whose templates were used, after falling back.
+    pub locale: String,
+    pub subject: String,
+    pub text: String,
+    pub html: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum TemplateError {
+    /// No locale in the fallback chain has templates for the kind and channel.
+    Missing { kind: String, channel: ChannelKind },
+    /// The template refers to a variable that was not supplied, or similar.
+    Render(minijinja::Error),
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TemplateError::Missing { kind, channel } => {
+                write!(f, "No {} template for {}", channel, kind)
+            }
+            TemplateError::Render(e) => write!(f, "Failed to render template: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for TemplateError {}
+
+/// Notification content per event kind, channel and locale.
+///
+/// Templates are MiniJinja files under `NOTIFICATION_TEMPLATE_DIR`, laid out
+/// as `{locale}/{kind}/{channel}.{part}`: `subject.txt` and `text.txt` are
+/// required, `html` is optional and only used by email. Variables are
+/// HTML-escaped in `.html` files only.
+///
+/// A locale such as `pt-BR` falls back to `pt` and then to
+/// `NOTIFICATION_DEFAULT_LOCALE`. The first locale with a subject template
+/// supplies every part, so one message never mixes two languages.
+pub struct Templates {
+    env: Environment<'static>,
+    default_locale: String,
+}
+
+impl Templates {
+    pub fn from_env() -> Result<Self, String> {
+        let dir = env::var("NOTIFICATION_TEMPLATE_DIR")
+            .unwrap_or_else(|_| DEFAULT_TEMPLATE_DIR.to_string());
+        let default_locale =
+            env::var("NOTIFICATION_DEFAULT_LOCALE").unwrap_or_else(|_| DEFAULT_LOCALE.to_string());
+
+        Self::load(Path::new(&dir), &default_locale)
+    }
+
+    /// Reads and compiles every template up front, so a syntax error stops
+    /// the service from starting rather than a notification from sending.
+    pub fn load(dir: &Path, default_locale: &str) -> Result<Self, String> {
+        let mut env = Environment::new();
+        // Printing a variable the request did not supply is an error rather
+        // than a blank; `{% if reason %}` still works for optional ones
+        env.set_undefined_behavior(UndefinedBehavior::SemiStrict);
+
+        add_templates(&mut env, dir, dir)?;
+        tracing::info!(
+            "Loaded {} notification templates from {}",
+            env.templates().count(),
+            dir.display()
+        );
+
+        Ok(Self {
+            env,
+            default_locale: normalize_locale(default_locale),
+        })
+    }
+
+    /// Renders the templates for `kind` on `channel` in the best available
+    /// locale. Webhooks carry the stored copy, so they use the in-app
+    /// templates.
+    pub fn render(
+        &self,
+        kind: &str,
+        channel: ChannelKind,
+        locale: &str,
+        variables: &Variables,
+    ) -> Result<Rendered, TemplateError> {
+        let channel = match channel {
+            ChannelKind::Webhook => ChannelKind::InApp,
+            channel => channel,
+        };
+
+        let locale = self
+            .locale_chain(locale)
+            .into_iter()
+            .find(|locale| self.has(&template_name(locale, kind, channel, SUBJECT)))
+            .ok_or_else(|| TemplateError::Missing {
+                kind: kind.to_string(),
+                channel,
+            })?;
+
+        let render = |part| {
+            let name = template_name(&locale, kind, channel, part);
+            match self.env.get_template(&name) {
+                Ok(template) => template
+                    .render(variables)
+                    .map(Some)
+                    .map_err(TemplateError::Render),
+                Err(_) => Ok(None),
+            }
+        };
+
+        let subject = render(SUBJECT)?.unwrap_or_default();
+        let text = render(TEXT)?.ok_or_else(|| TemplateError::Missing {
+            kind: kind.to_string(),
+            channel,
+        })?;
+        let html = match channel {
+            ChannelKind::Email => render(HTML)?,
+            _ => None,
+        };
+
+        Ok(Rendered {
+            // Subjects end up in a single line, e.g. an email header
+            subject: subject.trim().to_string(),
+            text,
+            html,
+            locale,
+        })
+    }
+
+    fn has(&self, name: &str) -> bool {
+        self.env.get_template(name).is_ok()
+    }
+
+    /// `zh-Hant-TW` becomes `zh-hant-tw`, `zh-hant`, `zh`, then the default.
+    fn locale_chain(&self, locale: &str) -> Vec<String> {
+        let mut chain = Vec::new();
+
+        let mut locale = normalize_locale(locale);
+        while !locale.is_empty() {
+            chain.push(locale.clone());
+            locale = match locale.rsplit_once('-') {
+                Some((parent, _)) => parent.to_string(),
+                None => String::new(),
+            };
+        }
+
+        if !chain.contains(&self.default_locale) {
+            chain.push(self.default_locale.clone());
+        }
+
+        chain
+    }
+}
+
+/// Stand-in values for previewing the templates of an event kind. The keys
+/// match what the service supplies when it sends one for real.
+pub fn sample_variables(kind: &str) -> Variables {
+    let common = [("username", "jane"), ("email", "jane@example.com")];
+    let specific: &[(&str, &str)] = match kind {
+        "product_listed" => &[("product_name", "Walnut desk lamp")],
+        "followed_seller_listed" => &[
+            ("seller_name", "Oak & Iron"),
+            ("product_name", "Walnut desk lamp"),
+        ],
+        "refund" => &[
+            ("order_id", "5b1e7f2a-3c44-4d8e-9f61-0a2b3c4d5e6f"),
+            ("amount", "24.99"),
+            ("reason", "Arrived damaged"),
+        ],
+        "password_reset" => &[
+            ("reset_token", "Xq3vN8rT2kLp9sWd"),
+            ("expires_at", "2026-10-19T12:00:00+00:00"),
+        ],
+        "email_verification" => &[
+            ("verification_token", "Hd7mK2pQ9xVn4bRs"),
+            ("expires_at", "2026-10-20T12:00:00+00:00"),
+        ],
+        _ => &[],
+    };
+
+    common
+        .iter()
+        .chain(specific)
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect()
+}
+
+fn add_templates(env: &mut Environment<'static>, root: &Path, dir: &Path) -> Result<(), String> {
+    let entries = fs::read_dir(dir)
+        .map_err(|e| format!("Failed to read templates in {}: {}", dir.display(), e))?;
+
+    for entry in entries {
+        let path = entry.map_err(|e| e.to_string())?.path();
+        if path.is_dir() {
+            add_templates(env, root, &path)?;
+            continue;
+        }
+
+        let source = fs::read_to_string(&path)
+            .map_err(|e| format!("Failed to read template {}: {}", path.display(), e))?;
+        // Locales are matched case-insensitively, so `pt-BR/` works too
+        let name = path
+            .strip_prefix(root)
+            .unwrap_or(&path)
+            .to_string_lossy()
+            .replace('\\', "/")
+            .to_lowercase();
+
+        env.add_template_owned(name, source)
+            .map_err(|e| format!("Invalid template {}: {}", path.display(), e))?;
+    }
+
+    Ok(())
+}
+
+fn template_name(locale: &str, kind: &str, channel: ChannelKind, part: &str) -> String {
+    format!("{}/{}/{}.{}", locale, kind, channel, part)
+}
+
+fn normalize_locale(locale: &str) -> String {
+    locale.trim().replace('_', "-").to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn templates(default_locale: &str, sources: &[(&str, &str)]) -> Templates {
+        let mut env = Environment::new();
+        env.set_undefined_behavior(UndefinedBehavior::SemiStrict);
+        for (name, source) in sources {
+            env.add_template_owned(name.to_string(), source.to_string())
+                .unwrap();
+        }
+
+        Templates {
+            env,
+            default_locale: normalize_locale(default_locale),
+        }
+    }
+
+    fn render(templates: &Templates, kind: &str, locale: &str) -> Result<Rendered, TemplateError> {
+        templates.render(kind, ChannelKind::InApp, locale, &Variables::new())
+    }
+
+    /// `refund` in English, Portuguese and Brazilian Portuguese.
+    fn portuguese() -> Templates {
+        templates(
+            "en",
+            &[
+                ("en/refund/in_app.subject.txt", "Refund issued"),
+                ("en/refund/in_app.text.txt", "Your refund is on its way."),
+                ("pt/refund/in_app.subject.txt", "Reembolso emitido"),
+                (
+                    "pt/refund/in_app.text.txt",
+                    "O seu reembolso está a caminho.",
+                ),
+                ("pt-br/refund/in_app.subject.txt", "Reembolso feito"),
+                (
+                    "pt-br/refund/in_app.text.txt",
+                    "Seu reembolso está a caminho.",
+                ),
+                ("en/product_listed/in_app.subject.txt", "Product listed"),
+                ("en/product_listed/in_app.text.txt", "It is live."),
+            ],
+        )
+    }
+
+    #[test]
+    fn locale_chain_drops_one_subtag_at_a_time_then_the_default() {
+        let templates = templates("en", &[]);
+
+        assert_eq!(templates.locale_chain("pt-BR"), ["pt-br", "pt", "en"]);
+        assert_eq!(
+            templates.locale_chain("zh_Hant_TW"),
+            ["zh-hant-tw", "zh-hant", "zh", "en"]
+        );
+        assert_eq!(templates.locale_chain("en-GB"), ["en-gb", "en"]);
+        assert_eq!(templates.locale_chain(""), ["en"]);
+    }
+
+    #[test]
+    fn uses_the_most_specific_locale_available() {
+        let templates = portuguese();
+
+        let rendered = render(&templates, "refund", "pt-BR").unwrap();
+        assert_eq!(rendered.locale, "pt-br");
+        assert_eq!(rendered.subject, "Reembolso feito");
+
+        let rendered = render(&templates, "refund", "pt-PT").unwrap();
+        assert_eq!(rendered.locale, "pt");
+        assert_eq!(rendered.text, "O seu reembolso está a caminho.");
+    }
+
+    #[test]
+    fn falls_back_to_the_default_locale() {
+        let templates = portuguese();
+
+        // No French at all
+        let rendered = render(&templates, "refund", "fr-CA").unwrap();
+        assert_eq!(rendered.locale, "en");
+        assert_eq!(rendered.subject, "Refund issued");
+
+        // Portuguese exists, but not for this kind
+        let rendered = render(&templates, "product_listed", "pt-BR").unwrap();
+        assert_eq!(rendered.locale, "en");
+        assert_eq!(rendered.subject, "Product listed");
+    }
+
+    #[test]
+    fn does_not_mix_parts_from_different_locales() {
+        let templates = templates(
+            "en",
+            &[
+                ("en/refund/email.subject.txt", "Refund issued"),
+                ("en/refund/email.text.txt", "Your refund is on its way."),
+                ("en/refund/email.html", "<p>Your refund is on its way.</p>"),
+                ("pt/refund/email.subject.txt", "Reembolso emitido"),
+                (
+                    "pt/refund/email.text.txt",
+                    "O seu reembolso está a caminho.",
+                ),
+            ],
+        );
+
+        let rendered = templates
+            .render("refund", ChannelKind::Email, "pt", &Variables::new())
+            .unwrap();
+
+        assert_eq!(rendered.locale, "pt");
+        assert!(rendered.html.is_none());
+    }
+
+    #[test]
+    fn reports_kinds_missing_from_every_locale() {
+        let result = render(&portuguese(), "password_reset", "pt-BR");
+
+        assert!(matches!(
+            result,
+            Err(TemplateError::Missing { kind, channel: ChannelKind::InApp }) if kind == "password_reset"
+        ));
+    }
+
+    #[test]
+    fn bundled_templates_load_and_fall_back() {
+        let templates = Templates::load(Path::new(DEFAULT_TEMPLATE_DIR), DEFAULT_LOCALE).unwrap();
+        let variables = sample_variables("refund");
+
+        let rendered = templates
+            .render("refund", ChannelKind::Email, "de-AT", &variables)
+            .unwrap();
+        assert_eq!(rendered.locale, "de");
+
+        let rendered = templates
+            .render("refund", ChannelKind::Webhook, "ja", &variables)
+            .unwrap();
+        assert_eq!(rendered.locale, "en");
+    }
+}